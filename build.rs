@@ -1,7 +1,14 @@
-use std::env;
-use std::path::{Path, PathBuf};
-
 fn main() {
+    // The pure-Rust engine needs no native code
+    #[cfg(feature = "fortran")]
+    build_fortran();
+}
+
+#[cfg(feature = "fortran")]
+fn build_fortran() {
+    use std::env;
+    use std::path::PathBuf;
+
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
 
     // Trigger rebuild if C header or source changes
@@ -10,6 +17,11 @@ fn main() {
     println!("cargo:rerun-if-changed=fortran/src/monte_carlo.f90");
     println!("cargo:rerun-if-changed=fortran/src/c_interface.f90");
     println!("cargo:rerun-if-changed=fortran/src/signals.f90");
+    println!("cargo:rerun-if-changed=fortran/src/scattering.f90");
+    println!("cargo:rerun-if-changed=fortran/src/geometry.f90");
+    println!("cargo:rerun-if-changed=fortran/src/beam.f90");
+    println!("cargo:rerun-if-changed=fortran/src/random_streams.f90");
+    println!("cargo:rerun-if-changed=fortran/src/sim_status.f90");
 
     // === Generate Rust bindings for the C interface ===
     let bindings = bindgen::Builder::default()
@@ -17,6 +29,7 @@ fn main() {
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate_comments(true)
         .allowlist_function("c_.*") // Only include the c_ prefixed functions
+        .allowlist_var("SEM_.*") // and the status codes they return
         .generate()
         .expect("Unable to generate bindings");
 
@@ -34,12 +47,18 @@ fn main() {
     println!("cargo:rustc-link-search=native=fortran/build");
     println!("cargo:rustc-link-lib=static=sem_sim");
     
-    // Link against the Fortran runtime (GFortran)
-    println!("cargo:rustc-link-search=native=/opt/homebrew/Cellar/gcc/14.2.0_1/lib/gcc/current");
+    // Link against the Fortran runtime (GFortran). Set GFORTRAN_LIB_DIR when
+    // libgfortran is not on the default search path, e.g. with Homebrew gcc.
+    println!("cargo:rerun-if-env-changed=GFORTRAN_LIB_DIR");
+    if let Ok(dir) = env::var("GFORTRAN_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", dir);
+    }
     println!("cargo:rustc-link-lib=gfortran");
-    
+
     // Link against system libraries
-    println!("cargo:rustc-link-lib=dylib=System");
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos") {
+        println!("cargo:rustc-link-lib=dylib=System");
+    }
     println!("cargo:rustc-link-lib=dylib=c");
     println!("cargo:rustc-link-lib=dylib=m");
 }
@@ -1,15 +1,116 @@
 #ifndef SEM_SIM_C_H
 #define SEM_SIM_C_H
 
+#include <stdint.h>
+
 #ifdef __cplusplus
 extern "C" {
 #endif
 
-void c_init_simulation(double energy, double current, int resolution, double distance);
-void c_run_simulation(void);
-void c_get_scatter_data(double** data, int* rows, int* cols);
-void c_get_line_data(double** data, int* points);
-void c_get_image_data(double** data, int* width, int* height);
+/* Opaque per-run simulation state owned by the Fortran engine. */
+typedef struct sem_sim_context sem_sim_context;
+
+/* Status returned by every function below other than c_create_simulation, which returns NULL when
+   out of memory, and c_free_simulation. A failed call leaves its outputs zeroed or NULL. */
+#define SEM_OK 0
+#define SEM_ERR_NULL_POINTER 1     /* ctx or a required array or output pointer is NULL */
+#define SEM_ERR_INVALID_ARGUMENT 2 /* a parameter is out of range or inconsistent */
+#define SEM_ERR_OUT_OF_MEMORY 3
+#define SEM_ERR_NOT_INITIALIZED 4  /* c_init_simulation has not succeeded on ctx */
+#define SEM_ERR_NO_DATA 5          /* no run has produced the requested results */
+#define SEM_ERR_LIMIT_EXCEEDED 6   /* a fixed-size engine table is full */
+
+/* Told how many pixels (or line-scan points) of `total` the current run has finished; returning
+   nonzero stops the run there, keeping the finished lines or points. */
+typedef int (*sem_progress_fn)(void* user_data, int64_t completed, int64_t total);
+
+sem_sim_context* c_create_simulation(void);
+void c_free_simulation(sem_sim_context* ctx);
+
+/* Beam energy (keV), current (nA), image size in pixels and working distance (mm), plus the scan
+   raster: horizontal field width (nm), raster centre (nm) and counter-clockwise rotation about
+   the beam axis (degrees). Every other call needs a context initialized by this one. */
+int c_init_simulation(sem_sim_context* ctx, double energy, double current, int width, int height,
+                      double distance, double field_of_view, double offset_x, double offset_y,
+                      double rotation);
+/* Sample material as a list of elements: atomic number, atomic weight (g/mol), weight fraction and
+   mean ionization energy (eV) per element, plus the bulk density (g/cm^3). Sets material 1, which
+   fills bulk samples, and drops any materials added with c_add_material. */
+int c_set_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                   const double* atomic_weights, const double* weight_fractions,
+                   const double* mean_ionizations, double density);
+/* Appends a material for geometry regions, described as for c_set_material, storing its index. */
+int c_add_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                   const double* atomic_weights, const double* weight_fractions,
+                   const double* mean_ionizations, double density, int* index);
+/* Secondary-electron escape from a material: attenuation length lambda_SE (nm) and surface
+   barrier (eV). */
+int c_set_secondary_emission(sem_sim_context* ctx, int material, double escape_depth, double work_function);
+/* Focused probe: Gaussian spot FWHM (nm), convergence semi-angle (rad; <= 0 derives it from the
+   final aperture diameter in um and the working distance), depth of the focal plane below the
+   nominal surface (nm) and pixel dwell time (s), which sets the electrons per pixel
+   unless fixed by c_set_electrons_per_pixel. */
+int c_set_probe(sem_sim_context* ctx, double spot_size, double convergence, double aperture_diameter,
+                double focus_depth, double dwell_time);
+/* Electron column predicting the probe at the current convergence: source reduced brightness
+   (A/(m^2 sr V)) and energy spread (eV), spherical and chromatic aberration coefficients (mm).
+   With target_diameter > 0 (nm) the beam current becomes the most such a probe can carry, and a
+   target no larger than the aberration and diffraction discs is rejected; otherwise the spot size
+   follows from the beam current. Call after c_set_probe. */
+int c_set_column(sem_sim_context* ctx, double reduced_brightness, double energy_spread, double cs,
+                 double cc, double target_diameter);
+/* Probe in effect: spot FWHM (nm), convergence semi-angle (rad) and beam current (nA). */
+int c_get_probe(sem_sim_context* ctx, double* spot_size, double* convergence, double* current);
+/* Primary electrons simulated per pixel (and per line-scan point), with no upper limit. A count
+   of zero or less follows the dose: beam current x dwell time. */
+int c_set_electrons_per_pixel(sem_sim_context* ctx, int count);
+/* Primary electrons simulated per pixel in the current configuration. */
+int c_get_electrons_per_pixel(sem_sim_context* ctx, int* count);
+/* Random sequence of later runs. Each pixel (or line-scan point) draws from its own stream of the
+   seed, so the same seed and settings reproduce a run bit for bit. Defaults to 0. */
+int c_set_seed(sem_sim_context* ctx, uint64_t seed);
+/* Called with `user_data` after every scan line and line-scan point of later runs (NULL disables). */
+int c_set_progress_callback(sem_sim_context* ctx, sem_progress_fn callback, void* user_data);
+/* Detector geometry and response: elevation above the sample plane and azimuth in degrees,
+   distance and inner/outer radius of the active area in mm, SE collection and BSE detection
+   efficiencies, amplifier gain, dark signal, noise level and response time constant (s). */
+int c_set_detector(sem_sim_context* ctx, double elevation, double azimuth, double distance,
+                   double inner_radius, double outer_radius, double se_efficiency,
+                   double bse_efficiency, double gain, double dark_current, double noise_level,
+                   double time_constant);
+/* Sample topography: heights in nm on an nx x ny grid (x fastest) with `spacing` nm between
+   points, centred on the beam axis. nx = 0 or ny = 0 restores a flat surface. */
+int c_set_height_map(sem_sim_context* ctx, const double* heights, int nx, int ny, double spacing);
+/* Volumetric sample replacing the bulk one. Shape k has kind kinds[k] (1 = box, 2 = sphere,
+   3 = cylinder, 4 = layer, 5 = half space) and 8 parameters params[8k..8k+7] in nm:
+   box min and max corners; sphere centre and radius; cylinder centre, unit axis, radius and half
+   length; layer top and bottom z; half space top z (z increases into the sample). Each region is
+   a postfix program of region_lengths[r] entries taken in turn from `program`: k >= 1 tests
+   shape k, -1 = union, -2 = difference, -3 = intersection of the two topmost results. Region r
+   is filled with material region_materials[r]; later regions win where they overlap, and
+   everything outside the regions is vacuum. num_regions = 0 restores the bulk sample. */
+int c_set_geometry(sem_sim_context* ctx, int num_shapes, const int* kinds, const double* params,
+                   int program_length, const int* program, int num_regions,
+                   const int* region_lengths, const int* region_materials);
+/* Record every collision vertex of the first max_electrons electrons of each run (0 disables). */
+int c_set_trajectory_recording(sem_sim_context* ctx, int max_electrons);
+int c_run_simulation(sem_sim_context* ctx);
+/* Single-spot simulations at num_points positions from (start_x, start_y) to (end_x, end_y), in nm. */
+int c_run_line_scan(sem_sim_context* ctx, double start_x, double start_y, double end_x, double end_y, int num_points);
+/* Final state of each tracked electron: rows = 8 values (x, y, z in nm, energy in keV,
+   direction dx, dy, dz, fate 0 = absorbed / 1 = backscattered / 2 = transmitted), cols = electrons.
+   Only the first 100000 electrons of a run (of all points of a line scan) are kept. */
+int c_get_scatter_data(sem_sim_context* ctx, double** data, int* rows, int* cols);
+/* Line scan results: 3 values per point (distance along the line in nm, BSE yield, SE yield). */
+int c_get_line_data(sem_sim_context* ctx, double** data, int* points);
+/* Image channel per primary electron: channel 0 = escaped SEs, 1 = backscattered energy fraction,
+   2 = detector output, 3 = electrons transmitted through the bottom of the sample,
+   4 = backscattered electrons. */
+int c_get_image_data(sem_sim_context* ctx, int channel, double** data, int* width, int* height);
+/* Recorded trajectory vertices: rows = 6 values (electron index from 1, x, y, z in nm, energy in keV,
+   event 0 = entry / 1 = elastic / 2 = backscattered / 3 = absorbed / 4 = transmitted /
+   5 = boundary crossing). */
+int c_get_trajectory_data(sem_sim_context* ctx, double** data, int* rows, int* vertices);
 
 #ifdef __cplusplus
 }
@@ -1,52 +1,135 @@
 module beam
   use iso_fortran_env, only: dp => real64
+  use random_streams, only: rng_state, random_uniform
   implicit none
   private
-  public :: initialize_beam, get_beam_energy, get_beam_direction, get_beam_position
+  public :: probe_type, setup_probe, sample_probe_ray
+  public :: column_type, probe_diameter, probe_current
 
-  real(dp) :: beam_energy        ! in keV
-  real(dp), dimension(3) :: beam_direction ! Unit vector
-  real(dp), dimension(3) :: beam_position  ! Initial position (nm)
+  real(dp), parameter :: PI = 3.141592653589793_dp
+  ! FWHM of a Gaussian in units of its standard deviation
+  real(dp), parameter :: FWHM_TO_SIGMA = 2.3548200450309493_dp
+  real(dp), parameter :: NM_PER_MM = 1.0e6_dp
+
+  ! Focused probe of one simulation. Electrons land with a Gaussian spread of
+  ! positions in the focal plane and arrive from a cone of half-angle
+  ! `convergence`, so above and below focus the spot blurs by convergence * |dz|.
+  type :: probe_type
+    real(dp) :: spot_size = 5.0_dp            ! FWHM in the focal plane (nm)
+    real(dp) :: aperture_diameter = 30.0_dp   ! Final aperture (µm)
+    real(dp) :: working_distance = 10.0_dp    ! Lens to focal plane (mm)
+    real(dp) :: convergence = 1.5e-3_dp       ! Semi-angle (rad)
+    real(dp) :: focus_depth = 0.0_dp          ! z of the focal plane (nm, into the sample)
+  end type probe_type
+
+  ! Electron source and objective lens of the column. The defaults describe a
+  ! tungsten hairpin gun and a conventional pinhole objective lens.
+  type :: column_type
+    real(dp) :: reduced_brightness = 5.0e4_dp  ! A/(m² sr V)
+    real(dp) :: energy_spread = 2.0_dp         ! FWHM in eV
+    real(dp) :: cs = 20.0_dp                   ! Spherical aberration coefficient (mm)
+    real(dp) :: cc = 10.0_dp                   ! Chromatic aberration coefficient (mm)
+  end type column_type
 
 contains
 
-  subroutine initialize_beam(energy, direction, position)
-    ! Initializes the beam with specified parameters
-    real(dp), intent(in) :: energy
-    real(dp), dimension(3), intent(in) :: direction, position
+  subroutine setup_probe(probe, spot_size, convergence, aperture_diameter, working_distance, &
+                         focus_depth)
+    ! Configures the probe. A convergence of zero or less is derived from the
+    ! aperture seen from the focal plane: alpha = aperture radius / working distance.
+    type(probe_type), intent(inout) :: probe
+    real(dp), intent(in) :: spot_size          ! nm FWHM
+    real(dp), intent(in) :: convergence        ! rad
+    real(dp), intent(in) :: aperture_diameter  ! µm
+    real(dp), intent(in) :: working_distance   ! mm
+    real(dp), intent(in) :: focus_depth        ! nm
 
-    if (energy <= 0.0_dp) then
-      print *, 'Error: Beam energy must be positive.'
-      stop
+    probe%spot_size = max(spot_size, 0.0_dp)
+    probe%aperture_diameter = aperture_diameter
+    probe%working_distance = working_distance
+    probe%focus_depth = focus_depth
+    if (convergence > 0.0_dp) then
+      probe%convergence = convergence
+    else
+      probe%convergence = 0.5_dp * aperture_diameter * 1.0e-3_dp / working_distance
     end if
+  end subroutine setup_probe
 
-    if (norm2(direction) == 0.0_dp) then
-      print *, 'Error: Beam direction vector must not be zero.'
-      stop
-    end if
+  subroutine sample_probe_ray(probe, rng, x0, y0, focus, direction)
+    ! Picks the ray of one beam electron aimed at (x0, y0): the point where it
+    ! crosses the focal plane and its unit direction. Aperture illumination is
+    ! uniform, so sin(theta) is distributed as a filled disc of radius sin(alpha).
+    type(probe_type), intent(in) :: probe
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(in) :: x0, y0
+    real(dp), intent(out) :: focus(3), direction(3)
+    real(dp) :: rand1, rand2, radius, sin_theta, phi
+
+    ! Gaussian landing position (Box-Muller)
+    call random_uniform(rng, rand1)
+    call random_uniform(rng, rand2)
+    radius = probe%spot_size / FWHM_TO_SIGMA * sqrt(-2.0_dp * log(1.0_dp - rand1))
+    focus = [x0 + radius * cos(2.0_dp * PI * rand2), y0 + radius * sin(2.0_dp * PI * rand2), &
+             probe%focus_depth]
+
+    ! Direction within the convergence cone, pointing into the sample (+z)
+    call random_uniform(rng, rand1)
+    call random_uniform(rng, rand2)
+    sin_theta = sin(probe%convergence) * sqrt(rand1)
+    phi = 2.0_dp * PI * rand2
+    direction = [sin_theta * cos(phi), sin_theta * sin(phi), sqrt(1.0_dp - sin_theta**2)]
+  end subroutine sample_probe_ray
+
+  subroutine probe_contributions(column, energy, convergence, d_spherical, d_diffraction, &
+                                 d_chromatic)
+    ! Disc diameters (nm) the lens aberrations and diffraction add to the probe
+    ! at beam energy `energy` (keV) and convergence semi-angle `convergence` (rad)
+    type(column_type), intent(in) :: column
+    real(dp), intent(in) :: energy, convergence
+    real(dp), intent(out) :: d_spherical, d_diffraction, d_chromatic
+    real(dp) :: volts, wavelength
+
+    ! Relativistic electron wavelength in nm
+    volts = energy * 1000.0_dp
+    wavelength = 1.226_dp / sqrt(volts * (1.0_dp + 0.9785e-6_dp * volts))
+
+    d_spherical = 0.5_dp * column%cs * NM_PER_MM * convergence**3
+    d_diffraction = 1.22_dp * wavelength / convergence
+    d_chromatic = column%cc * NM_PER_MM * (column%energy_spread / volts) * convergence
+  end subroutine probe_contributions
+
+  function probe_diameter(column, energy, current, convergence) result(diameter)
+    ! Probe diameter (nm) carrying `current` nA at `energy` keV: the demagnified
+    ! source image and the aberration and diffraction discs added in quadrature
+    type(column_type), intent(in) :: column
+    real(dp), intent(in) :: energy, current, convergence
+    real(dp) :: diameter
+    real(dp) :: brightness, d_gaussian, d_spherical, d_diffraction, d_chromatic
+
+    ! Source image size from the brightness: I = beta (pi d^2 / 4) (pi alpha^2)
+    brightness = column%reduced_brightness * energy * 1000.0_dp  ! A/(m² sr)
+    d_gaussian = 2.0_dp / PI * sqrt(current * 1.0e-9_dp / brightness) / convergence * 1.0e9_dp
+    call probe_contributions(column, energy, convergence, d_spherical, d_diffraction, d_chromatic)
+    diameter = sqrt(d_gaussian**2 + d_spherical**2 + d_diffraction**2 + d_chromatic**2)
+  end function probe_diameter
+
+  function probe_current(column, energy, diameter, convergence) result(current)
+    ! Largest current (nA) a probe of `diameter` nm can carry, zero if the
+    ! aberration and diffraction discs alone are already larger
+    type(column_type), intent(in) :: column
+    real(dp), intent(in) :: energy, diameter, convergence
+    real(dp) :: current
+    real(dp) :: brightness, d_gaussian_sq, d_spherical, d_diffraction, d_chromatic
+
+    call probe_contributions(column, energy, convergence, d_spherical, d_diffraction, d_chromatic)
+    d_gaussian_sq = diameter**2 - d_spherical**2 - d_diffraction**2 - d_chromatic**2
+    current = 0.0_dp
+    if (d_gaussian_sq <= 0.0_dp) return
 
-    beam_energy = energy
-    beam_direction = direction / norm2(direction)  ! Normalize direction vector
-    beam_position = position
-  end subroutine initialize_beam
-
-  function get_beam_energy() result(energy)
-    ! Returns the beam energy in keV
-    real(dp) :: energy
-    energy = beam_energy
-  end function get_beam_energy
-
-  function get_beam_direction() result(direction)
-    ! Returns the normalized beam direction vector
-    real(dp), dimension(3) :: direction
-    direction = beam_direction
-  end function get_beam_direction
-
-  function get_beam_position() result(position)
-    ! Returns the beam's initial position
-    real(dp), dimension(3) :: position
-    position = beam_position
-  end function get_beam_position
+    brightness = column%reduced_brightness * energy * 1000.0_dp
+    current = brightness * (PI / 4.0_dp * d_gaussian_sq * 1.0e-18_dp) * (PI * convergence**2) &
+              * 1.0e9_dp
+  end function probe_current
 
   function norm2(v) result(norm)
     ! Computes the Euclidean norm of a 3D vector
@@ -1,104 +1,404 @@
 module c_interface
   use iso_c_binding
   use iso_fortran_env, only: dp => real64
-  use monte_carlo, only: f_init_simulation, f_run_simulation, f_get_scatter_data, &
-                        f_run_line_scan, f_get_line_data, f_get_image_data, image_width, image_height
-  !use monte_carlo, only: init_simulation, run_simulation, get_scatter_data
+  use monte_carlo, only: sim_context, f_init_simulation, f_set_material, f_add_material, &
+                         f_set_secondary_emission, f_set_trajectory_recording, f_set_detector, &
+                         f_set_height_map, f_set_geometry, f_set_probe, f_set_column, &
+                         f_set_electrons_per_pixel, f_set_seed, f_set_progress_callback, &
+                         f_run_simulation, f_run_line_scan, &
+                         CHANNEL_SE, CHANNEL_BSE, CHANNEL_DETECTOR, CHANNEL_TRANSMITTED, &
+                         CHANNEL_BACKSCATTERED
+  use sim_status, only: STATUS_OK, STATUS_INVALID_ARGUMENT, STATUS_NOT_INITIALIZED, STATUS_NO_DATA
   implicit none
 
-  ! Persistent buffers
-  real(c_double), allocatable, target, save :: scatter_temp(:,:)
-  real(c_double), pointer,    save  :: scatter_flat(:)
-  real(c_double), allocatable, target, save :: line_temp(:,:)
-  real(c_double), pointer,    save  :: line_flat(:)
-  real(dp), pointer :: line_data(:,:)
-
 contains
 
-  subroutine init_simulation(energy, current, resolution, distance) bind(C, name="init_simulation")
+  ! Resolve an opaque handle created by create_simulation back to its context
+  function context_from_handle(handle) result(ctx)
+    type(c_ptr), intent(in) :: handle
+    type(sim_context), pointer :: ctx
+
+    call c_f_pointer(handle, ctx)
+  end function context_from_handle
+
+  ! Resolve a handle whose context must already be set up by init_simulation.
+  ! The C layer has rejected null handles before they get here.
+  subroutine initialized_context(handle, ctx, status)
+    type(c_ptr), intent(in) :: handle
+    type(sim_context), pointer, intent(out) :: ctx
+    integer(c_int), intent(out) :: status
+
+    ctx => context_from_handle(handle)
+    status = STATUS_OK
+    if (.not. ctx%initialized) status = STATUS_NOT_INITIALIZED
+  end subroutine initialized_context
+
+  function create_simulation() result(handle) bind(C, name="fortran_create_simulation")
+    type(c_ptr) :: handle
+    type(sim_context), pointer :: ctx
+    integer :: alloc_status
+
+    ! A null handle tells the caller the context could not be allocated
+    handle = c_null_ptr
+    allocate(ctx, stat=alloc_status)
+    if (alloc_status == 0) handle = c_loc(ctx)
+  end function create_simulation
+
+  subroutine free_simulation(handle) bind(C, name="fortran_free_simulation")
+    type(c_ptr), value :: handle
+    type(sim_context), pointer :: ctx
+
+    if (.not. c_associated(handle)) return
+    ctx => context_from_handle(handle)
+    ! Allocatable components are released together with the context
+    deallocate(ctx)
+  end subroutine free_simulation
+
+  function init_simulation(handle, energy, current, width, height, distance, field_of_view, &
+                           offset_x, offset_y, rotation) result(status) bind(C, name="fortran_init_simulation")
+    type(c_ptr), value :: handle
     real(c_double), value :: energy    ! Beam energy in keV
     real(c_double), value :: current   ! Beam current in nA
-    integer(c_int), value :: resolution ! Image resolution in pixels
+    integer(c_int), value :: width, height  ! Image size in pixels
     real(c_double), value :: distance  ! Working distance in mm
-    
-    call f_init_simulation(energy, current, resolution, distance)
-  end subroutine init_simulation
+    real(c_double), value :: field_of_view  ! Horizontal field width in nm
+    real(c_double), value :: offset_x, offset_y  ! Raster centre in nm
+    real(c_double), value :: rotation  ! Scan rotation in degrees
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    ctx => context_from_handle(handle)
+    call f_init_simulation(ctx, energy, current, width, height, distance, field_of_view, &
+                           offset_x, offset_y, rotation, status)
+  end function init_simulation
+
+  function set_material(handle, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                        mean_ionizations, density) result(status) bind(C, name="fortran_set_material")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: num_elements
+    real(c_double), intent(in) :: atomic_numbers(num_elements)    ! Z
+    real(c_double), intent(in) :: atomic_weights(num_elements)    ! A in g/mol
+    real(c_double), intent(in) :: weight_fractions(num_elements)
+    real(c_double), intent(in) :: mean_ionizations(num_elements)  ! J in eV
+    real(c_double), value :: density                               ! g/cm³
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                        mean_ionizations, density, status)
+  end function set_material
+
+  function add_material(handle, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                        mean_ionizations, density, material) result(status) bind(C, name="fortran_add_material")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: num_elements
+    real(c_double), intent(in) :: atomic_numbers(num_elements)
+    real(c_double), intent(in) :: atomic_weights(num_elements)
+    real(c_double), intent(in) :: weight_fractions(num_elements)
+    real(c_double), intent(in) :: mean_ionizations(num_elements)
+    real(c_double), value :: density
+    integer(c_int), intent(out) :: material  ! Index of the new material
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    material = 0
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_add_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                        mean_ionizations, density, material, status)
+  end function add_material
+
+  function set_secondary_emission(handle, material, escape_depth, work_function) &
+      result(status) bind(C, name="fortran_set_secondary_emission")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: material       ! Material index from 1
+    real(c_double), value :: escape_depth   ! λ_SE in nm
+    real(c_double), value :: work_function  ! Surface barrier in eV
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_secondary_emission(ctx, material, escape_depth, work_function, status)
+  end function set_secondary_emission
+
+  function set_probe(handle, spot_size, convergence, aperture_diameter, focus_depth, dwell_time) &
+      result(status) bind(C, name="fortran_set_probe")
+    type(c_ptr), value :: handle
+    real(c_double), value :: spot_size          ! FWHM in nm
+    real(c_double), value :: convergence        ! Semi-angle in rad, <= 0 to derive it
+    real(c_double), value :: aperture_diameter  ! µm
+    real(c_double), value :: focus_depth        ! Focal plane below the nominal surface, nm
+    real(c_double), value :: dwell_time         ! s
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_probe(ctx, spot_size, convergence, aperture_diameter, focus_depth, dwell_time, status)
+  end function set_probe
+
+  function set_column(handle, reduced_brightness, energy_spread, cs, cc, target_diameter) &
+      result(status) bind(C, name="fortran_set_column")
+    type(c_ptr), value :: handle
+    real(c_double), value :: reduced_brightness  ! A/(m² sr V)
+    real(c_double), value :: energy_spread       ! eV
+    real(c_double), value :: cs, cc              ! Aberration coefficients, mm
+    real(c_double), value :: target_diameter     ! nm, <= 0 to derive it from the current
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_column(ctx, reduced_brightness, energy_spread, cs, cc, target_diameter, status)
+  end function set_column
+
+  function get_probe(handle, spot_size, convergence, current) result(status) bind(C, name="fortran_get_probe")
+    type(c_ptr), value :: handle
+    real(c_double), intent(out) :: spot_size, convergence, current
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    spot_size = 0.0_dp
+    convergence = 0.0_dp
+    current = 0.0_dp
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    spot_size = ctx%probe%spot_size
+    convergence = ctx%probe%convergence
+    current = ctx%beam_current
+  end function get_probe
+
+  function set_electrons_per_pixel(handle, count) result(status) bind(C, name="fortran_set_electrons_per_pixel")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: count  ! <= 0 to follow the beam current and dwell time
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_electrons_per_pixel(ctx, count)
+  end function set_electrons_per_pixel
+
+  function get_electrons_per_pixel(handle, count) result(status) bind(C, name="fortran_get_electrons_per_pixel")
+    type(c_ptr), value :: handle
+    integer(c_int), intent(out) :: count
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    count = 0
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    count = ctx%electrons_per_pixel
+  end function get_electrons_per_pixel
 
-  subroutine run_simulation() bind(C, name="run_simulation")
-    call f_run_simulation()
-  end subroutine run_simulation
+  function set_seed(handle, seed) result(status) bind(C, name="fortran_set_seed")
+    type(c_ptr), value :: handle
+    integer(c_int64_t), value :: seed  ! uint64_t on the C side; only the bits matter
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
 
-  subroutine run_line_scan(start_x, end_x, num_points) bind(C, name="run_line_scan")
-    real(c_double), value :: start_x, end_x
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_seed(ctx, seed)
+  end function set_seed
+
+  function set_progress_callback(handle, callback, user_data) result(status) &
+      bind(C, name="fortran_set_progress_callback")
+    type(c_ptr), value :: handle
+    type(c_funptr), value :: callback  ! int (*)(void*, int64_t, int64_t), or NULL
+    type(c_ptr), value :: user_data    ! Passed back to every call
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_progress_callback(ctx, callback, user_data)
+  end function set_progress_callback
+
+  function set_detector(handle, elevation, azimuth, distance, inner_radius, outer_radius, &
+                        se_efficiency, bse_efficiency, gain, dark_current, noise_level, &
+                        time_constant) result(status) bind(C, name="fortran_set_detector")
+    type(c_ptr), value :: handle
+    real(c_double), value :: elevation, azimuth                    ! degrees
+    real(c_double), value :: distance, inner_radius, outer_radius  ! mm
+    real(c_double), value :: se_efficiency, bse_efficiency, gain
+    real(c_double), value :: dark_current, noise_level
+    real(c_double), value :: time_constant                         ! s
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_detector(ctx, elevation, azimuth, distance, inner_radius, outer_radius, &
+                        se_efficiency, bse_efficiency, gain, dark_current, noise_level, &
+                        time_constant, status)
+  end function set_detector
+
+  function set_height_map(handle, heights, nx, ny, spacing) result(status) bind(C, name="fortran_set_height_map")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: nx, ny
+    real(c_double), intent(in) :: heights(nx, ny)  ! nm, x fastest
+    real(c_double), value :: spacing               ! nm per grid step
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_height_map(ctx, nx, ny, heights, spacing, status)
+  end function set_height_map
+
+  function set_geometry(handle, num_shapes, kinds, params, program_length, program, num_regions, &
+                        region_lengths, region_materials) result(status) bind(C, name="fortran_set_geometry")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: num_shapes, program_length, num_regions
+    integer(c_int), intent(in) :: kinds(num_shapes)
+    real(c_double), intent(in) :: params(8, num_shapes)        ! nm
+    integer(c_int), intent(in) :: program(program_length)      ! Postfix, all regions in turn
+    integer(c_int), intent(in) :: region_lengths(num_regions)
+    integer(c_int), intent(in) :: region_materials(num_regions)
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_geometry(ctx, num_shapes, kinds, params, program_length, program, num_regions, &
+                        region_lengths, region_materials, status)
+  end function set_geometry
+
+  function set_trajectory_recording(handle, max_electrons) &
+      result(status) bind(C, name="fortran_set_trajectory_recording")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: max_electrons  ! 0 disables recording
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_set_trajectory_recording(ctx, max_electrons, status)
+  end function set_trajectory_recording
+
+  function run_simulation(handle) result(status) bind(C, name="fortran_run_simulation")
+    type(c_ptr), value :: handle
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_run_simulation(ctx, status)
+  end function run_simulation
+
+  function run_line_scan(handle, start_x, start_y, end_x, end_y, num_points) &
+      result(status) bind(C, name="fortran_run_line_scan")
+    type(c_ptr), value :: handle
+    real(c_double), value :: start_x, start_y, end_x, end_y
     integer(c_int), value :: num_points
-    
-    call f_run_line_scan(start_x, end_x, num_points)
-  end subroutine run_line_scan
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
 
-  function get_scatter_data() result(data) bind(C, name="get_scatter_data")
-    type(c_ptr) :: data
-    data = c_loc(f_get_scatter_data())
-  end function get_scatter_data
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    call f_run_line_scan(ctx, start_x, start_y, end_x, end_y, num_points, status)
+  end function run_line_scan
 
-  subroutine c_get_scatter_data(data_ptr, rows, cols) bind(C, name="c_get_scatter_data")
-    use monte_carlo, only: f_get_scatter_data
-    ! C interface uses TYPE(C_PTR) instead of POINTER dummy
+  function get_scatter_data(handle, data_ptr, rows, cols) result(status) bind(C, name="fortran_get_scatter_data")
+    type(c_ptr), value :: handle
     type(c_ptr), intent(out) :: data_ptr
     integer(c_int), intent(out) :: rows, cols
-    integer :: total
-
-    ! Get the 2D scatter data
-    scatter_temp = f_get_scatter_data()
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
 
-    rows = size(scatter_temp, 1)    ! should be 3
-    cols = size(scatter_temp, 2)    ! number of electrons
-    total = rows * cols
+    data_ptr = c_null_ptr
+    rows = 0
+    cols = 0
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    if (.not. allocated(ctx%scatter_positions)) then
+      status = STATUS_NO_DATA
+      return
+    end if
 
-    ! Create a Fortran pointer to the contiguous data
-    call c_f_pointer(c_loc(scatter_temp), scatter_flat, [total])
-
-    ! Return its C address
-    data_ptr = c_loc(scatter_flat)
-  end subroutine
+    rows = size(ctx%scatter_positions, 1)    ! (x,y,z,energy,dx,dy,dz,fate)
+    cols = ctx%num_recorded                  ! electrons tracked in the last run
+    data_ptr = c_loc(ctx%scatter_positions)
+  end function get_scatter_data
 
-  subroutine get_line_data(data_ptr, points) bind(C, name="get_line_data")
+  function get_line_data(handle, data_ptr, points) result(status) bind(C, name="fortran_get_line_data")
+    type(c_ptr), value :: handle
     type(c_ptr), intent(out) :: data_ptr
     integer(c_int), intent(out) :: points
-    integer :: total
-    
-    ! Get the line scan data as a Fortran pointer
-    line_data => f_get_line_data()
-    
-    ! Copy to our temporary buffer
-    if (allocated(line_temp)) deallocate(line_temp)
-    allocate(line_temp(size(line_data,1), size(line_data,2)))
-    line_temp = line_data
-    
-    points = size(line_temp, 2)
-    total = size(line_temp)
-    
-    ! Create a Fortran pointer to the contiguous data
-    if (associated(line_flat)) nullify(line_flat)
-    call c_f_pointer(c_loc(line_temp), line_flat, [total])
-    
-    ! Return its C address
-    data_ptr = c_loc(line_flat)
-  end subroutine get_line_data
-
-  function c_get_image_data(data_ptr, width, height) bind(C, name="fortran_get_image_data")
-    use iso_c_binding
-    use monte_carlo, only: f_get_image_data
-    implicit none
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    data_ptr = c_null_ptr
+    points = 0
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    if (.not. allocated(ctx%line_scan_data)) then
+      status = STATUS_NO_DATA
+      return
+    end if
+
+    points = size(ctx%line_scan_data, 2)    ! rows are (distance, BSE, SE)
+    data_ptr = c_loc(ctx%line_scan_data)
+  end function get_line_data
+
+  function get_image_data(handle, channel, data_ptr, width, height) result(status) &
+      bind(C, name="fortran_get_image_data")
+    type(c_ptr), value :: handle
+    integer(c_int), value :: channel   ! One of the CHANNEL_* indices
     type(c_ptr), intent(out) :: data_ptr
     integer(c_int), intent(out) :: width, height
-    real(c_double), pointer :: fortran_array(:,:)
-    integer :: c_get_image_data
-
-    fortran_array => f_get_image_data()
-    data_ptr = c_loc(fortran_array(1,1))
-    width = image_width
-    height = image_height
-    c_get_image_data = 0
-  end function c_get_image_data
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    data_ptr = c_null_ptr
+    width = 0
+    height = 0
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    ! The channels are allocated by init_simulation, so only the index can be wrong
+    select case (channel)
+    case (CHANNEL_SE)
+      data_ptr = c_loc(ctx%se_image)
+    case (CHANNEL_BSE)
+      data_ptr = c_loc(ctx%bse_image)
+    case (CHANNEL_DETECTOR)
+      data_ptr = c_loc(ctx%detector_image)
+    case (CHANNEL_TRANSMITTED)
+      data_ptr = c_loc(ctx%transmitted_image)
+    case (CHANNEL_BACKSCATTERED)
+      data_ptr = c_loc(ctx%backscattered_image)
+    case default
+      status = STATUS_INVALID_ARGUMENT
+      return
+    end select
+
+    width = ctx%image_width
+    height = ctx%image_height
+  end function get_image_data
+
+  function get_trajectory_data(handle, data_ptr, rows, vertices) &
+      result(status) bind(C, name="fortran_get_trajectory_data")
+    type(c_ptr), value :: handle
+    type(c_ptr), intent(out) :: data_ptr
+    integer(c_int), intent(out) :: rows, vertices
+    integer(c_int) :: status
+    type(sim_context), pointer :: ctx
+
+    data_ptr = c_null_ptr
+    rows = 0
+    vertices = 0
+    ! Nothing recorded (recording disabled) is an empty result, not an error
+    call initialized_context(handle, ctx, status)
+    if (status /= STATUS_OK) return
+    if (.not. allocated(ctx%trajectory_data) .or. ctx%num_vertices == 0) return
+
+    rows = size(ctx%trajectory_data, 1)    ! (electron,x,y,z,energy,event)
+    vertices = ctx%num_vertices
+    data_ptr = c_loc(ctx%trajectory_data)
+  end function get_trajectory_data
 
 end module c_interface
@@ -0,0 +1,575 @@
+! geometry.f90
+! Sample description used to decide where electrons enter and leave the sample
+! and which material they are travelling through
+
+module geometry
+  use iso_fortran_env, only: dp => real64
+  implicit none
+  private
+  public :: sample_geometry, set_height_map, clear_height_map
+  public :: surface_height, surface_normal, is_inside, distance_to_surface
+  public :: set_regions, clear_regions, regions_valid, material_at, next_boundary, beam_entry
+  public :: SHAPE_PARAMS, SHAPE_BOX, SHAPE_SPHERE, SHAPE_CYLINDER, SHAPE_LAYER, SHAPE_HALF_SPACE
+  public :: OP_UNION, OP_DIFFERENCE, OP_INTERSECTION
+
+  ! Bisection steps used to locate a surface crossing once it is bracketed
+  integer, parameter :: CROSSING_ITERATIONS = 30
+  ! Upper bound on march steps along one segment
+  integer, parameter :: MAX_MARCH_STEPS = 10000
+
+  ! Primitive shapes and their parameters (nm, z increasing into the sample):
+  !   box        min corner (1:3), max corner (4:6)
+  !   sphere     centre (1:3), radius (4)
+  !   cylinder   centre (1:3), unit axis (4:6), radius (7), half length (8)
+  !   layer      top z (1), bottom z (2), unbounded in x and y
+  !   half space top z (1), filling everything below
+  integer, parameter :: SHAPE_PARAMS = 8
+  integer, parameter :: SHAPE_BOX = 1
+  integer, parameter :: SHAPE_SPHERE = 2
+  integer, parameter :: SHAPE_CYLINDER = 3
+  integer, parameter :: SHAPE_LAYER = 4
+  integer, parameter :: SHAPE_HALF_SPACE = 5
+
+  ! Region programs are postfix: a positive entry tests primitive k, a negative
+  ! one combines the two results on top of the stack
+  integer, parameter :: OP_UNION = -1
+  integer, parameter :: OP_DIFFERENCE = -2
+  integer, parameter :: OP_INTERSECTION = -3
+  integer, parameter :: MAX_STACK = 64
+
+  ! Offset used to step off a boundary when probing the material beyond it (nm)
+  real(dp), parameter :: BOUNDARY_EPS = 1.0e-6_dp
+  ! Fixed-point steps locating where a tilted beam ray meets a height map
+  integer, parameter :: ENTRY_ITERATIONS = 8
+
+  ! Without regions the sample is a bulk of material 1 below the surface
+  ! z = -h(x, y), with z increasing into the sample; without a height map that
+  ! surface is the flat plane z = 0. With regions the sample is made of the
+  ! regions alone, later regions taking precedence where they overlap, and
+  ! vacuum everywhere else.
+  type :: sample_geometry
+    logical :: has_height_map = .false.
+    real(dp), allocatable :: heights(:,:)  ! Height above z = 0 in nm, x fastest
+    real(dp) :: spacing = 1.0_dp           ! nm between grid points
+    real(dp) :: origin_x = 0.0_dp          ! Position of heights(1, 1) in nm
+    real(dp) :: origin_y = 0.0_dp
+    real(dp) :: height_min = 0.0_dp
+    real(dp) :: height_max = 0.0_dp
+
+    ! Constructive solid geometry
+    integer :: num_regions = 0
+    integer, allocatable :: shape_kind(:)          ! Kind of each primitive
+    real(dp), allocatable :: shape_params(:,:)     ! (SHAPE_PARAMS, primitive)
+    integer, allocatable :: program(:)             ! Postfix programs of all regions
+    integer, allocatable :: region_start(:)        ! First program entry of each region
+    integer, allocatable :: region_length(:)
+    integer, allocatable :: region_material(:)     ! Material index of each region
+    real(dp) :: scene_top = 0.0_dp                 ! Smallest z reached by any primitive
+  end type sample_geometry
+
+contains
+
+  subroutine set_height_map(geom, heights, spacing)
+    ! Installs a height map (nm) sampled every `spacing` nm and centred on the beam axis
+    type(sample_geometry), intent(inout) :: geom
+    real(dp), intent(in) :: heights(:,:)
+    real(dp), intent(in) :: spacing
+
+    geom%heights = heights
+    geom%spacing = spacing
+    geom%origin_x = -0.5_dp * (size(heights, 1) - 1) * spacing
+    geom%origin_y = -0.5_dp * (size(heights, 2) - 1) * spacing
+    geom%height_min = minval(heights)
+    geom%height_max = maxval(heights)
+    geom%has_height_map = .true.
+  end subroutine set_height_map
+
+  subroutine clear_height_map(geom)
+    ! Returns to a flat surface at z = 0
+    type(sample_geometry), intent(inout) :: geom
+
+    if (allocated(geom%heights)) deallocate(geom%heights)
+    geom%has_height_map = .false.
+    geom%height_min = 0.0_dp
+    geom%height_max = 0.0_dp
+  end subroutine clear_height_map
+
+  pure function surface_height(geom, x, y) result(height)
+    ! Bilinearly interpolated surface height at (x, y); the map's edge values
+    ! extend beyond its borders
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: x, y
+    real(dp) :: height
+    real(dp) :: gx, gy, fx, fy
+    integer :: i, j, nx, ny
+
+    height = 0.0_dp
+    if (.not. geom%has_height_map) return
+
+    nx = size(geom%heights, 1)
+    ny = size(geom%heights, 2)
+    gx = min(max((x - geom%origin_x) / geom%spacing + 1.0_dp, 1.0_dp), real(nx, dp))
+    gy = min(max((y - geom%origin_y) / geom%spacing + 1.0_dp, 1.0_dp), real(ny, dp))
+    i = min(int(gx), max(nx - 1, 1))
+    j = min(int(gy), max(ny - 1, 1))
+    fx = gx - i
+    fy = gy - j
+
+    height = geom%heights(i, j)
+    if (nx > 1) height = height + fx * (geom%heights(i + 1, j) - geom%heights(i, j))
+    if (ny > 1) then
+      height = height + fy * (geom%heights(i, j + 1) - geom%heights(i, j))
+      if (nx > 1) height = height + fx * fy * (geom%heights(i + 1, j + 1) - geom%heights(i + 1, j) &
+                                               - geom%heights(i, j + 1) + geom%heights(i, j))
+    end if
+  end function surface_height
+
+  pure function surface_normal(geom, x, y) result(normal)
+    ! Outward unit normal of the surface above (x, y), pointing into the vacuum
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: x, y
+    real(dp) :: normal(3)
+    real(dp) :: h, grad_x, grad_y
+
+    normal = [0.0_dp, 0.0_dp, -1.0_dp]
+    if (.not. geom%has_height_map) return
+
+    ! Central differences over one grid spacing
+    h = 0.5_dp * geom%spacing
+    grad_x = (surface_height(geom, x + h, y) - surface_height(geom, x - h, y)) / (2.0_dp * h)
+    grad_y = (surface_height(geom, x, y + h) - surface_height(geom, x, y - h)) / (2.0_dp * h)
+    normal = -[grad_x, grad_y, 1.0_dp] / sqrt(1.0_dp + grad_x**2 + grad_y**2)
+  end function surface_normal
+
+  pure function is_inside(geom, position) result(inside)
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: position(3)
+    logical :: inside
+
+    inside = position(3) + surface_height(geom, position(1), position(2)) > 0.0_dp
+  end function is_inside
+
+  function distance_to_surface(geom, position, direction, max_distance) result(distance)
+    ! Distance along a straight path from a point inside the sample to where it
+    ! first leaves through the surface, or huge() if it stays inside for
+    ! max_distance. Height maps are marched in half-spacing steps and the
+    ! crossing refined by bisection.
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: position(3), direction(3)
+    real(dp), intent(in) :: max_distance
+    real(dp) :: distance
+    real(dp) :: t_in, t_out, t_mid
+    integer :: k, steps, iter
+
+    distance = huge(1.0_dp)
+    if (max_distance <= 0.0_dp) return
+
+    if (.not. geom%has_height_map) then
+      ! Flat surface at z = 0
+      if (direction(3) < 0.0_dp) then
+        t_out = -position(3) / direction(3)
+        if (t_out <= max_distance) distance = max(t_out, 0.0_dp)
+      end if
+      return
+    end if
+
+    ! The whole segment stays below the lowest point of the surface
+    if (min(position(3), position(3) + max_distance * direction(3)) + geom%height_min > 0.0_dp) return
+
+    steps = min(max(ceiling(max_distance / (0.5_dp * geom%spacing)), 1), MAX_MARCH_STEPS)
+    t_in = 0.0_dp
+    do k = 1, steps
+      t_out = max_distance * real(k, dp) / real(steps, dp)
+      if (.not. is_inside(geom, position + t_out * direction)) then
+        do iter = 1, CROSSING_ITERATIONS
+          t_mid = 0.5_dp * (t_in + t_out)
+          if (is_inside(geom, position + t_mid * direction)) then
+            t_in = t_mid
+          else
+            t_out = t_mid
+          end if
+        end do
+        distance = t_out
+        return
+      end if
+      t_in = t_out
+    end do
+  end function distance_to_surface
+
+  subroutine set_regions(geom, shape_kind, shape_params, program, region_length, region_material)
+    ! Installs a constructive-solid-geometry sample. Each region is a slice of
+    ! `program` of the given length, filled with the given material.
+    type(sample_geometry), intent(inout) :: geom
+    integer, intent(in) :: shape_kind(:)
+    real(dp), intent(in) :: shape_params(:,:)
+    integer, intent(in) :: program(:)
+    integer, intent(in) :: region_length(:)
+    integer, intent(in) :: region_material(:)
+    integer :: r, k
+
+    geom%num_regions = size(region_length)
+    geom%shape_kind = shape_kind
+    geom%shape_params = shape_params
+    geom%program = program
+    geom%region_length = region_length
+    geom%region_material = region_material
+    if (allocated(geom%region_start)) deallocate(geom%region_start)
+    allocate(geom%region_start(geom%num_regions))
+    k = 1
+    do r = 1, geom%num_regions
+      geom%region_start(r) = k
+      k = k + region_length(r)
+    end do
+
+    ! Beams start just above the highest primitive
+    geom%scene_top = 0.0_dp
+    do k = 1, size(shape_kind)
+      geom%scene_top = min(geom%scene_top, shape_top(shape_kind(k), shape_params(:, k)))
+    end do
+  end subroutine set_regions
+
+  subroutine clear_regions(geom)
+    ! Returns to a bulk sample below the surface
+    type(sample_geometry), intent(inout) :: geom
+
+    geom%num_regions = 0
+    geom%scene_top = 0.0_dp
+  end subroutine clear_regions
+
+  pure function regions_valid(shape_kind, program, region_length) result(valid)
+    ! Whether set_regions can evaluate the programs: known primitive kinds, and
+    ! region slices covering `program` that each leave exactly one result on a
+    ! stack of at most MAX_STACK entries
+    integer, intent(in) :: shape_kind(:)
+    integer, intent(in) :: program(:)
+    integer, intent(in) :: region_length(:)
+    logical :: valid
+    integer :: r, k, op, top, first
+
+    valid = all(shape_kind >= SHAPE_BOX .and. shape_kind <= SHAPE_HALF_SPACE) .and. &
+            all(region_length >= 1) .and. sum(region_length) == size(program)
+    if (.not. valid) return
+
+    first = 1
+    do r = 1, size(region_length)
+      top = 0
+      do k = first, first + region_length(r) - 1
+        op = program(k)
+        if (op > 0 .and. op <= size(shape_kind)) then
+          top = top + 1
+        else if (op >= OP_INTERSECTION .and. op <= OP_UNION .and. top >= 2) then
+          top = top - 1
+        else
+          valid = .false.
+        end if
+        if (top > MAX_STACK) valid = .false.
+        if (.not. valid) return
+      end do
+      if (top /= 1) then
+        valid = .false.
+        return
+      end if
+      first = first + region_length(r)
+    end do
+  end function regions_valid
+
+  pure function shape_top(kind, p) result(top)
+    ! Smallest z reached by a primitive
+    integer, intent(in) :: kind
+    real(dp), intent(in) :: p(SHAPE_PARAMS)
+    real(dp) :: top
+
+    select case (kind)
+    case (SHAPE_BOX)
+      top = p(3)
+    case (SHAPE_SPHERE)
+      top = p(3) - p(4)
+    case (SHAPE_CYLINDER)
+      top = p(3) - abs(p(6)) * p(8) - sqrt(max(1.0_dp - p(6)**2, 0.0_dp)) * p(7)
+    case default
+      top = p(1)
+    end select
+  end function shape_top
+
+  pure function shape_contains(kind, p, point) result(inside)
+    integer, intent(in) :: kind
+    real(dp), intent(in) :: p(SHAPE_PARAMS)
+    real(dp), intent(in) :: point(3)
+    logical :: inside
+    real(dp) :: v(3), h
+
+    select case (kind)
+    case (SHAPE_BOX)
+      inside = all(point >= p(1:3)) .and. all(point <= p(4:6))
+    case (SHAPE_SPHERE)
+      inside = sum((point - p(1:3))**2) <= p(4)**2
+    case (SHAPE_CYLINDER)
+      v = point - p(1:3)
+      h = dot_product(v, p(4:6))
+      inside = abs(h) <= p(8) .and. sum((v - h * p(4:6))**2) <= p(7)**2
+    case (SHAPE_LAYER)
+      inside = point(3) >= p(1) .and. point(3) <= p(2)
+    case (SHAPE_HALF_SPACE)
+      inside = point(3) >= p(1)
+    case default
+      inside = .false.
+    end select
+  end function shape_contains
+
+  pure function shape_normal(kind, p, point) result(normal)
+    ! Unit normal of a primitive's surface at a point on it (sign unspecified)
+    integer, intent(in) :: kind
+    real(dp), intent(in) :: p(SHAPE_PARAMS)
+    real(dp), intent(in) :: point(3)
+    real(dp) :: normal(3)
+    real(dp) :: v(3), radial(3), h, face(6)
+    integer :: i
+
+    normal = [0.0_dp, 0.0_dp, 1.0_dp]
+    select case (kind)
+    case (SHAPE_BOX)
+      ! Face closest to the point
+      face = [abs(point - p(1:3)), abs(point - p(4:6))]
+      i = mod(minloc(face, 1) - 1, 3) + 1
+      normal = 0.0_dp
+      normal(i) = 1.0_dp
+    case (SHAPE_SPHERE)
+      normal = (point - p(1:3)) / p(4)
+    case (SHAPE_CYLINDER)
+      v = point - p(1:3)
+      h = dot_product(v, p(4:6))
+      radial = v - h * p(4:6)
+      if (abs(abs(h) - p(8)) < abs(norm2(radial) - p(7))) then
+        normal = p(4:6)
+      else if (norm2(radial) > 0.0_dp) then
+        normal = radial / norm2(radial)
+      end if
+    end select
+  end function shape_normal
+
+  pure subroutine shape_crossings(kind, p, position, direction, t, n)
+    ! Distances along a ray at which it may cross the primitive's surface. Some
+    ! candidates lie on the surface's extension and are discarded by the caller.
+    integer, intent(in) :: kind
+    real(dp), intent(in) :: p(SHAPE_PARAMS)
+    real(dp), intent(in) :: position(3), direction(3)
+    real(dp), intent(out) :: t(6)
+    integer, intent(out) :: n
+    real(dp) :: v(3), a, b, c, disc, o_perp(3), d_perp(3), h0, dh
+    integer :: i
+
+    n = 0
+    select case (kind)
+    case (SHAPE_BOX)
+      do i = 1, 3
+        if (direction(i) /= 0.0_dp) then
+          t(n + 1) = (p(i) - position(i)) / direction(i)
+          t(n + 2) = (p(i + 3) - position(i)) / direction(i)
+          n = n + 2
+        end if
+      end do
+    case (SHAPE_SPHERE)
+      v = position - p(1:3)
+      b = dot_product(v, direction)
+      c = dot_product(v, v) - p(4)**2
+      disc = b * b - c
+      if (disc >= 0.0_dp) then
+        t(1) = -b - sqrt(disc)
+        t(2) = -b + sqrt(disc)
+        n = 2
+      end if
+    case (SHAPE_CYLINDER)
+      v = position - p(1:3)
+      h0 = dot_product(v, p(4:6))
+      dh = dot_product(direction, p(4:6))
+      o_perp = v - h0 * p(4:6)
+      d_perp = direction - dh * p(4:6)
+      a = dot_product(d_perp, d_perp)
+      b = dot_product(o_perp, d_perp)
+      c = dot_product(o_perp, o_perp) - p(7)**2
+      disc = b * b - a * c
+      if (a > 0.0_dp .and. disc >= 0.0_dp) then
+        t(1) = (-b - sqrt(disc)) / a
+        t(2) = (-b + sqrt(disc)) / a
+        n = 2
+      end if
+      if (dh /= 0.0_dp) then
+        t(n + 1) = (p(8) - h0) / dh
+        t(n + 2) = (-p(8) - h0) / dh
+        n = n + 2
+      end if
+    case (SHAPE_LAYER)
+      if (direction(3) /= 0.0_dp) then
+        t(1) = (p(1) - position(3)) / direction(3)
+        t(2) = (p(2) - position(3)) / direction(3)
+        n = 2
+      end if
+    case (SHAPE_HALF_SPACE)
+      if (direction(3) /= 0.0_dp) then
+        t(1) = (p(1) - position(3)) / direction(3)
+        n = 1
+      end if
+    end select
+  end subroutine shape_crossings
+
+  pure function region_contains(geom, r, point) result(inside)
+    ! Evaluates region r's postfix program at a point
+    type(sample_geometry), intent(in) :: geom
+    integer, intent(in) :: r
+    real(dp), intent(in) :: point(3)
+    logical :: inside
+    logical :: stack(MAX_STACK)
+    integer :: k, op, top
+
+    top = 0
+    do k = geom%region_start(r), geom%region_start(r) + geom%region_length(r) - 1
+      op = geom%program(k)
+      if (op > 0) then
+        top = min(top + 1, MAX_STACK)
+        stack(top) = shape_contains(geom%shape_kind(op), geom%shape_params(:, op), point)
+      else if (top >= 2) then
+        select case (op)
+        case (OP_UNION)
+          stack(top - 1) = stack(top - 1) .or. stack(top)
+        case (OP_DIFFERENCE)
+          stack(top - 1) = stack(top - 1) .and. .not. stack(top)
+        case (OP_INTERSECTION)
+          stack(top - 1) = stack(top - 1) .and. stack(top)
+        end select
+        top = top - 1
+      end if
+    end do
+    inside = top >= 1
+    if (inside) inside = stack(top)
+  end function region_contains
+
+  pure function material_at(geom, point) result(material)
+    ! Material index at a point, or 0 for vacuum
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: point(3)
+    integer :: material
+    integer :: r
+
+    material = 0
+    if (geom%num_regions == 0) then
+      if (is_inside(geom, point)) material = 1
+      return
+    end if
+    do r = geom%num_regions, 1, -1
+      if (region_contains(geom, r, point)) then
+        material = geom%region_material(r)
+        return
+      end if
+    end do
+  end function material_at
+
+  subroutine next_boundary(geom, position, direction, max_distance, current, distance, &
+                           next_material, cos_normal)
+    ! Finds where a straight path through `current` material first enters a
+    ! different material (0 = vacuum) within max_distance. Returns the distance
+    ! to the crossing, the new material and the cosine between the path and the
+    ! boundary normal there; without a crossing, distance is huge() and
+    ! next_material equals current. Bulk samples are never re-entered from vacuum.
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: position(3), direction(3)
+    real(dp), intent(in) :: max_distance
+    integer, intent(in) :: current
+    real(dp), intent(out) :: distance
+    integer, intent(out) :: next_material
+    real(dp), intent(out) :: cos_normal
+    real(dp), allocatable :: cand_t(:)
+    integer, allocatable :: cand_shape(:)
+    real(dp) :: t(6), crossing(3), key_t
+    integer :: k, i, j, n, num, key_s, m
+
+    distance = huge(1.0_dp)
+    next_material = current
+    cos_normal = 1.0_dp
+
+    if (geom%num_regions == 0) then
+      if (current == 0) return
+      distance = distance_to_surface(geom, position, direction, max_distance)
+      if (distance <= max_distance) then
+        next_material = 0
+        crossing = position + distance * direction
+        cos_normal = abs(dot_product(direction, surface_normal(geom, crossing(1), crossing(2))))
+      else
+        distance = huge(1.0_dp)
+      end if
+      return
+    end if
+
+    ! Collect candidate crossings of every primitive ahead of the position
+    allocate(cand_t(6 * size(geom%shape_kind)), cand_shape(6 * size(geom%shape_kind)))
+    num = 0
+    do k = 1, size(geom%shape_kind)
+      call shape_crossings(geom%shape_kind(k), geom%shape_params(:, k), position, direction, t, n)
+      do i = 1, n
+        if (t(i) > BOUNDARY_EPS .and. t(i) <= max_distance) then
+          num = num + 1
+          cand_t(num) = t(i)
+          cand_shape(num) = k
+        end if
+      end do
+    end do
+
+    ! Sort them by distance (insertion sort; the lists are short)
+    do i = 2, num
+      key_t = cand_t(i)
+      key_s = cand_shape(i)
+      j = i - 1
+      do while (j >= 1)
+        if (cand_t(j) <= key_t) exit
+        cand_t(j + 1) = cand_t(j)
+        cand_shape(j + 1) = cand_shape(j)
+        j = j - 1
+      end do
+      cand_t(j + 1) = key_t
+      cand_shape(j + 1) = key_s
+    end do
+
+    ! The first candidate beyond which the material changes is the crossing
+    do i = 1, num
+      m = material_at(geom, position + (cand_t(i) + BOUNDARY_EPS) * direction)
+      if (m /= current) then
+        distance = cand_t(i)
+        next_material = m
+        k = cand_shape(i)
+        crossing = position + distance * direction
+        cos_normal = abs(dot_product(direction, &
+                         shape_normal(geom%shape_kind(k), geom%shape_params(:, k), crossing)))
+        return
+      end if
+    end do
+  end subroutine next_boundary
+
+  subroutine beam_entry(geom, through, direction, position, material)
+    ! Point where a beam electron travelling along `direction` through the point
+    ! `through` first enters the sample, and the material there (0 if it misses
+    ! the sample entirely). Beam directions point into the sample (direction(3) > 0).
+    type(sample_geometry), intent(in) :: geom
+    real(dp), intent(in) :: through(3), direction(3)
+    real(dp), intent(out) :: position(3)
+    integer, intent(out) :: material
+    real(dp) :: distance, cos_normal, t
+    integer :: k
+
+    if (geom%num_regions == 0) then
+      ! Solve z = -h(x, y) along the ray; exact after one step on a flat surface
+      position = through
+      do k = 1, ENTRY_ITERATIONS
+        t = (-surface_height(geom, position(1), position(2)) - through(3)) / direction(3)
+        position = through + t * direction
+      end do
+      material = 1
+      return
+    end if
+
+    ! Start in vacuum above the highest primitive and fly to the first region
+    t = (geom%scene_top - 1.0_dp - through(3)) / direction(3)
+    position = through + t * direction
+    call next_boundary(geom, position, direction, huge(1.0_dp), 0, distance, material, cos_normal)
+    if (material /= 0) position = position + distance * direction
+  end subroutine beam_entry
+
+end module geometry
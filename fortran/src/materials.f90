@@ -1,4 +1,5 @@
 module materials
+    use sim_status, only: STATUS_OK, STATUS_LIMIT_EXCEEDED
     implicit none
     private
     public :: define_material, get_atomic_number, get_density, get_mean_free_path
@@ -18,16 +19,19 @@ module materials
     integer :: material_count = 0
 
 contains
-    subroutine define_material(name, Z, density, mean_free_path)
+    subroutine define_material(name, Z, density, mean_free_path, status)
+        ! Reports STATUS_LIMIT_EXCEEDED once max_materials are defined
         character(len=*), intent(in) :: name
         integer, intent(in) :: Z
         real(dp), intent(in) :: density, mean_free_path
+        integer, intent(out) :: status
         
         if(material_count >= max_materials) then
-            print *, 'ERROR: Maximum number of materials reached.'
-            stop
+            status = STATUS_LIMIT_EXCEEDED
+            return
         end if
 
+        status = STATUS_OK
         material_count = material_count + 1
         material_list(material_count)%name = name
         material_list(material_count)%Z = Z
@@ -1,12 +1,24 @@
 module monte_carlo
     use iso_c_binding
-    use iso_fortran_env, only: dp => real64
+    use iso_fortran_env, only: dp => real64, int64
+    use beam, only: probe_type, setup_probe, sample_probe_ray, column_type, probe_diameter, &
+                    probe_current
+    use scattering, only: generate_secondaries, sample_se_energy, se_escapes
+    use random_streams, only: rng_state, rng_seed, random_uniform
+    use geometry, only: sample_geometry, set_height_map, clear_height_map, set_regions, &
+                        clear_regions, regions_valid, next_boundary, beam_entry, SHAPE_PARAMS
+    use sim_status, only: STATUS_OK, STATUS_INVALID_ARGUMENT, STATUS_OUT_OF_MEMORY
+    use signals, only: detector_type, setup_detector, generate_signal, apply_detector_response, &
+                       SIGNAL_SE, SIGNAL_BSE
     implicit none
 
-    ! Make module variables visible to other modules
-    public :: f_init_simulation, f_run_simulation, f_get_scatter_data, f_get_image_data
-    public :: f_run_line_scan, f_get_line_data
-    public :: scatter_positions, num_electrons, line_scan_data
+    ! Make module procedures visible to other modules
+    public :: sim_context
+    public :: f_init_simulation, f_set_material, f_add_material, f_set_secondary_emission
+    public :: f_set_trajectory_recording, f_set_detector, f_set_height_map, f_set_geometry
+    public :: f_set_probe, f_set_column, f_set_electrons_per_pixel, f_set_seed, f_set_progress_callback
+    public :: f_run_simulation, f_run_line_scan
+    public :: CHANNEL_SE, CHANNEL_BSE, CHANNEL_DETECTOR, CHANNEL_TRANSMITTED, CHANNEL_BACKSCATTERED
 
     ! Physical constants
     real(dp), parameter :: ELECTRON_MASS = 9.10938356e-31_dp  ! kg
@@ -19,373 +31,1112 @@ module monte_carlo
     real(dp), parameter :: BOHR_RADIUS = 5.29177210903e-11_dp ! m
     real(dp), parameter :: FINE_STRUCTURE = 1.0_dp/137.035999084_dp
     real(dp), parameter :: REST_MASS_ENERGY = 511.0_dp ! keV
+    real(dp), parameter :: ELECTRONS_PER_NA_S = 6.241509074e9_dp ! Electrons per nA·s
 
     ! Simulation parameters
-    integer, parameter :: MAX_ELECTRONS = 100000
-    integer :: num_electrons
-    real(dp), allocatable, target :: scatter_positions(:,:)  ! (x,y,z,energy) for each electron
-    real(dp), allocatable :: surface_heights(:,:)           ! Surface topography
-    real(dp), allocatable :: material_properties(:,:,:)     ! Composition and crystal orientation
-    real(dp), allocatable, target :: line_scan_data(:,:)   ! Line scan intensity data
-    real(dp), allocatable, target :: image_buffer(:,:)  ! 2D image buffer
-    integer :: image_width, image_height
-    
-    ! Sample properties (Iron Oxide - Fe2O3)
-    real(dp), parameter :: FE_ATOMIC_NUMBER = 26.0_dp
-    real(dp), parameter :: O_ATOMIC_NUMBER = 8.0_dp
-    real(dp), parameter :: DENSITY = 5.24_dp  ! g/cm³
-    real(dp), parameter :: CRYSTAL_SIZE = 50.0_dp  ! nm
-    real(dp), parameter :: SURFACE_ROUGHNESS = 10.0_dp  ! nm
-    real(dp), parameter :: MEAN_IONIZATION_POTENTIAL = 286.0_dp  ! eV (Fe2O3)
-    
-    ! Beam parameters
-    real(dp) :: beam_energy           ! keV
-    real(dp) :: beam_current         ! nA
-    real(dp) :: spot_size           ! nm
-    real(dp) :: working_distance    ! mm
-    real(dp) :: scan_resolution     ! pixels
-    real(dp) :: dwell_time         ! μs
-    logical :: is_line_scan = .false. ! Mode switch
+    integer, parameter :: MAX_RECORDED = 100000  ! Final states kept in scatter_positions per run
+    real(dp), parameter :: CUTOFF_ENERGY = 0.1_dp     ! keV, electrons below this are absorbed
+    real(dp), parameter :: SE_EXCITATION_ENERGY = 15.0_dp  ! eV spent per slow secondary excited
+    real(dp), parameter :: SE_SAMPLING_DEPTH = 5.0_dp      ! SE escape depths below which none escape
+    integer, parameter :: MAX_SE_CROSSINGS = 8  ! Interfaces a secondary may cross on its way out
+
+    ! Electron fates recorded in scatter_positions
+    integer, parameter :: SCATTER_ROWS = 8  ! (x,y,z,energy,dx,dy,dz,fate) per electron
+    integer, parameter :: FATE_ABSORBED = 0
+    integer, parameter :: FATE_BACKSCATTERED = 1
+    integer, parameter :: FATE_TRANSMITTED = 2
+
+    ! Trajectory vertex events
+    integer, parameter :: TRAJECTORY_ROWS = 6  ! (electron,x,y,z,energy,event) per vertex
+    integer, parameter :: EVENT_ENTRY = 0
+    integer, parameter :: EVENT_ELASTIC = 1
+    integer, parameter :: EVENT_BACKSCATTERED = 2
+    integer, parameter :: EVENT_ABSORBED = 3
+    integer, parameter :: EVENT_TRANSMITTED = 4
+    integer, parameter :: EVENT_BOUNDARY = 5
+
+    ! Image channels
+    integer, parameter :: CHANNEL_SE = 0
+    integer, parameter :: CHANNEL_BSE = 1
+    integer, parameter :: CHANNEL_DETECTOR = 2
+    integer, parameter :: CHANNEL_TRANSMITTED = 3
+    integer, parameter :: CHANNEL_BACKSCATTERED = 4
+
+    ! Host callback told how many pixels (or line-scan points) of the total are
+    ! done; a nonzero result stops the run there.
+    abstract interface
+        function progress_function(user_data, completed, total) result(cancel) bind(C)
+            import :: c_ptr, c_int, c_int64_t
+            type(c_ptr), value :: user_data
+            integer(c_int64_t), value :: completed, total
+            integer(c_int) :: cancel
+        end function progress_function
+    end interface
+
+    ! State of a single simulation run. Each job owns its own context, so
+    ! concurrent runs never share buffers.
+    type :: sim_context
+        logical :: initialized = .false.                 ! Set up by f_init_simulation
+        integer :: electrons_per_pixel = 0               ! Primaries simulated per pixel or line point
+        logical :: fixed_electrons = .false.             ! Set explicitly rather than from the dose
+        integer :: num_recorded = 0                      ! Electrons stored in scatter_positions
+        real(dp), allocatable :: scatter_positions(:,:)  ! (x,y,z,energy,dx,dy,dz,fate) for each electron
+        type(sample_geometry) :: geometry                        ! Surface topography or regions
+        real(dp), allocatable :: crystal_orientation(:,:)       ! Local crystal orientation (rad)
+        real(dp), allocatable :: line_scan_data(:,:)   ! Line scan intensity data
+        real(dp), allocatable :: se_image(:,:)   ! Escaped secondaries per primary
+        real(dp), allocatable :: bse_image(:,:)  ! Backscattered energy fraction per primary
+        real(dp), allocatable :: detector_image(:,:)  ! Detector output per primary
+        real(dp), allocatable :: transmitted_image(:,:)  ! Transmitted electrons per primary
+        real(dp), allocatable :: backscattered_image(:,:)  ! Backscattered electrons per primary
+        type(detector_type) :: detector
+        integer :: image_width = 0, image_height = 0
+
+        ! Scan raster: field width, centre and rotation about the beam axis
+        real(dp) :: field_of_view = 10000.0_dp  ! Horizontal field width in nm
+        real(dp) :: scan_offset(2) = 0.0_dp      ! Raster centre (x, y) in nm
+        real(dp) :: scan_rotation = 0.0_dp       ! Counter-clockwise, radians
+
+        ! Opt-in trajectory recording for the first trajectory_limit electrons
+        integer :: trajectory_limit = 0
+        integer :: num_traced = 0
+        integer :: num_vertices = 0
+        real(dp), allocatable :: trajectory_data(:,:)  ! (electron,x,y,z,energy,event) per vertex
+
+        ! Sample materials, indexed (element, material). Material 1 fills a bulk
+        ! sample; geometry regions refer to materials by index.
+        integer :: num_materials = 0
+        integer, allocatable :: num_elements(:)
+        real(dp), allocatable :: element_z(:,:)           ! Z
+        real(dp), allocatable :: element_a(:,:)           ! A in g/mol
+        real(dp), allocatable :: element_fraction(:,:)    ! Weight fraction
+        real(dp), allocatable :: element_ionization(:,:)  ! J in eV
+        real(dp), allocatable :: density(:)               ! g/cm³
+        real(dp), allocatable :: se_escape_depth(:)       ! SE attenuation length (λ_SE) in nm
+        real(dp), allocatable :: work_function(:)         ! Surface barrier for SEs in eV
+
+        ! Beam parameters
+        real(dp) :: beam_energy           ! keV
+        real(dp) :: beam_current         ! nA
+        real(dp) :: working_distance    ! mm
+        real(dp) :: dwell_time         ! Pixel dwell time in s
+        type(probe_type) :: probe       ! Spot, convergence and focus
+        type(column_type) :: column     ! Source and lens predicting the spot, if set
+        logical :: is_line_scan = .false. ! Mode switch
+
+        ! Random numbers: one stream per pixel (or line-scan point) of the seed
+        integer(int64) :: seed = 0_int64
+        type(rng_state) :: rng
+
+        ! Called after every scan line and line-scan point when set
+        type(c_funptr) :: progress_callback = c_null_funptr
+        type(c_ptr) :: progress_data = c_null_ptr
+    end type sim_context
 
 contains
-    subroutine f_init_simulation(energy, current, resolution, distance) bind(C, name="f_init_simulation")
+    subroutine f_init_simulation(ctx, energy, current, width, height, distance, field_of_view, &
+                                 offset_x, offset_y, rotation, status)
+        ! Sets up the context for a scan. Invalid settings leave it untouched;
+        ! running out of memory leaves it uninitialized.
+        type(sim_context), intent(inout) :: ctx
         real(c_double), value :: energy    ! Beam energy in keV
         real(c_double), value :: current   ! Beam current in nA
-        integer(c_int), value :: resolution ! Image resolution in pixels
+        integer(c_int), value :: width, height  ! Image size in pixels
         real(c_double), value :: distance  ! Working distance in mm
-        integer :: i, j
-        real(dp) :: rand
+        real(c_double), value :: field_of_view  ! Horizontal field width in nm
+        real(c_double), value :: offset_x, offset_y  ! Raster centre in nm
+        real(c_double), value :: rotation  ! Scan rotation in degrees, counter-clockwise
+        integer, intent(out) :: status
+
+        status = STATUS_INVALID_ARGUMENT
+        if (energy <= 0.0_dp .or. current <= 0.0_dp .or. distance <= 0.0_dp) return
+        if (width <= 0 .or. height <= 0 .or. field_of_view <= 0.0_dp) return
 
-        beam_energy = energy
-        beam_current = current
-        working_distance = distance
-        scan_resolution = real(resolution, dp)
-        is_line_scan = .false.
+        ctx%initialized = .false.
+        ctx%beam_energy = energy
+        ctx%beam_current = current
+        ctx%working_distance = distance
+        ctx%field_of_view = field_of_view
+        ctx%scan_offset = [offset_x, offset_y]
+        ctx%scan_rotation = rotation * PI / 180.0_dp
+        ctx%is_line_scan = .false.
         
-        ! Calculate number of electrons based on beam current and dwell time
-        dwell_time = 1.0e-6_dp  ! 1 microsecond default dwell time
-        num_electrons = min(int(beam_current * 6.242e9_dp * dwell_time), MAX_ELECTRONS)
+        ! 1 microsecond dwell and a 5 nm spot focused on the surface until a probe is set
+        ctx%fixed_electrons = .false.
+        call set_dwell_time(ctx, 1.0e-6_dp)
+        ctx%probe = probe_type()
+        call setup_probe(ctx%probe, 5.0_dp, 0.0_dp, 30.0_dp, ctx%working_distance, 0.0_dp)
         
         ! Initialize arrays
-        if (allocated(scatter_positions)) deallocate(scatter_positions)
-        if (allocated(surface_heights)) deallocate(surface_heights)
-        if (allocated(material_properties)) deallocate(material_properties)
-        if (allocated(line_scan_data)) deallocate(line_scan_data)
-        
-        allocate(scatter_positions(4, num_electrons))
-        allocate(surface_heights(resolution, resolution))
-        allocate(material_properties(resolution, resolution, 3))
-        
-        ! Generate realistic surface topography
-        do i = 1, resolution
-            do j = 1, resolution
-                call random_number(rand)
-                surface_heights(i,j) = generate_surface_feature(i, j, resolution)
-            end do
-        end do
+        if (allocated(ctx%crystal_orientation)) deallocate(ctx%crystal_orientation)
+        if (allocated(ctx%line_scan_data)) deallocate(ctx%line_scan_data)
+        allocate(ctx%crystal_orientation(width, height), stat=status)
+        if (status /= 0) then
+            status = STATUS_OUT_OF_MEMORY
+            return
+        end if
+
+        ! Flat bulk surface until a height map or regions are set
+        call clear_height_map(ctx%geometry)
+        call clear_regions(ctx%geometry)
         
         ! Initialize material properties with crystalline structure
-        call initialize_crystal_structure(resolution)
-
-        ! Initialize image buffer
-        image_width = resolution
-        image_height = resolution
-        if (allocated(image_buffer)) deallocate(image_buffer)
-        allocate(image_buffer(image_width, image_height))
-        image_buffer = 0.0_dp
+        ctx%seed = 0_int64
+        call initialize_crystal_structure(ctx)
+
+        ! Pure silicon until a material is set
+        call f_set_material(ctx, 1, [14.0_dp], [28.085_dp], [1.0_dp], [173.0_dp], 2.33_dp, status)
+        call f_set_secondary_emission(ctx, 1, 2.0_dp, 4.05_dp, status)
+
+        ! Initialize SE and BSE image channels
+        ctx%image_width = width
+        ctx%image_height = height
+        if (allocated(ctx%se_image)) deallocate(ctx%se_image)
+        if (allocated(ctx%bse_image)) deallocate(ctx%bse_image)
+        if (allocated(ctx%detector_image)) deallocate(ctx%detector_image)
+        if (allocated(ctx%transmitted_image)) deallocate(ctx%transmitted_image)
+        if (allocated(ctx%backscattered_image)) deallocate(ctx%backscattered_image)
+        allocate(ctx%se_image(ctx%image_width, ctx%image_height), &
+                 ctx%bse_image(ctx%image_width, ctx%image_height), &
+                 ctx%detector_image(ctx%image_width, ctx%image_height), &
+                 ctx%transmitted_image(ctx%image_width, ctx%image_height), &
+                 ctx%backscattered_image(ctx%image_width, ctx%image_height), stat=status)
+        if (status /= 0) then
+            status = STATUS_OUT_OF_MEMORY
+            return
+        end if
+        ctx%se_image = 0.0_dp
+        ctx%bse_image = 0.0_dp
+        ctx%detector_image = 0.0_dp
+        ctx%transmitted_image = 0.0_dp
+        ctx%backscattered_image = 0.0_dp
+
+        ! Everhart-Thornley detector until one is set
+        ctx%detector = detector_type()
+        call setup_detector(ctx%detector, 30.0_dp, 0.0_dp, 25.0_dp, 0.0_dp, 7.5_dp)
+        ctx%initialized = .true.
+        status = STATUS_OK
     end subroutine f_init_simulation
 
-    function generate_surface_feature(x, y, size) result(height)
-        integer, intent(in) :: x, y, size
-        real(dp) :: height, dx, dy, dist
-        real(dp) :: rand
-        integer :: num_crystals, i
-        
-        height = 0.0_dp
-        num_crystals = size/10  ! Approximate number of crystal grains
-        
-        ! Add multiple crystalline features
-        do i = 1, num_crystals
-            dx = x - size/2
-            dy = y - size/2
-            dist = sqrt(dx*dx + dy*dy)
-            
-            call random_number(rand)
-            height = height + CRYSTAL_SIZE * exp(-dist/SURFACE_ROUGHNESS) * &
-                    (1.0_dp + 0.2_dp * (rand - 0.5_dp))
-        end do
-    end function generate_surface_feature
+    subroutine f_set_probe(ctx, spot_size, convergence, aperture_diameter, focus_depth, dwell_time, &
+                           status)
+        ! Probe focused at the working distance: Gaussian spot FWHM (nm), convergence
+        ! semi-angle (rad, derived from the aperture diameter in µm when <= 0), depth
+        ! of the focal plane below the nominal surface (nm) and pixel dwell time (s)
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: spot_size, convergence, aperture_diameter, focus_depth
+        real(dp), intent(in) :: dwell_time
+        integer, intent(out) :: status
+
+        status = STATUS_INVALID_ARGUMENT
+        if (spot_size < 0.0_dp .or. dwell_time <= 0.0_dp) return
+        if (convergence <= 0.0_dp .and. aperture_diameter <= 0.0_dp) return
+
+        status = STATUS_OK
+        call setup_probe(ctx%probe, spot_size, convergence, aperture_diameter, &
+                         ctx%working_distance, focus_depth)
+        call set_dwell_time(ctx, dwell_time)
+    end subroutine f_set_probe
+
+    subroutine f_set_column(ctx, reduced_brightness, energy_spread, cs, cc, target_diameter, status)
+        ! Predicts the probe from the electron source (reduced brightness in
+        ! A/(m² sr V), energy spread in eV) and the objective lens aberrations (mm),
+        ! at the probe's convergence angle. With target_diameter > 0 (nm) the beam
+        ! current becomes the most a probe of that size can carry; otherwise the
+        ! spot size becomes the diameter of a probe carrying the beam current.
+        ! A target the column cannot form leaves the context unchanged.
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: reduced_brightness, energy_spread, cs, cc, target_diameter
+        integer, intent(out) :: status
+        type(column_type) :: column
+        real(dp) :: current
+
+        status = STATUS_INVALID_ARGUMENT
+        if (reduced_brightness <= 0.0_dp .or. energy_spread < 0.0_dp) return
+        if (cs < 0.0_dp .or. cc < 0.0_dp) return
+
+        column = ctx%column
+        column%reduced_brightness = reduced_brightness
+        column%energy_spread = energy_spread
+        column%cs = cs
+        column%cc = cc
+        if (target_diameter > 0.0_dp) then
+            current = probe_current(column, ctx%beam_energy, target_diameter, ctx%probe%convergence)
+            if (current <= 0.0_dp) return
+        end if
+
+        status = STATUS_OK
+        ctx%column = column
+        if (target_diameter > 0.0_dp) then
+            ctx%probe%spot_size = target_diameter
+            ctx%beam_current = current
+            call set_dwell_time(ctx, ctx%dwell_time)
+        else
+            ctx%probe%spot_size = probe_diameter(ctx%column, ctx%beam_energy, ctx%beam_current, &
+                                                 ctx%probe%convergence)
+        end if
+    end subroutine f_set_column
+
+    subroutine f_set_electrons_per_pixel(ctx, count)
+        ! Simulates `count` primaries per pixel. A count of zero or less returns to
+        ! the number the beam current delivers in one dwell.
+        type(sim_context), intent(inout) :: ctx
+        integer(c_int), value :: count
+
+        ctx%fixed_electrons = count > 0
+        if (ctx%fixed_electrons) then
+            ctx%electrons_per_pixel = count
+        else
+            call set_dwell_time(ctx, ctx%dwell_time)
+        end if
+    end subroutine f_set_electrons_per_pixel
+
+    subroutine set_dwell_time(ctx, dwell_time)
+        ! Sets the dwell and, unless fixed, the electrons per pixel: the charge
+        ! the beam current delivers in one dwell
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: dwell_time  ! s
+
+        ctx%dwell_time = dwell_time
+        if (.not. ctx%fixed_electrons) then
+            ctx%electrons_per_pixel = nint(min(ctx%beam_current * ELECTRONS_PER_NA_S * ctx%dwell_time, &
+                                               real(huge(0), dp)))
+            ctx%electrons_per_pixel = max(ctx%electrons_per_pixel, 1)
+        end if
+    end subroutine set_dwell_time
+
+    subroutine reserve_records(ctx, count, status)
+        ! Empties scatter_positions, keeping room for `count` final states or
+        ! MAX_RECORDED, whichever is fewer. Electrons beyond it are still simulated.
+        type(sim_context), intent(inout) :: ctx
+        integer(int64), intent(in) :: count
+        integer, intent(out) :: status
+        integer :: capacity
+
+        status = STATUS_OK
+        ctx%num_recorded = 0
+        capacity = int(min(count, int(MAX_RECORDED, int64)))
+        if (allocated(ctx%scatter_positions)) then
+            if (size(ctx%scatter_positions, 2) /= capacity) deallocate(ctx%scatter_positions)
+        end if
+        if (allocated(ctx%scatter_positions)) return
 
-    subroutine initialize_crystal_structure(size)
-        integer, intent(in) :: size
+        allocate(ctx%scatter_positions(SCATTER_ROWS, capacity), stat=status)
+        if (status /= 0) status = STATUS_OUT_OF_MEMORY
+    end subroutine reserve_records
+
+    subroutine f_set_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                              mean_ionizations, density, status)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: num_elements
+        real(dp), intent(in) :: atomic_numbers(num_elements)    ! Z
+        real(dp), intent(in) :: atomic_weights(num_elements)    ! g/mol
+        real(dp), intent(in) :: weight_fractions(num_elements)
+        real(dp), intent(in) :: mean_ionizations(num_elements)  ! eV
+        real(dp), intent(in) :: density                          ! g/cm³
+        integer, intent(out) :: status
+
+        ! Sets the bulk material and drops any others added before
+        status = STATUS_INVALID_ARGUMENT
+        if (.not. material_valid(num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                                 mean_ionizations, density)) return
+        status = STATUS_OK
+        ctx%num_materials = 0
+        call store_material(ctx, 1, num_elements, atomic_numbers, atomic_weights, &
+                            weight_fractions, mean_ionizations, density)
+    end subroutine f_set_material
+
+    subroutine f_add_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                              mean_ionizations, density, material, status)
+        ! Appends a material for geometry regions and returns its index
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: num_elements
+        real(dp), intent(in) :: atomic_numbers(num_elements)
+        real(dp), intent(in) :: atomic_weights(num_elements)
+        real(dp), intent(in) :: weight_fractions(num_elements)
+        real(dp), intent(in) :: mean_ionizations(num_elements)
+        real(dp), intent(in) :: density
+        integer, intent(out) :: material
+        integer, intent(out) :: status
+
+        material = 0
+        status = STATUS_INVALID_ARGUMENT
+        if (.not. material_valid(num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                                 mean_ionizations, density)) return
+        status = STATUS_OK
+        material = ctx%num_materials + 1
+        call store_material(ctx, material, num_elements, atomic_numbers, atomic_weights, &
+                            weight_fractions, mean_ionizations, density)
+    end subroutine f_add_material
+
+    pure function material_valid(num_elements, atomic_numbers, atomic_weights, weight_fractions, &
+                                 mean_ionizations, density) result(valid)
+        ! Whether the elements describe a material the transport loop can handle
+        integer, intent(in) :: num_elements
+        real(dp), intent(in) :: atomic_numbers(num_elements)
+        real(dp), intent(in) :: atomic_weights(num_elements)
+        real(dp), intent(in) :: weight_fractions(num_elements)
+        real(dp), intent(in) :: mean_ionizations(num_elements)
+        real(dp), intent(in) :: density
+        logical :: valid
+
+        valid = num_elements >= 1 .and. density > 0.0_dp
+        if (.not. valid) return
+        valid = all(atomic_numbers >= 1.0_dp) .and. all(atomic_weights > 0.0_dp) .and. &
+                all(mean_ionizations > 0.0_dp) .and. all(weight_fractions >= 0.0_dp) .and. &
+                sum(weight_fractions) > 0.0_dp
+    end function material_valid
+
+    subroutine store_material(ctx, material, num_elements, atomic_numbers, atomic_weights, &
+                              weight_fractions, mean_ionizations, density)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: material, num_elements
+        real(dp), intent(in) :: atomic_numbers(num_elements)
+        real(dp), intent(in) :: atomic_weights(num_elements)
+        real(dp), intent(in) :: weight_fractions(num_elements)
+        real(dp), intent(in) :: mean_ionizations(num_elements)
+        real(dp), intent(in) :: density
+
+        call reserve_materials(ctx, num_elements, material)
+        ctx%num_materials = max(ctx%num_materials, material)
+        ctx%num_elements(material) = num_elements
+        ctx%element_z(1:num_elements, material) = atomic_numbers
+        ctx%element_a(1:num_elements, material) = atomic_weights
+        ctx%element_ionization(1:num_elements, material) = mean_ionizations
+        ! Normalize so the fractions always sum to one
+        ctx%element_fraction(1:num_elements, material) = weight_fractions / sum(weight_fractions)
+        ctx%density(material) = density
+        ctx%se_escape_depth(material) = 2.0_dp
+        ctx%work_function(material) = 4.05_dp
+    end subroutine store_material
+
+    subroutine reserve_materials(ctx, num_elements, num_materials)
+        ! Grows the material table to hold at least the given sizes, keeping its contents
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: num_elements, num_materials
+        integer :: rows, cols, old_rows, old_cols
+        integer, allocatable :: counts(:)
+        real(dp), allocatable :: table(:,:), values(:)
+
+        old_rows = 0
+        old_cols = 0
+        if (allocated(ctx%element_z)) then
+            old_rows = size(ctx%element_z, 1)
+            old_cols = size(ctx%element_z, 2)
+        end if
+        if (num_elements <= old_rows .and. num_materials <= old_cols) return
+        rows = max(num_elements, old_rows)
+        cols = max(num_materials, 2 * old_cols)
+
+        allocate(counts(cols))
+        counts = 0
+        if (old_cols > 0) counts(1:old_cols) = ctx%num_elements
+        call move_alloc(counts, ctx%num_elements)
+
+        call grow_table(ctx%element_z)
+        call grow_table(ctx%element_a)
+        call grow_table(ctx%element_fraction)
+        call grow_table(ctx%element_ionization)
+        call grow_values(ctx%density)
+        call grow_values(ctx%se_escape_depth)
+        call grow_values(ctx%work_function)
+
+    contains
+
+        subroutine grow_table(array)
+            real(dp), allocatable, intent(inout) :: array(:,:)
+
+            allocate(table(rows, cols))
+            table = 0.0_dp
+            if (old_cols > 0) table(1:old_rows, 1:old_cols) = array
+            call move_alloc(table, array)
+        end subroutine grow_table
+
+        subroutine grow_values(array)
+            real(dp), allocatable, intent(inout) :: array(:)
+
+            allocate(values(cols))
+            values = 0.0_dp
+            if (old_cols > 0) values(1:old_cols) = array
+            call move_alloc(values, array)
+        end subroutine grow_values
+    end subroutine reserve_materials
+
+    subroutine f_set_secondary_emission(ctx, material, escape_depth, work_function, status)
+        ! Material surface properties governing which secondaries escape
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: material
+        real(dp), intent(in) :: escape_depth   ! λ_SE in nm
+        real(dp), intent(in) :: work_function  ! eV
+        integer, intent(out) :: status
+
+        status = STATUS_INVALID_ARGUMENT
+        if (material < 1 .or. material > ctx%num_materials) return
+        if (escape_depth <= 0.0_dp .or. work_function <= 0.0_dp) return
+        status = STATUS_OK
+        ctx%se_escape_depth(material) = escape_depth
+        ctx%work_function(material) = work_function
+    end subroutine f_set_secondary_emission
+
+    subroutine f_set_detector(ctx, elevation, azimuth, distance, inner_radius, outer_radius, &
+                              se_efficiency, bse_efficiency, gain, dark_current, noise_level, &
+                              time_constant, status)
+        ! Detector geometry (degrees, mm) and response used to form the detector image
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: elevation, azimuth, distance, inner_radius, outer_radius
+        real(dp), intent(in) :: se_efficiency, bse_efficiency, gain, dark_current, noise_level
+        real(dp), intent(in) :: time_constant  ! s
+        integer, intent(out) :: status
+
+        status = STATUS_INVALID_ARGUMENT
+        if (distance <= 0.0_dp .or. inner_radius < 0.0_dp .or. outer_radius <= inner_radius) return
+        if (se_efficiency < 0.0_dp .or. se_efficiency > 1.0_dp) return
+        if (bse_efficiency < 0.0_dp .or. bse_efficiency > 1.0_dp) return
+        if (gain <= 0.0_dp .or. time_constant <= 0.0_dp) return
+        if (dark_current < 0.0_dp .or. noise_level < 0.0_dp) return
+
+        status = STATUS_OK
+        call setup_detector(ctx%detector, elevation, azimuth, distance, inner_radius, outer_radius)
+        ctx%detector%se_efficiency = se_efficiency
+        ctx%detector%bse_efficiency = bse_efficiency
+        ctx%detector%gain = gain
+        ctx%detector%dark_current = dark_current
+        ctx%detector%noise_level = noise_level
+        ctx%detector%time_constant = time_constant
+    end subroutine f_set_detector
+
+    subroutine f_set_height_map(ctx, nx, ny, heights, spacing, status)
+        ! Sample topography as heights (nm) on an nx × ny grid with `spacing` nm
+        ! between points, centred on the beam axis. An empty grid restores a flat surface.
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: nx, ny
+        real(dp), intent(in) :: heights(nx, ny)
+        real(dp), intent(in) :: spacing
+        integer, intent(out) :: status
+
+        status = STATUS_OK
+        if (nx <= 0 .or. ny <= 0) then
+            call clear_height_map(ctx%geometry)
+        else if (spacing <= 0.0_dp) then
+            status = STATUS_INVALID_ARGUMENT
+        else
+            call set_height_map(ctx%geometry, heights, spacing)
+        end if
+    end subroutine f_set_height_map
+
+    subroutine f_set_geometry(ctx, num_shapes, shape_kind, shape_params, program_length, program, &
+                              num_regions, region_length, region_material, status)
+        ! Volumetric sample built from primitive shapes combined by postfix
+        ! programs, one per region, each region filled with a material index.
+        ! Without regions the sample is bulk material 1 below the surface.
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: num_shapes, program_length, num_regions
+        integer, intent(in) :: shape_kind(num_shapes)
+        real(dp), intent(in) :: shape_params(SHAPE_PARAMS, num_shapes)
+        integer, intent(in) :: program(program_length)
+        integer, intent(in) :: region_length(num_regions)
+        integer, intent(in) :: region_material(num_regions)
+        integer, intent(out) :: status
+
+        status = STATUS_OK
+        if (num_regions <= 0) then
+            call clear_regions(ctx%geometry)
+        else if (.not. regions_valid(shape_kind, program, region_length) .or. &
+                 any(region_material < 1 .or. region_material > ctx%num_materials)) then
+            status = STATUS_INVALID_ARGUMENT
+        else
+            call set_regions(ctx%geometry, shape_kind, shape_params, program, region_length, &
+                             region_material)
+        end if
+    end subroutine f_set_geometry
+
+    subroutine f_set_seed(ctx, seed)
+        ! Selects the random sequence. Pixel k of every run draws from stream k of
+        ! the seed, so equal seeds and settings give bit-identical results.
+        type(sim_context), intent(inout) :: ctx
+        integer(int64), intent(in) :: seed
+
+        ctx%seed = seed
+        call initialize_crystal_structure(ctx)
+    end subroutine f_set_seed
+
+    subroutine f_set_progress_callback(ctx, callback, user_data)
+        ! Registers the progress callback of later runs; a null callback removes it.
+        type(sim_context), intent(inout) :: ctx
+        type(c_funptr), intent(in) :: callback
+        type(c_ptr), intent(in) :: user_data
+
+        ctx%progress_callback = callback
+        ctx%progress_data = user_data
+    end subroutine f_set_progress_callback
+
+    logical function run_cancelled(ctx, completed, total)
+        ! Reports progress to the host and returns whether it asked to stop.
+        type(sim_context), intent(in) :: ctx
+        integer(int64), intent(in) :: completed, total
+        procedure(progress_function), pointer :: report
+
+        run_cancelled = .false.
+        if (.not. c_associated(ctx%progress_callback)) return
+        call c_f_procpointer(ctx%progress_callback, report)
+        run_cancelled = report(ctx%progress_data, completed, total) /= 0
+    end function run_cancelled
+
+    subroutine initialize_crystal_structure(ctx)
+        type(sim_context), intent(inout) :: ctx
         integer :: i, j
         real(dp) :: rand, orientation
         
-        do i = 1, size
-            do j = 1, size
-                ! Set iron and oxygen concentrations
-                material_properties(i,j,1) = 0.4_dp  ! Fe concentration
-                material_properties(i,j,2) = 0.6_dp  ! O concentration
-                
+        call rng_seed(ctx%rng, ctx%seed, 0_int64)
+        do i = 1, size(ctx%crystal_orientation, 1)
+            do j = 1, size(ctx%crystal_orientation, 2)
                 ! Set crystal orientation (0 to 2π)
-                call random_number(rand)
+                call random_uniform(ctx%rng, rand)
                 orientation = 2.0_dp * PI * rand
-                material_properties(i,j,3) = orientation
+                ctx%crystal_orientation(i,j) = orientation
             end do
         end do
     end subroutine initialize_crystal_structure
 
-    subroutine f_run_simulation() bind(C, name="f_run_simulation")
-        integer :: i, j, num_collisions, pixel_x, pixel_y
-        real(dp) :: energy, path_length, mfp
-        real(dp) :: x, y, z, dx, dy, dz
-        real(dp) :: theta, phi, energy_loss
-        real(dp) :: se_yield, pixel_size
-        real(dp) :: scan_x, scan_y
-        logical :: generate_se
-
-        ! Clear image buffer
-        image_buffer = 0.0_dp
+    subroutine f_run_simulation(ctx, status)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(out) :: status
+        integer :: i, j, k, se_count, fate
+        real(dp) :: energy, x, y, z, dx, dy, dz
+        real(dp) :: pixel_size, collected
+        real(dp) :: scan_x, scan_y, u, v
+
+        ! Clear image channels
+        ctx%se_image = 0.0_dp
+        ctx%bse_image = 0.0_dp
+        ctx%detector_image = 0.0_dp
+        ctx%transmitted_image = 0.0_dp
+        ctx%backscattered_image = 0.0_dp
+        call reserve_records(ctx, int(ctx%electrons_per_pixel, int64) * ctx%image_width * ctx%image_height, &
+                             status)
+        if (status /= STATUS_OK) return
+        call reset_trajectories(ctx)
         
-        ! Calculate pixel size based on a typical 10μm field of view
-        pixel_size = 10000.0_dp / image_width  ! nm per pixel
+        ! Square pixels spanning the field width
+        pixel_size = ctx%field_of_view / ctx%image_width  ! nm per pixel
         
         ! Scan over the surface
-        do j = 1, image_height
-            do i = 1, image_width
-                ! Calculate beam position
-                scan_x = (i - image_width/2) * pixel_size
-                scan_y = (j - image_height/2) * pixel_size
+        do j = 1, ctx%image_height
+            do i = 1, ctx%image_width
+                ! Beam position at the pixel centre, in the rotated raster frame
+                u = (i - 0.5_dp - 0.5_dp * ctx%image_width) * pixel_size
+                v = (j - 0.5_dp - 0.5_dp * ctx%image_height) * pixel_size
+                scan_x = ctx%scan_offset(1) + u * cos(ctx%scan_rotation) - v * sin(ctx%scan_rotation)
+                scan_y = ctx%scan_offset(2) + u * sin(ctx%scan_rotation) + v * cos(ctx%scan_rotation)
+                call rng_seed(ctx%rng, ctx%seed, int(j - 1, int64) * ctx%image_width + i)
                 
-                ! Run multiple electrons per pixel
-                do k = 1, num_electrons/image_width/image_height
-                    ! Initialize electron at surface with beam position
-                    energy = beam_energy
-                    x = scan_x
-                    y = scan_y
-                    z = 0.0_dp
-                    dx = 0.0_dp
-                    dy = 0.0_dp
-                    dz = 1.0_dp  ! Initial direction along z-axis
-                    
-                    ! Add initial beam spread
-                    call beam_spread(dx, dy, dz)
+                ! Run multiple electrons per pixel. Detector signals are assigned
+                ! to the pixel under the beam, wherever the electrons emerge.
+                collected = 0.0_dp
+                do k = 1, ctx%electrons_per_pixel
+                    call track_electron(ctx, scan_x, scan_y, x, y, z, energy, &
+                                        dx, dy, dz, fate, se_count)
+                    call record_electron(ctx, x, y, z, energy, dx, dy, dz, fate)
                     
-                    ! Track electron until it's absorbed or escapes
-                    do while (z >= 0.0_dp .and. energy > 0.1_dp)
-                        ! Calculate mean free path (including both elastic and inelastic)
-                        mfp = calculate_mfp(energy)
-                        
-                        ! Sample path length (exponential distribution)
-                        call random_number(path_length)
-                        path_length = -mfp * log(path_length)
-                        
-                        ! Move electron
-                        x = x + path_length * dx
-                        y = y + path_length * dy
-                        z = z + path_length * dz
-                        
-                        ! Determine if this collision generates SE
-                        call random_number(se_yield)
-                        generate_se = se_yield < calculate_se_yield(energy)
-                        
-                        ! Calculate scattering angles using screened Rutherford
-                        call calculate_scatter_angles(energy, theta, phi)
-                        
-                        ! Update direction
-                        call update_direction(dx, dy, dz, theta, phi)
-                        
-                        ! Calculate energy loss (Bethe formula with straggling)
-                        energy_loss = calculate_energy_loss(energy, path_length)
-                        energy = energy - energy_loss
-                        
-                        ! If electron escapes surface (backscattered)
-                        if (z < 0.0_dp) then
-                            ! Add to image intensity with distance-based weighting
-                            pixel_x = nint((x + (image_width/2) * pixel_size) / pixel_size)
-                            pixel_y = nint((y + (image_height/2) * pixel_size) / pixel_size)
-                            
-                            if (pixel_x >= 1 .and. pixel_x <= image_width .and. &
-                                pixel_y >= 1 .and. pixel_y <= image_height) then
-                                image_buffer(pixel_x, pixel_y) = &
-                                    image_buffer(pixel_x, pixel_y) + energy/beam_energy
-                            end if
-                        end if
-                    end do
+                    ! Escaping secondaries feed the SE channel
+                    ctx%se_image(i, j) = ctx%se_image(i, j) + real(se_count, dp)
+                    collected = collected + se_count * &
+                        generate_signal(ctx%detector, SIGNAL_SE, [dx, dy, dz], 0.0_dp)
+
+                    ! Backscattered primaries feed the BSE channel, weighted by energy,
+                    ! and are counted for the backscatter coefficient
+                    if (fate == FATE_BACKSCATTERED) then
+                        ctx%bse_image(i, j) = ctx%bse_image(i, j) + energy/ctx%beam_energy
+                        ctx%backscattered_image(i, j) = ctx%backscattered_image(i, j) + 1.0_dp
+                        collected = collected + generate_signal(ctx%detector, SIGNAL_BSE, &
+                                                                [dx, dy, dz], energy/ctx%beam_energy)
+                    end if
+
+                    ! Electrons leaving through the bottom of a film reach a
+                    ! transmission detector below the sample (STEM-in-SEM)
+                    if (fate == FATE_TRANSMITTED) then
+                        ctx%transmitted_image(i, j) = ctx%transmitted_image(i, j) + 1.0_dp
+                    end if
                 end do
+
+                ! Pass what the detector collected through its amplifier chain
+                ctx%detector_image(i, j) = apply_detector_response(ctx%detector, ctx%rng, collected, &
+                                                                   ctx%dwell_time)
             end do
+
+            ! Report the finished line; a cancelled run keeps the lines done so far
+            if (run_cancelled(ctx, int(j, int64) * ctx%image_width, int(ctx%image_width, int64) * ctx%image_height)) exit
         end do
         
-        ! Normalize image
-        image_buffer = image_buffer / maxval(image_buffer)
+        ! Express all channels as yields per primary electron
+        ctx%se_image = ctx%se_image / real(ctx%electrons_per_pixel, dp)
+        ctx%bse_image = ctx%bse_image / real(ctx%electrons_per_pixel, dp)
+        ctx%detector_image = ctx%detector_image / real(ctx%electrons_per_pixel, dp)
+        ctx%transmitted_image = ctx%transmitted_image / real(ctx%electrons_per_pixel, dp)
+        ctx%backscattered_image = ctx%backscattered_image / real(ctx%electrons_per_pixel, dp)
     end subroutine f_run_simulation
     
     ! Helper functions
+
+    subroutine track_electron(ctx, x0, y0, x, y, z, energy, dx, dy, dz, fate, se_count)
+        ! Follows one primary electron aimed at (x0, y0) until it leaves the sample
+        ! or slows below the cutoff energy. Returns its final position, energy,
+        ! direction and fate, and the number of secondaries released close enough
+        ! to the surface to escape. The electron follows a ray of the focused probe
+        ! and enters where it first meets the sample; crossing into another region switches material,
+        ! and an electron leaving one region may fly through vacuum into another.
+        ! The first trajectory_limit electrons of a run also have their vertices recorded.
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: x0, y0
+        real(dp), intent(out) :: x, y, z, energy
+        real(dp), intent(out) :: dx, dy, dz
+        integer, intent(out) :: fate
+        integer, intent(out) :: se_count
+        integer :: element, trace_id, material, next_material
+        real(dp) :: path_length, mfp, rand, boundary, cos_normal
+        real(dp) :: theta, phi, start(3), loss, position(3), direction(3), focus(3)
+        logical :: crossed
+
+        energy = ctx%beam_energy
+        se_count = 0
+        fate = FATE_ABSORBED
+
+        ! A ray of the focused probe, followed down to the sample
+        call sample_probe_ray(ctx%probe, ctx%rng, x0, y0, focus, direction)
+        call beam_entry(ctx%geometry, focus, direction, position, material)
+
+        ! Decide whether this electron's path is recorded
+        trace_id = 0
+        if (ctx%num_traced < ctx%trajectory_limit) then
+            ctx%num_traced = ctx%num_traced + 1
+            trace_id = ctx%num_traced
+        end if
+        call add_vertex(ctx, trace_id, position, energy, EVENT_ENTRY)
+
+        if (material == 0) then
+            ! The beam misses every region
+            fate = FATE_TRANSMITTED
+            call add_vertex(ctx, trace_id, position, energy, EVENT_TRANSMITTED)
+        end if
+
+        ! Track electron until it's absorbed or escapes
+        do while (material /= 0)
+            ! Elastic mean free path and the element hit at the next collision
+            call sample_collision(ctx, material, energy, mfp, element)
+            
+            ! Sample path length (exponential distribution)
+            call random_uniform(ctx%rng, rand)
+            path_length = -mfp * log(1.0_dp - rand)
+
+            ! Stop at the boundary if this step leaves the current material
+            call next_boundary(ctx%geometry, position, direction, path_length, material, &
+                               boundary, next_material, cos_normal)
+            crossed = boundary <= path_length
+            if (crossed) path_length = boundary
+            
+            ! Move electron, losing energy continuously along the path (Bethe formula)
+            start = position
+            position = position + path_length * direction
+            loss = min(calculate_energy_loss(ctx, material, energy, path_length), energy)
+            energy = energy - loss
+
+            ! Slow secondaries excited by the energy deposited along this step
+            se_count = se_count + emit_secondaries(ctx, material, start, position, loss)
+
+            if (crossed .and. next_material == 0) then
+                ! Left through a surface: it escapes unless another region lies ahead
+                call next_boundary(ctx%geometry, position, direction, huge(1.0_dp), 0, &
+                                   boundary, next_material, cos_normal)
+                if (next_material == 0) then
+                    ! A bulk sample can only be left through its top surface
+                    if (direction(3) < 0.0_dp .or. ctx%geometry%num_regions == 0) then
+                        fate = FATE_BACKSCATTERED
+                        call add_vertex(ctx, trace_id, position, energy, EVENT_BACKSCATTERED)
+                    else
+                        fate = FATE_TRANSMITTED
+                        call add_vertex(ctx, trace_id, position, energy, EVENT_TRANSMITTED)
+                    end if
+                    exit
+                end if
+                call add_vertex(ctx, trace_id, position, energy, EVENT_BOUNDARY)
+                position = position + boundary * direction
+            end if
+            if (energy <= CUTOFF_ENERGY) then
+                call add_vertex(ctx, trace_id, position, energy, EVENT_ABSORBED)
+                exit
+            end if
+            if (crossed) then
+                ! Carry on in a straight line through the new material
+                material = next_material
+                call add_vertex(ctx, trace_id, position, energy, EVENT_BOUNDARY)
+                cycle
+            end if
+            call add_vertex(ctx, trace_id, position, energy, EVENT_ELASTIC)
+            
+            ! Calculate scattering angles using screened Rutherford
+            call calculate_scatter_angles(ctx%rng, ctx%element_z(element, material), energy, theta, phi)
+            
+            ! Update direction
+            call update_direction(direction(1), direction(2), direction(3), theta, phi)
+        end do
+
+        x = position(1)
+        y = position(2)
+        z = position(3)
+        dx = direction(1)
+        dy = direction(2)
+        dz = direction(3)
+    end subroutine track_electron
+
+    function emit_secondaries(ctx, material, start, finish, energy_loss) result(escaped)
+        ! Releases slow secondaries along a straight step from start to finish (nm)
+        ! through `material` that deposits energy_loss (keV) uniformly, and returns
+        ! how many escape. Each secondary leaves in a random direction and escapes
+        ! through the real surface, so those released near edges and slopes escape
+        ! more easily. Secondaries may cross into other regions on the way out,
+        ! attenuated by each material's escape depth and leaving over the barrier of
+        ! the last one. For bulk samples only the part of the step within
+        ! SE_SAMPLING_DEPTH escape depths of the surface is sampled, as secondaries
+        ! from deeper down are all reabsorbed.
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: material
+        real(dp), intent(in) :: start(3), finish(3), energy_loss
+        integer :: escaped
+        integer :: i, k, generated, current, next_material
+        real(dp) :: max_path, z_top, z_limit, t_lo, t_hi, t_a, t_b, rand
+        real(dp) :: position(3), direction(3), path, depth, cos_normal, se_energy
+
+        escaped = 0
+        if (energy_loss <= 0.0_dp) return
+
+        max_path = SE_SAMPLING_DEPTH * ctx%se_escape_depth(material)
+        t_lo = 0.0_dp
+        t_hi = 1.0_dp
+        if (ctx%geometry%num_regions == 0) then
+            ! Points deeper than z_limit are out of reach of even the lowest surface point
+            z_top = min(start(3), finish(3))
+            z_limit = min(max(start(3), finish(3)), max_path - ctx%geometry%height_min)
+            if (z_top >= z_limit) return
+
+            ! Part of the step (and its energy loss) lying in the sampled layer
+            if (finish(3) /= start(3)) then
+                t_a = (z_top - start(3)) / (finish(3) - start(3))
+                t_b = (z_limit - start(3)) / (finish(3) - start(3))
+                t_lo = min(t_a, t_b)
+                t_hi = max(t_a, t_b)
+            end if
+        end if
+
+        generated = generate_secondaries(ctx%rng, (t_hi - t_lo) * energy_loss, SE_EXCITATION_ENERGY)
+        do i = 1, generated
+            call random_uniform(ctx%rng, rand)
+            position = start + (t_lo + rand * (t_hi - t_lo)) * (finish - start)
+            direction = isotropic_direction(ctx%rng)
+            se_energy = sample_se_energy(ctx%rng, ctx%work_function(material))
+
+            ! Follow the secondary out, measuring its path in escape depths
+            current = material
+            depth = 0.0_dp
+            do k = 1, MAX_SE_CROSSINGS
+                max_path = (SE_SAMPLING_DEPTH - depth) * ctx%se_escape_depth(current)
+                call next_boundary(ctx%geometry, position, direction, max_path, current, &
+                                   path, next_material, cos_normal)
+                if (path > max_path) exit
+                depth = depth + path / ctx%se_escape_depth(current)
+                if (next_material == 0) then
+                    if (se_escapes(ctx%rng, se_energy, depth, cos_normal, 1.0_dp, &
+                                   ctx%work_function(current))) then
+                        escaped = escaped + 1
+                    end if
+                    exit
+                end if
+                position = position + path * direction
+                current = next_material
+            end do
+        end do
+    end function emit_secondaries
+
+    function isotropic_direction(rng) result(direction)
+        ! Unit vector uniformly distributed over the sphere
+        type(rng_state), intent(inout) :: rng
+        real(dp) :: direction(3)
+        real(dp) :: cos_theta, sin_theta, phi, rand
+
+        call random_uniform(rng, rand)
+        cos_theta = 2.0_dp * rand - 1.0_dp
+        sin_theta = sqrt(max(1.0_dp - cos_theta**2, 0.0_dp))
+        call random_uniform(rng, rand)
+        phi = 2.0_dp * PI * rand
+        direction = [sin_theta * cos(phi), sin_theta * sin(phi), cos_theta]
+    end function isotropic_direction
+
+    subroutine add_vertex(ctx, trace_id, position, energy, event)
+        ! Appends a trajectory vertex for a traced electron (trace_id > 0)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: trace_id
+        real(dp), intent(in) :: position(3), energy
+        integer, intent(in) :: event
+        real(dp), allocatable :: grown(:,:)
+
+        if (trace_id <= 0) return
+
+        ! Grow the vertex buffer geometrically
+        if (.not. allocated(ctx%trajectory_data)) then
+            allocate(ctx%trajectory_data(TRAJECTORY_ROWS, 1024))
+        else if (ctx%num_vertices >= size(ctx%trajectory_data, 2)) then
+            allocate(grown(TRAJECTORY_ROWS, 2 * size(ctx%trajectory_data, 2)))
+            grown(:, 1:ctx%num_vertices) = ctx%trajectory_data(:, 1:ctx%num_vertices)
+            call move_alloc(grown, ctx%trajectory_data)
+        end if
+
+        ctx%num_vertices = ctx%num_vertices + 1
+        ctx%trajectory_data(:, ctx%num_vertices) = &
+            [real(trace_id, dp), position, energy, real(event, dp)]
+    end subroutine add_vertex
+
+    subroutine f_set_trajectory_recording(ctx, max_electrons, status)
+        ! Record full trajectories for the first max_electrons electrons of each run
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: max_electrons
+        integer, intent(out) :: status
+
+        status = STATUS_INVALID_ARGUMENT
+        if (max_electrons < 0) return
+        status = STATUS_OK
+        ctx%trajectory_limit = max_electrons
+    end subroutine f_set_trajectory_recording
+
+    subroutine reset_trajectories(ctx)
+        type(sim_context), intent(inout) :: ctx
+
+        ctx%num_traced = 0
+        ctx%num_vertices = 0
+    end subroutine reset_trajectories
+
+    subroutine record_electron(ctx, x, y, z, energy, dx, dy, dz, fate)
+        ! Appends one electron's final state to scatter_positions
+        type(sim_context), intent(inout) :: ctx
+        real(dp), intent(in) :: x, y, z, energy, dx, dy, dz
+        integer, intent(in) :: fate
+
+        if (ctx%num_recorded >= size(ctx%scatter_positions, 2)) return
+        ctx%num_recorded = ctx%num_recorded + 1
+        ctx%scatter_positions(:, ctx%num_recorded) = &
+            [x, y, z, energy, dx, dy, dz, real(fate, dp)]
+    end subroutine record_electron
     
-    subroutine beam_spread(dx, dy, dz)
-        real(dp), intent(inout) :: dx, dy, dz
-        real(dp) :: angle_x, angle_y, norm
-        
-        call random_number(angle_x)
-        call random_number(angle_y)
-        
-        ! Gaussian beam profile
-        angle_x = sqrt(-2.0_dp * log(angle_x)) * cos(2.0_dp * PI * angle_y) * spot_size
-        angle_y = sqrt(-2.0_dp * log(angle_x)) * sin(2.0_dp * PI * angle_y) * spot_size
-        
-        dx = sin(angle_x)
-        dy = sin(angle_y)
-        dz = sqrt(1.0_dp - dx*dx - dy*dy)  ! Normalize
-        
-        ! Ensure normalization
-        norm = sqrt(dx*dx + dy*dy + dz*dz)
-        dx = dx/norm
-        dy = dy/norm
-        dz = dz/norm
-    end subroutine beam_spread
-    
-    function calculate_mfp(energy) result(mfp)
-        real(dp), intent(in) :: energy
-        real(dp) :: mfp, cross_section, elastic_cs, inelastic_cs
-        
-        ! Elastic cross-section (screened Rutherford)
-        elastic_cs = 5.21e-21_dp * (FE_ATOMIC_NUMBER**2) / (energy**2) * &
-                    (1.0_dp + energy/REST_MASS_ENERGY)**(-2)  ! Relativistic correction
-        
-        ! Inelastic cross-section (Møller)
-        inelastic_cs = 2.0_dp * PI * (BOHR_RADIUS**2) * FINE_STRUCTURE * &
-                      (FE_ATOMIC_NUMBER/energy) * (log(energy/MEAN_IONIZATION_POTENTIAL) + 0.198_dp)
-        
-        ! Total cross-section
-        cross_section = elastic_cs + inelastic_cs
-        
-        ! Mean free path
-        mfp = 1.0_dp / (DENSITY * AVOGADRO * cross_section / (FE_ATOMIC_NUMBER*1.0_dp + O_ATOMIC_NUMBER*1.0_dp))
-        mfp = mfp * 1.0e9_dp  ! Convert to nm
-    end function calculate_mfp
+    function screening_parameter(atomic_number, energy) result(alpha)
+        real(dp), intent(in) :: atomic_number
+        real(dp), intent(in) :: energy  ! keV
+        real(dp) :: alpha
+
+        ! Screening parameter of the screened Rutherford cross-section
+        alpha = 3.4e-3_dp * atomic_number**0.67_dp / energy
+    end function screening_parameter
+
+    function elastic_cross_section(atomic_number, energy) result(cross_section)
+        real(dp), intent(in) :: atomic_number
+        real(dp), intent(in) :: energy  ! keV
+        real(dp) :: cross_section, alpha
+
+        ! Screened Rutherford cross-section with relativistic correction, cm²
+        alpha = screening_parameter(atomic_number, energy)
+        cross_section = 5.21e-21_dp * (atomic_number**2) / (energy**2) * &
+                        (4.0_dp * PI / (alpha * (1.0_dp + alpha))) * &
+                        ((energy + REST_MASS_ENERGY) / (energy + 2.0_dp * REST_MASS_ENERGY))**2
+    end function elastic_cross_section
+
+    subroutine sample_collision(ctx, material, energy, mfp, element)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: material
+        real(dp), intent(in) :: energy  ! keV
+        real(dp), intent(out) :: mfp    ! nm
+        integer, intent(out) :: element
+        real(dp) :: partial(ctx%num_elements(material)), total, rand
+        integer :: i, n
+
+        ! Inverse mean free path of each element: n_i * sigma_i in 1/cm
+        n = ctx%num_elements(material)
+        do i = 1, n
+            partial(i) = ctx%density(material) * AVOGADRO * ctx%element_fraction(i, material) / &
+                         ctx%element_a(i, material) * &
+                         elastic_cross_section(ctx%element_z(i, material), energy)
+        end do
+        total = sum(partial)
+
+        ! Energy loss between collisions is handled by the continuous slowing-down
+        ! approximation, so only elastic events are sampled.
+        mfp = 1.0e7_dp / total  ! Convert cm to nm
+
+        ! Pick the scattering element in proportion to its share of the cross-section
+        call random_uniform(ctx%rng, rand)
+        rand = rand * total
+        element = n
+        do i = 1, n
+            rand = rand - partial(i)
+            if (rand <= 0.0_dp) then
+                element = i
+                exit
+            end if
+        end do
+    end subroutine sample_collision
     
-    subroutine calculate_scatter_angles(energy, theta, phi)
+    subroutine calculate_scatter_angles(rng, atomic_number, energy, theta, phi)
+        type(rng_state), intent(inout) :: rng
+        real(dp), intent(in) :: atomic_number
         real(dp), intent(in) :: energy
         real(dp), intent(out) :: theta, phi
-        real(dp) :: rand, eta, screening_parameter
+        real(dp) :: rand, alpha
         
-        ! TF screening parameter
-        screening_parameter = 0.885_dp * BOHR_RADIUS * FE_ATOMIC_NUMBER**(-1.0_dp/3.0_dp)
-        eta = 2.0_dp * energy * screening_parameter / (FINE_STRUCTURE * BOHR_RADIUS)
+        alpha = screening_parameter(atomic_number, energy)
         
         ! Sample theta from screened Rutherford
-        call random_number(rand)
-        theta = acos(1.0_dp - 2.0_dp * rand/(1.0_dp + eta * (1.0_dp - rand)))
+        call random_uniform(rng, rand)
+        theta = acos(1.0_dp - 2.0_dp * alpha * rand / (1.0_dp + alpha - rand))
         
         ! Uniform phi
-        call random_number(rand)
+        call random_uniform(rng, rand)
         phi = 2.0_dp * PI * rand
     end subroutine calculate_scatter_angles
+
+    subroutine update_direction(dx, dy, dz, theta, phi)
+        real(dp), intent(inout) :: dx, dy, dz
+        real(dp), intent(in) :: theta, phi
+        real(dp) :: sin_t, cos_t, sin_p, cos_p, perp, ux, uy, uz
+
+        sin_t = sin(theta)
+        cos_t = cos(theta)
+        sin_p = sin(phi)
+        cos_p = cos(phi)
+        perp = sqrt(max(1.0_dp - dz*dz, 0.0_dp))
+
+        if (perp < 1.0e-10_dp) then
+            ! Travelling along the z-axis: rotate in the fixed frame
+            ux = sin_t * cos_p
+            uy = sin_t * sin_p
+            uz = sign(cos_t, dz)
+        else
+            ! Rotate the direction cosines by (theta, phi) about the current path
+            ux = dx * cos_t + sin_t * (dx * dz * cos_p - dy * sin_p) / perp
+            uy = dy * cos_t + sin_t * (dy * dz * cos_p + dx * sin_p) / perp
+            uz = dz * cos_t - perp * sin_t * cos_p
+        end if
+
+        dx = ux
+        dy = uy
+        dz = uz
+    end subroutine update_direction
     
-    function calculate_se_yield(energy) result(yield)
-        real(dp), intent(in) :: energy
-        real(dp) :: yield, E_max
-        
-        ! Maximum SE yield energy (typically 1-2 keV for most materials)
-        E_max = 1.5_dp  ! keV for Si
-        
-        ! Empirical SE yield formula (Sternglass)
-        yield = 1.36_dp * (energy/E_max) * exp(-2.3_dp * (energy/E_max)**0.5_dp)
-    end function calculate_se_yield
     
-    function calculate_energy_loss(energy, path_length) result(energy_loss)
+    function calculate_energy_loss(ctx, material, energy, path_length) result(energy_loss)
+        type(sim_context), intent(inout) :: ctx
+        integer, intent(in) :: material
         real(dp), intent(in) :: energy, path_length
         real(dp) :: energy_loss, stopping_power
-        real(dp) :: beta2, gamma, rand
-        
-        ! Relativistic factors
-        beta2 = 1.0_dp - 1.0_dp/(1.0_dp + energy/REST_MASS_ENERGY)**2
-        gamma = 1.0_dp/sqrt(1.0_dp - beta2)
+        real(dp) :: j_kev, k, rand
+        integer :: i
         
-        ! Bethe formula with density effect correction
-        stopping_power = 0.1536_dp * (DENSITY/FE_ATOMIC_NUMBER) * (FE_ATOMIC_NUMBER/beta2) * &
-                        (log(2.0_dp*ELECTRON_MASS*beta2*gamma**2/MEAN_IONIZATION_POTENTIAL) - &
-                         beta2 - calculate_density_effect(beta2))
+        ! Joy-Luo modified Bethe formula, valid down to a few hundred eV,
+        ! summed over the constituent elements by weight fraction
+        stopping_power = 0.0_dp
+        do i = 1, ctx%num_elements(material)
+            j_kev = ctx%element_ionization(i, material) * 1.0e-3_dp
+            k = 0.731_dp + 0.0688_dp * log10(ctx%element_z(i, material))
+            stopping_power = stopping_power + ctx%element_fraction(i, material) * &
+                             ctx%element_z(i, material) / ctx%element_a(i, material) * &
+                             log(1.166_dp * (energy + k * j_kev) / j_kev)
+        end do
+        stopping_power = 78500.0_dp * ctx%density(material) / energy * stopping_power  ! keV/cm
         
         ! Add energy straggling (Landau-Vavilov)
-        call random_number(rand)
+        call random_uniform(ctx%rng, rand)
         energy_loss = stopping_power * path_length * (1.0_dp + 0.1_dp * (2.0_dp * rand - 1.0_dp))
         
-        ! Convert to keV
-        energy_loss = energy_loss * 1.0e-3_dp
+        ! Convert path length from nm to cm
+        energy_loss = energy_loss * 1.0e-7_dp
     end function calculate_energy_loss
-    
-    function calculate_density_effect(beta2) result(delta)
-        real(dp), intent(in) :: beta2
-        real(dp) :: delta, x
-        
-        ! Simplified Sternheimer formula
-        x = log10(sqrt(beta2/(1.0_dp - beta2)))
-        if (x < 0.0_dp) then
-            delta = 0.0_dp
-        else
-            delta = 4.606_dp * x - 1.0_dp
-        end if
-    end function calculate_density_effect
-
-    function f_get_scatter_data() result(data)
-        real(dp), pointer :: data(:,:)
-        data => scatter_positions
-    end function f_get_scatter_data
 
-    subroutine f_run_line_scan(start_x, end_x, num_points) bind(C, name="f_run_line_scan")
-        real(c_double), value :: start_x, end_x  ! Line scan start and end positions in nm
-        integer(c_int), value :: num_points      ! Number of points in the line scan
+    subroutine f_run_line_scan(ctx, start_x, start_y, end_x, end_y, num_points, status)
+        type(sim_context), intent(inout) :: ctx
+        real(c_double), value :: start_x, start_y  ! Line scan start position in nm
+        real(c_double), value :: end_x, end_y      ! Line scan end position in nm
+        integer(c_int), value :: num_points        ! Number of points in the line scan
+        integer, intent(out) :: status
         integer :: i
-        real(dp) :: x, step_size
-        
-        is_line_scan = .true.
+        real(dp) :: x, y, t, se_signal, bse_signal
         
-        ! Allocate line scan data array (position, intensity)
-        if (allocated(line_scan_data)) deallocate(line_scan_data)
-        allocate(line_scan_data(2, num_points))
+        status = STATUS_INVALID_ARGUMENT
+        if (num_points < 1) return
+        ctx%is_line_scan = .true.
+        call reset_trajectories(ctx)
         
-        step_size = (end_x - start_x) / (num_points - 1)
+        ! Allocate line scan data array (distance along line, BSE, SE)
+        if (allocated(ctx%line_scan_data)) deallocate(ctx%line_scan_data)
+        allocate(ctx%line_scan_data(3, num_points), stat=status)
+        if (status /= 0) then
+            status = STATUS_OUT_OF_MEMORY
+            return
+        end if
+
+        ! Final states of every point are kept in turn
+        call reserve_records(ctx, int(ctx%electrons_per_pixel, int64) * num_points, status)
+        if (status /= STATUS_OK) then
+            deallocate(ctx%line_scan_data)
+            return
+        end if
         
         ! Perform line scan
         do i = 1, num_points
-            x = start_x + (i-1) * step_size
-            line_scan_data(1, i) = x  ! Position
+            if (num_points > 1) then
+                t = real(i - 1, dp) / real(num_points - 1, dp)
+            else
+                t = 0.0_dp
+            end if
+            x = start_x + t * (end_x - start_x)
+            y = start_y + t * (end_y - start_y)
+            ctx%line_scan_data(1, i) = t * sqrt((end_x - start_x)**2 + (end_y - start_y)**2)
             
             ! Run simulation at this point
-            call simulate_point(x, 0.0_dp)  ! y=0 for line scan
-            
-            ! Calculate intensity from scattered electrons
-            line_scan_data(2, i) = calculate_intensity()
+            call rng_seed(ctx%rng, ctx%seed, int(i, int64))
+            call simulate_point(ctx, x, y, bse_signal, se_signal, status)
+            if (status /= STATUS_OK) then
+                deallocate(ctx%line_scan_data)
+                return
+            end if
+            ctx%line_scan_data(2, i) = bse_signal
+            ctx%line_scan_data(3, i) = se_signal
+
+            ! A cancelled scan keeps the points done so far
+            if (run_cancelled(ctx, int(i, int64), int(num_points, int64))) then
+                ctx%line_scan_data = ctx%line_scan_data(:, 1:i)
+                exit
+            end if
         end do
     end subroutine f_run_line_scan
 
-    function f_get_line_data() result(data)
-        real(dp), pointer :: data(:,:)
-        data => line_scan_data
-    end function f_get_line_data
-
-    subroutine simulate_point(x, y)
+    subroutine simulate_point(ctx, x, y, bse_signal, se_signal, status)
+        ! Runs every electron of the dwell at a single beam position, appending the
+        ! final states that fit to scatter_positions.
+        type(sim_context), intent(inout) :: ctx
         real(dp), intent(in) :: x, y
-        ! ... implementation of single point simulation ...
-        ! This will be similar to f_run_simulation but for a single point
-    end subroutine simulate_point
+        real(dp), intent(out) :: bse_signal  ! Backscattered electrons per primary
+        real(dp), intent(out) :: se_signal   ! Escaping secondaries per primary
+        integer, intent(out) :: status
+        integer :: k, se_count, se_total, bse_total, fate
+        real(dp) :: ex, ey, ez, energy, dx, dy, dz
 
-    function calculate_intensity() result(intensity)
-        real(dp) :: intensity
-        integer :: i
-        
-        intensity = 0.0_dp
-        do i = 1, num_electrons
-            if (scatter_positions(3,i) < 0.0_dp) then  ! Backscattered electron
-                intensity = intensity + 1.0_dp
-            end if
+        bse_signal = 0.0_dp
+        se_signal = 0.0_dp
+        status = STATUS_OK
+        se_total = 0
+        bse_total = 0
+        do k = 1, ctx%electrons_per_pixel
+            call track_electron(ctx, x, y, ex, ey, ez, energy, dx, dy, dz, fate, se_count)
+            call record_electron(ctx, ex, ey, ez, energy, dx, dy, dz, fate)
+            se_total = se_total + se_count
+            if (fate == FATE_BACKSCATTERED) bse_total = bse_total + 1
         end do
-        intensity = intensity / num_electrons
-    end function calculate_intensity
-
-    function f_get_image_data() result(data)
-        real(dp), pointer :: data(:,:)
-        data => image_buffer
-    end function f_get_image_data
+        bse_signal = real(bse_total, dp) / real(ctx%electrons_per_pixel, dp)
+        se_signal = real(se_total, dp) / real(ctx%electrons_per_pixel, dp)
+    end subroutine simulate_point
 end module monte_carlo
@@ -0,0 +1,105 @@
+! random_streams.f90
+! Seeded random numbers for the Monte Carlo engine. Each pixel draws from its own
+! xoshiro128** stream keyed by the run seed and the pixel number, so an image is
+! bit-identical for a given seed whatever order the pixels are simulated in.
+
+module random_streams
+  use iso_fortran_env, only: dp => real64, int64
+  implicit none
+  private
+  public :: rng_state, rng_seed, random_uniform
+
+  ! 32-bit words are held in 64-bit integers so every product and sum is exact
+  ! and never relies on integer overflow
+  integer(int64), parameter :: MASK32 = 4294967295_int64
+  integer(int64), parameter :: MASK16 = 65535_int64
+  integer(int64), parameter :: GOLDEN32 = 2654435769_int64  ! 2^32 / golden ratio
+  integer, parameter :: SEED_ROUNDS = 4
+  real(dp), parameter :: TWO_POW_21 = 2097152.0_dp
+  real(dp), parameter :: TWO_POW_M53 = 1.0_dp / 9007199254740992.0_dp
+
+  ! State of one xoshiro128** stream
+  type :: rng_state
+    integer(int64) :: s(4) = [1_int64, 0_int64, 0_int64, 0_int64]
+  end type rng_state
+
+contains
+
+  subroutine rng_seed(rng, seed, stream)
+    ! Starts stream `stream` of the sequence selected by `seed`. The two 64-bit
+    ! keys are spread over the whole 128-bit state by invertible mixing rounds,
+    ! so distinct (seed, stream) pairs always give distinct states.
+    type(rng_state), intent(out) :: rng
+    integer(int64), intent(in) :: seed, stream
+    integer :: round, i
+
+    rng%s = [iand(seed, MASK32), iand(ishft(seed, -32), MASK32), &
+             iand(stream, MASK32), iand(ishft(stream, -32), MASK32)]
+    do round = 1, SEED_ROUNDS
+      do i = 1, 4
+        rng%s(i) = ieor(mix32(iand(rng%s(i) + GOLDEN32 * i, MASK32)), rng%s(mod(i, 4) + 1))
+      end do
+    end do
+
+    ! The all-zero state is the generator's only fixed point
+    if (all(rng%s == 0_int64)) rng%s(1) = 1_int64
+  end subroutine rng_seed
+
+  subroutine random_uniform(rng, harvest)
+    ! Uniform deviate in [0, 1) with 53 random bits, replacing random_number
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(out) :: harvest
+    integer(int64) :: high, low
+
+    high = next32(rng)
+    low = next32(rng)
+    harvest = (real(high, dp) * TWO_POW_21 + real(ishft(low, -11), dp)) * TWO_POW_M53
+  end subroutine random_uniform
+
+  function next32(rng) result(word)
+    ! Next 32-bit output of xoshiro128** (Blackman & Vigna)
+    type(rng_state), intent(inout) :: rng
+    integer(int64) :: word
+    integer(int64) :: t
+
+    word = mul32(rotl32(mul32(rng%s(2), 5_int64), 7), 9_int64)
+    t = iand(ishft(rng%s(2), 9), MASK32)
+
+    rng%s(3) = ieor(rng%s(3), rng%s(1))
+    rng%s(4) = ieor(rng%s(4), rng%s(2))
+    rng%s(2) = ieor(rng%s(2), rng%s(3))
+    rng%s(1) = ieor(rng%s(1), rng%s(4))
+    rng%s(3) = ieor(rng%s(3), t)
+    rng%s(4) = rotl32(rng%s(4), 11)
+  end function next32
+
+  pure function mix32(x) result(h)
+    ! MurmurHash3 finaliser: a bijection on 32-bit words with full avalanche
+    integer(int64), intent(in) :: x
+    integer(int64) :: h
+
+    h = ieor(x, ishft(x, -16))
+    h = mul32(h, 2246822507_int64)  ! 0x85ebca6b
+    h = ieor(h, ishft(h, -13))
+    h = mul32(h, 3266489909_int64)  ! 0xc2b2ae35
+    h = ieor(h, ishft(h, -16))
+  end function mix32
+
+  pure function mul32(a, b) result(product)
+    ! a * b modulo 2^32, splitting b into 16-bit halves to stay below 2^49
+    integer(int64), intent(in) :: a, b
+    integer(int64) :: product
+
+    product = iand(a * iand(b, MASK16) + ishft(iand(a * ishft(b, -16), MASK16), 16), MASK32)
+  end function mul32
+
+  pure function rotl32(x, k) result(rotated)
+    ! Rotates a 32-bit word left by k bits (0 < k < 32)
+    integer(int64), intent(in) :: x
+    integer, intent(in) :: k
+    integer(int64) :: rotated
+
+    rotated = iand(ior(ishft(x, k), ishft(x, k - 32)), MASK32)
+  end function rotl32
+
+end module random_streams
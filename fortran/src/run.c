@@ -2,19 +2,193 @@
 #include <stddef.h>
 
 // Forward declarations of Fortran functions with correct names
-extern void init_simulation(double* energy, double* current, int* resolution, double* distance);
-extern void run_simulation(void);
-extern void fortran_get_image_data(double** data, int* width, int* height);
+extern sem_sim_context* fortran_create_simulation(void);
+extern void fortran_free_simulation(sem_sim_context* ctx);
+extern int fortran_init_simulation(sem_sim_context* ctx, double energy, double current, int width, int height,
+                                   double distance, double field_of_view, double offset_x, double offset_y,
+                                   double rotation);
+extern int fortran_set_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                                const double* atomic_weights, const double* weight_fractions,
+                                const double* mean_ionizations, double density);
+extern int fortran_add_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                                const double* atomic_weights, const double* weight_fractions,
+                                const double* mean_ionizations, double density, int* index);
+extern int fortran_set_secondary_emission(sem_sim_context* ctx, int material, double escape_depth,
+                                          double work_function);
+extern int fortran_set_probe(sem_sim_context* ctx, double spot_size, double convergence,
+                             double aperture_diameter, double focus_depth, double dwell_time);
+extern int fortran_set_column(sem_sim_context* ctx, double reduced_brightness, double energy_spread,
+                              double cs, double cc, double target_diameter);
+extern int fortran_get_probe(sem_sim_context* ctx, double* spot_size, double* convergence, double* current);
+extern int fortran_set_electrons_per_pixel(sem_sim_context* ctx, int count);
+extern int fortran_get_electrons_per_pixel(sem_sim_context* ctx, int* count);
+extern int fortran_set_seed(sem_sim_context* ctx, uint64_t seed);
+extern int fortran_set_progress_callback(sem_sim_context* ctx, sem_progress_fn callback, void* user_data);
+extern int fortran_set_detector(sem_sim_context* ctx, double elevation, double azimuth, double distance,
+                                double inner_radius, double outer_radius, double se_efficiency,
+                                double bse_efficiency, double gain, double dark_current,
+                                double noise_level, double time_constant);
+extern int fortran_set_height_map(sem_sim_context* ctx, const double* heights, int nx, int ny, double spacing);
+extern int fortran_set_geometry(sem_sim_context* ctx, int num_shapes, const int* kinds, const double* params,
+                                int program_length, const int* program, int num_regions,
+                                const int* region_lengths, const int* region_materials);
+extern int fortran_set_trajectory_recording(sem_sim_context* ctx, int max_electrons);
+extern int fortran_run_simulation(sem_sim_context* ctx);
+extern int fortran_run_line_scan(sem_sim_context* ctx, double start_x, double start_y, double end_x, double end_y, int num_points);
+extern int fortran_get_scatter_data(sem_sim_context* ctx, double** data, int* rows, int* cols);
+extern int fortran_get_line_data(sem_sim_context* ctx, double** data, int* points);
+extern int fortran_get_image_data(sem_sim_context* ctx, int channel, double** data, int* width, int* height);
+extern int fortran_get_trajectory_data(sem_sim_context* ctx, double** data, int* rows, int* vertices);
+
+// The Fortran side dereferences every pointer it is given, so reject null ones here.
+// Arrays may only be null when they hold no elements.
+#define REQUIRE(cond) do { if (!(cond)) return SEM_ERR_NULL_POINTER; } while (0)
+#define REQUIRE_ARRAY(ptr, count) REQUIRE((ptr) != NULL || (count) <= 0)
 
 // C wrapper functions that match the header declarations
-void c_init_simulation(double energy, double current, int resolution, double distance) {
-    init_simulation(&energy, &current, &resolution, &distance);
+sem_sim_context* c_create_simulation(void) {
+    return fortran_create_simulation();
+}
+
+void c_free_simulation(sem_sim_context* ctx) {
+    fortran_free_simulation(ctx);
+}
+
+int c_init_simulation(sem_sim_context* ctx, double energy, double current, int width, int height,
+                      double distance, double field_of_view, double offset_x, double offset_y,
+                      double rotation) {
+    REQUIRE(ctx);
+    return fortran_init_simulation(ctx, energy, current, width, height, distance, field_of_view, offset_x,
+                                   offset_y, rotation);
+}
+
+int c_set_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                   const double* atomic_weights, const double* weight_fractions,
+                   const double* mean_ionizations, double density) {
+    REQUIRE(ctx);
+    REQUIRE_ARRAY(atomic_numbers, num_elements);
+    REQUIRE_ARRAY(atomic_weights, num_elements);
+    REQUIRE_ARRAY(weight_fractions, num_elements);
+    REQUIRE_ARRAY(mean_ionizations, num_elements);
+    return fortran_set_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions,
+                                mean_ionizations, density);
+}
+
+int c_add_material(sem_sim_context* ctx, int num_elements, const double* atomic_numbers,
+                   const double* atomic_weights, const double* weight_fractions,
+                   const double* mean_ionizations, double density, int* index) {
+    REQUIRE(ctx && index);
+    REQUIRE_ARRAY(atomic_numbers, num_elements);
+    REQUIRE_ARRAY(atomic_weights, num_elements);
+    REQUIRE_ARRAY(weight_fractions, num_elements);
+    REQUIRE_ARRAY(mean_ionizations, num_elements);
+    return fortran_add_material(ctx, num_elements, atomic_numbers, atomic_weights, weight_fractions,
+                                mean_ionizations, density, index);
+}
+
+int c_set_secondary_emission(sem_sim_context* ctx, int material, double escape_depth, double work_function) {
+    REQUIRE(ctx);
+    return fortran_set_secondary_emission(ctx, material, escape_depth, work_function);
+}
+
+int c_set_probe(sem_sim_context* ctx, double spot_size, double convergence, double aperture_diameter,
+                double focus_depth, double dwell_time) {
+    REQUIRE(ctx);
+    return fortran_set_probe(ctx, spot_size, convergence, aperture_diameter, focus_depth, dwell_time);
+}
+
+int c_set_column(sem_sim_context* ctx, double reduced_brightness, double energy_spread, double cs,
+                 double cc, double target_diameter) {
+    REQUIRE(ctx);
+    return fortran_set_column(ctx, reduced_brightness, energy_spread, cs, cc, target_diameter);
+}
+
+int c_get_probe(sem_sim_context* ctx, double* spot_size, double* convergence, double* current) {
+    REQUIRE(ctx && spot_size && convergence && current);
+    return fortran_get_probe(ctx, spot_size, convergence, current);
+}
+
+int c_set_electrons_per_pixel(sem_sim_context* ctx, int count) {
+    REQUIRE(ctx);
+    return fortran_set_electrons_per_pixel(ctx, count);
+}
+
+int c_get_electrons_per_pixel(sem_sim_context* ctx, int* count) {
+    REQUIRE(ctx && count);
+    return fortran_get_electrons_per_pixel(ctx, count);
+}
+
+int c_set_seed(sem_sim_context* ctx, uint64_t seed) {
+    REQUIRE(ctx);
+    return fortran_set_seed(ctx, seed);
+}
+
+int c_set_progress_callback(sem_sim_context* ctx, sem_progress_fn callback, void* user_data) {
+    REQUIRE(ctx);
+    return fortran_set_progress_callback(ctx, callback, user_data);
+}
+
+int c_set_detector(sem_sim_context* ctx, double elevation, double azimuth, double distance,
+                   double inner_radius, double outer_radius, double se_efficiency,
+                   double bse_efficiency, double gain, double dark_current, double noise_level,
+                   double time_constant) {
+    REQUIRE(ctx);
+    return fortran_set_detector(ctx, elevation, azimuth, distance, inner_radius, outer_radius,
+                                se_efficiency, bse_efficiency, gain, dark_current, noise_level,
+                                time_constant);
+}
+
+int c_set_height_map(sem_sim_context* ctx, const double* heights, int nx, int ny, double spacing) {
+    REQUIRE(ctx);
+    REQUIRE(heights || nx <= 0 || ny <= 0);
+    return fortran_set_height_map(ctx, heights, nx, ny, spacing);
+}
+
+int c_set_geometry(sem_sim_context* ctx, int num_shapes, const int* kinds, const double* params,
+                   int program_length, const int* program, int num_regions,
+                   const int* region_lengths, const int* region_materials) {
+    REQUIRE(ctx);
+    if (num_regions > 0) {
+        REQUIRE_ARRAY(kinds, num_shapes);
+        REQUIRE_ARRAY(params, num_shapes);
+        REQUIRE_ARRAY(program, program_length);
+        REQUIRE(region_lengths && region_materials);
+    }
+    return fortran_set_geometry(ctx, num_shapes, kinds, params, program_length, program, num_regions,
+                                region_lengths, region_materials);
+}
+
+int c_set_trajectory_recording(sem_sim_context* ctx, int max_electrons) {
+    REQUIRE(ctx);
+    return fortran_set_trajectory_recording(ctx, max_electrons);
+}
+
+int c_run_simulation(sem_sim_context* ctx) {
+    REQUIRE(ctx);
+    return fortran_run_simulation(ctx);
+}
+
+int c_run_line_scan(sem_sim_context* ctx, double start_x, double start_y, double end_x, double end_y, int num_points) {
+    REQUIRE(ctx);
+    return fortran_run_line_scan(ctx, start_x, start_y, end_x, end_y, num_points);
+}
+
+int c_get_scatter_data(sem_sim_context* ctx, double** data, int* rows, int* cols) {
+    REQUIRE(ctx && data && rows && cols);
+    return fortran_get_scatter_data(ctx, data, rows, cols);
+}
+
+int c_get_line_data(sem_sim_context* ctx, double** data, int* points) {
+    REQUIRE(ctx && data && points);
+    return fortran_get_line_data(ctx, data, points);
 }
 
-void c_run_simulation(void) {
-    run_simulation();
+int c_get_image_data(sem_sim_context* ctx, int channel, double** data, int* width, int* height) {
+    REQUIRE(ctx && data && width && height);
+    return fortran_get_image_data(ctx, channel, data, width, height);
 }
 
-void c_get_image_data(double** data, int* width, int* height) {
-    fortran_get_image_data(data, width, height);
+int c_get_trajectory_data(sem_sim_context* ctx, double** data, int* rows, int* vertices) {
+    REQUIRE(ctx && data && rows && vertices);
+    return fortran_get_trajectory_data(ctx, data, rows, vertices);
 }
@@ -3,9 +3,11 @@
 
 module scattering
   use iso_fortran_env, only: dp => real64
+  use random_streams, only: rng_state, random_uniform
   implicit none
   private
   public :: elastic_scatter, inelastic_scatter, generate_secondaries
+  public :: sample_se_energy, se_escapes
 
   ! Physical constants
   real(dp), parameter :: PI = 3.141592653589793_dp
@@ -15,12 +17,14 @@ module scattering
   real(dp), parameter :: RYDBERG_ENERGY = 13.605693122994_dp ! eV
   real(dp), parameter :: FINE_STRUCTURE = 1.0_dp/137.035999084_dp
   real(dp), parameter :: SPEED_OF_LIGHT = 2.99792458e8_dp ! m/s
-  integer, parameter :: MAX_SE = 10 ! Maximum number of secondary electrons
+  integer, parameter :: MAX_SE = 100 ! Safety cap on secondaries generated per call
+  real(dp), parameter :: SE_MAX_ENERGY = 50.0_dp ! eV, upper limit of the SE spectrum
 
 contains
 
-  function elastic_scatter(energy_in, atomic_number) result(scatter_angle)
+  function elastic_scatter(rng, energy_in, atomic_number) result(scatter_angle)
     ! Simulates an elastic scattering angle using Mott cross-section approximation
+    type(rng_state), intent(inout) :: rng
     real(dp), intent(in) :: energy_in
     integer, intent(in) :: atomic_number
     real(dp) :: scatter_angle
@@ -38,12 +42,13 @@ contains
     eta = 2.0_dp * energy * screening_param / (FINE_STRUCTURE * BOHR_RADIUS)
     
     ! Sample from screened Rutherford distribution
-    call random_number(rand)
+    call random_uniform(rng, rand)
     scatter_angle = acos(1.0_dp - 2.0_dp * rand/(1.0_dp + eta * (1.0_dp - rand)))
   end function elastic_scatter
 
-  function inelastic_scatter(energy_in, atomic_number) result(energy_loss)
+  function inelastic_scatter(rng, energy_in, atomic_number) result(energy_loss)
     ! Simulates inelastic scattering using Bethe formula with straggling
+    type(rng_state), intent(inout) :: rng
     real(dp), intent(in) :: energy_in
     integer, intent(in) :: atomic_number
     real(dp) :: energy_loss
@@ -56,7 +61,7 @@ contains
     mean_loss = (78500.0_dp * atomic_number) / energy * log(1.166_dp * energy/RYDBERG_ENERGY)
     
     ! Add energy straggling (simplified model)
-    call random_number(rand)
+    call random_uniform(rng, rand)
     straggling = mean_loss * 0.1_dp * (2.0_dp * rand - 1.0_dp)
     energy_loss = mean_loss + straggling
     
@@ -64,18 +69,82 @@ contains
     energy_loss = energy_loss * 0.001_dp
   end function inelastic_scatter
 
-  function generate_secondaries(primary_energy) result(num_secondaries)
-    ! Generates secondary electrons based on primary electron energy
-    real(dp), intent(in) :: primary_energy
+  function generate_secondaries(rng, deposited_energy, excitation_energy) result(num_secondaries)
+    ! Samples the number of slow secondaries excited when deposited_energy (keV) is lost,
+    ! each costing excitation_energy (eV) on average. The count is Poisson distributed.
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(in) :: deposited_energy
+    real(dp), intent(in) :: excitation_energy
     integer :: num_secondaries
-    real(dp) :: rand, yield
-    
-    ! Calculate SE yield using empirical formula
-    yield = 1.36_dp * (primary_energy/1.5_dp) * exp(-2.3_dp * sqrt(primary_energy/1.5_dp))
-    
-    ! Sample number of secondaries
-    call random_number(rand)
-    num_secondaries = min(int(rand * yield), MAX_SE)
+    real(dp) :: mean, threshold, product, rand
+
+    num_secondaries = 0
+    mean = deposited_energy * 1000.0_dp / excitation_energy
+    if (mean <= 0.0_dp) return
+
+    ! Knuth's multiplication method
+    threshold = exp(-mean)
+    call random_uniform(rng, rand)
+    product = rand
+    do while (product > threshold .and. num_secondaries < MAX_SE)
+      num_secondaries = num_secondaries + 1
+      call random_uniform(rng, rand)
+      product = product * rand
+    end do
   end function generate_secondaries
 
+  function sample_se_energy(rng, work_function) result(energy)
+    ! Vacuum kinetic energy (eV) of a secondary from the Chung-Everhart spectrum
+    ! N(E) ~ E / (E + phi)^4, truncated at SE_MAX_ENERGY. The cumulative distribution
+    ! F(E) = 1 - phi^2 (3E + phi) / (E + phi)^3 is inverted by bisection.
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(in) :: work_function
+    real(dp) :: energy
+    real(dp) :: target, lo, hi
+    integer :: i
+
+    call random_uniform(rng, target)
+    target = target * chung_everhart_cdf(SE_MAX_ENERGY, work_function)
+    lo = 0.0_dp
+    hi = SE_MAX_ENERGY
+    do i = 1, 40
+      energy = 0.5_dp * (lo + hi)
+      if (chung_everhart_cdf(energy, work_function) < target) then
+        lo = energy
+      else
+        hi = energy
+      end if
+    end do
+  end function sample_se_energy
+
+  pure function chung_everhart_cdf(energy, work_function) result(cdf)
+    real(dp), intent(in) :: energy, work_function
+    real(dp) :: cdf
+
+    cdf = 1.0_dp - work_function**2 * (3.0_dp * energy + work_function) / &
+          (energy + work_function)**3
+  end function chung_everhart_cdf
+
+  function se_escapes(rng, energy, path, cos_normal, escape_depth, work_function) result(escaped)
+    ! Decides whether a secondary reaching the surface after travelling `path` nm
+    ! leaves the sample. It must survive attenuation exp(-path / escape_depth) and
+    ! meet the surface steeply enough to cross the barrier: with internal energy
+    ! E + phi, the normal component must exceed phi ((E + phi) cos^2 > phi).
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(in) :: energy         ! Vacuum kinetic energy in eV
+    real(dp), intent(in) :: path           ! nm travelled inside the sample
+    real(dp), intent(in) :: cos_normal     ! Direction cosine to the outward surface normal
+    real(dp), intent(in) :: escape_depth   ! SE attenuation length in nm
+    real(dp), intent(in) :: work_function  ! Surface barrier in eV
+    logical :: escaped
+    real(dp) :: rand
+
+    escaped = .false.
+    if (cos_normal <= 0.0_dp) return
+    if ((energy + work_function) * cos_normal**2 <= work_function) return
+
+    call random_uniform(rng, rand)
+    escaped = rand < exp(-path / escape_depth)
+  end function se_escapes
+
 end module scattering
@@ -1,105 +1,118 @@
 module signals
   use iso_fortran_env, only: dp => real64
+  use random_streams, only: rng_state, random_uniform
   implicit none
   private
-  public :: generate_signal, apply_detector_response, setup_detector
+  public :: detector_type, generate_signal, apply_detector_response, setup_detector
+  public :: SIGNAL_SE, SIGNAL_BSE
 
   ! Physical constants
   real(dp), parameter :: PI = 3.141592653589793_dp
 
-  ! Detector parameters
-  real(dp), parameter :: SE_EFFICIENCY = 0.85_dp     ! Secondary electron detection efficiency
-  real(dp), parameter :: BSE_EFFICIENCY = 0.90_dp    ! Backscattered electron detection efficiency
-  real(dp), parameter :: NOISE_LEVEL = 0.02_dp       ! Base noise level (standard deviation)
-  real(dp), parameter :: DARK_CURRENT = 0.005_dp     ! Dark current contribution
-  real(dp), parameter :: GAIN = 1000.0_dp            ! Detector gain
-  real(dp), parameter :: TIME_CONSTANT = 100.0e-9_dp ! Detector response time (s)
+  ! Kinds of escaping electron seen by a detector
+  integer, parameter :: SIGNAL_SE = 0
+  integer, parameter :: SIGNAL_BSE = 1
 
-  ! Detector geometry
-  real(dp) :: detector_distance = 10.0_dp  ! mm
-  real(dp) :: detector_angle = 45.0_dp     ! degrees
-  real(dp) :: detector_solid_angle         ! steradians
+  ! Geometry and response of one detector. The defaults describe a side-mounted
+  ! Everhart-Thornley detector; call setup_detector to derive the acceptance cone.
+  type :: detector_type
+    ! Detector geometry
+    real(dp) :: elevation = 30.0_dp      ! Take-off angle above the sample plane (degrees)
+    real(dp) :: azimuth = 0.0_dp         ! Direction around the beam axis (degrees)
+    real(dp) :: distance = 25.0_dp       ! Sample to detector face (mm)
+    real(dp) :: inner_radius = 0.0_dp    ! Radius of the central hole, if any (mm)
+    real(dp) :: outer_radius = 7.5_dp    ! Radius of the active area (mm)
+    real(dp) :: axis(3) = [0.0_dp, 0.0_dp, -1.0_dp]  ! Unit vector from sample to detector
+    real(dp) :: cos_inner = 1.0_dp       ! Acceptance cone limits around the axis
+    real(dp) :: cos_outer = 1.0_dp
+    real(dp) :: solid_angle = 0.0_dp     ! steradians
 
-  ! Image formation parameters
-  real(dp), parameter :: EDGE_ENHANCEMENT = 1.2_dp   ! Edge brightness enhancement factor
-  real(dp), parameter :: DEPTH_SENSITIVITY = 0.8_dp  ! Sensitivity to topographical features
-  real(dp), parameter :: CRYSTAL_CONTRAST = 0.15_dp  ! Contrast between crystal orientations
+    ! Detector response
+    real(dp) :: se_efficiency = 0.85_dp  ! Fraction of escaping secondaries collected
+    real(dp) :: bse_efficiency = 0.90_dp ! Detection efficiency for BSE inside the cone
+    real(dp) :: gain = 1000.0_dp
+    real(dp) :: dark_current = 0.005_dp  ! Dark signal per pixel (collected-electron units)
+    real(dp) :: noise_level = 0.02_dp    ! Base noise level (standard deviation)
+    real(dp) :: time_constant = 100.0e-9_dp ! Detector response time (s)
+  end type detector_type
 
 contains
 
-  subroutine setup_detector(distance_mm, angle_deg)
-    ! Configure detector geometry
-    real(dp), intent(in) :: distance_mm, angle_deg
-    
-    detector_distance = distance_mm
-    detector_angle = angle_deg
-    
-    ! Calculate detector solid angle (simplified circular detector)
-    detector_solid_angle = 2.0_dp * PI * (1.0_dp - cos(0.1_dp))  ! Assumes 0.1 rad acceptance
+  subroutine setup_detector(det, elevation_deg, azimuth_deg, distance_mm, inner_radius_mm, outer_radius_mm)
+    ! Configure detector geometry and derive its acceptance cone and solid angle
+    type(detector_type), intent(inout) :: det
+    real(dp), intent(in) :: elevation_deg, azimuth_deg
+    real(dp), intent(in) :: distance_mm, inner_radius_mm, outer_radius_mm
+    real(dp) :: elevation, azimuth
+
+    det%elevation = elevation_deg
+    det%azimuth = azimuth_deg
+    det%distance = distance_mm
+    det%inner_radius = inner_radius_mm
+    det%outer_radius = outer_radius_mm
+
+    ! Detector axis, pointing up out of the sample (z increases into the sample)
+    elevation = elevation_deg * PI / 180.0_dp
+    azimuth = azimuth_deg * PI / 180.0_dp
+    det%axis = [cos(elevation) * cos(azimuth), cos(elevation) * sin(azimuth), -sin(elevation)]
+
+    ! Flat detector face (annular if inner_radius > 0) seen from the beam spot
+    det%cos_inner = cos(atan2(inner_radius_mm, distance_mm))
+    det%cos_outer = cos(atan2(outer_radius_mm, distance_mm))
+    det%solid_angle = 2.0_dp * PI * (det%cos_inner - det%cos_outer)
   end subroutine setup_detector
 
-  function generate_signal(position, energy, surface_normal, crystal_orientation) result(signal_intensity)
-    ! Generates signal intensity from SE and BSE with energy and angular dependence
-    real(dp), intent(in) :: position(3)           ! (x,y,z) coordinates
-    real(dp), intent(in) :: energy                ! Electron energy in keV
-    real(dp), intent(in) :: surface_normal(3)     ! Local surface normal vector
-    real(dp), intent(in) :: crystal_orientation   ! Local crystal orientation
+  function generate_signal(det, kind, direction, energy_fraction) result(signal_intensity)
+    ! Signal contributed by one electron leaving the sample. Secondaries are pulled
+    ! in by the collector field whatever their direction; backscattered electrons
+    ! only count when they fly into the detector's acceptance cone, weighted by
+    ! their energy as in a scintillator or solid-state detector.
+    type(detector_type), intent(in) :: det
+    integer, intent(in) :: kind                   ! SIGNAL_SE or SIGNAL_BSE
+    real(dp), intent(in) :: direction(3)          ! Unit exit direction
+    real(dp), intent(in) :: energy_fraction       ! Exit energy / beam energy
     real(dp) :: signal_intensity
+    real(dp) :: cos_angle
 
-    real(dp) :: topo_factor, edge_factor, crystal_factor
-    real(dp) :: detector_vector(3), cos_angle
-    real(dp) :: local_height, gradient(2)
-    
-    ! Calculate topographical contrast
-    detector_vector = [sin(detector_angle), 0.0_dp, cos(detector_angle)]
-    cos_angle = dot_product(surface_normal, detector_vector)
-    topo_factor = (1.0_dp + DEPTH_SENSITIVITY * abs(cos_angle))
-
-    ! Calculate edge enhancement
-    local_height = position(3)
-    gradient = [position(1), position(2)] - [0.0_dp, 0.0_dp]  ! Simplified gradient
-    edge_factor = 1.0_dp + EDGE_ENHANCEMENT * exp(-norm2(gradient)**2 / 100.0_dp)
-
-    ! Calculate crystal orientation contrast
-    crystal_factor = 1.0_dp + CRYSTAL_CONTRAST * cos(crystal_orientation)
-
-    ! Combine all contrast mechanisms
-    signal_intensity = SE_EFFICIENCY * topo_factor * edge_factor * crystal_factor * &
-                      exp(-norm2(position) / detector_distance) * &
-                      (1.0_dp - exp(-energy/2.0_dp))  ! Energy-dependent yield
+    signal_intensity = 0.0_dp
+    select case (kind)
+    case (SIGNAL_SE)
+      signal_intensity = det%se_efficiency
+    case (SIGNAL_BSE)
+      cos_angle = dot_product(direction, det%axis)
+      if (cos_angle <= det%cos_inner .and. cos_angle >= det%cos_outer) then
+        signal_intensity = det%bse_efficiency * energy_fraction
+      end if
+    end select
   end function generate_signal
 
-  function apply_detector_response(signal_intensity, dwell_time) result(measured_signal)
+  function apply_detector_response(det, rng, signal_intensity, dwell_time) result(measured_signal)
     ! Simulates detector's response including various real-world effects
-    real(dp), intent(in) :: signal_intensity
+    type(detector_type), intent(in) :: det
+    type(rng_state), intent(inout) :: rng
+    real(dp), intent(in) :: signal_intensity  ! Collected signal for one pixel
     real(dp), intent(in) :: dwell_time        ! Pixel dwell time in seconds
     real(dp) :: measured_signal
-    real(dp) :: noise, shot_noise, thermal_noise
-    real(dp) :: rand1, rand2, response_factor
-    
+    real(dp) :: shot_noise, thermal_noise
+    real(dp) :: rand1, rand2, gauss_r, response_factor
+
     ! Time-dependent response
-    response_factor = 1.0_dp - exp(-dwell_time/TIME_CONSTANT)
-    
-    ! Generate realistic noise
-    call random_number(rand1)
-    call random_number(rand2)
-    
-    thermal_noise = NOISE_LEVEL * sqrt(-2.0_dp * log(rand1)) * cos(2.0_dp * PI * rand2)
-    shot_noise = sqrt(abs(signal_intensity)) * NOISE_LEVEL * &
-                 sqrt(-2.0_dp * log(rand1)) * sin(2.0_dp * PI * rand2)
-    
-    ! Combine signal components
-    measured_signal = response_factor * signal_intensity + &
-                     shot_noise + thermal_noise + DARK_CURRENT
-    
-    ! Apply realistic dynamic range
-    measured_signal = max(0.0_dp, min(measured_signal, 255.0_dp))
-  end function apply_detector_response
+    response_factor = 1.0_dp - exp(-dwell_time/det%time_constant)
 
-  pure function dot_product(a, b) result(result)
-    real(dp), dimension(3), intent(in) :: a, b
-    real(dp) :: result
-    result = a(1)*b(1) + a(2)*b(2) + a(3)*b(3)
-  end function dot_product
+    ! Generate realistic noise (Box-Muller pair)
+    call random_uniform(rng, rand1)
+    call random_uniform(rng, rand2)
+    gauss_r = sqrt(-2.0_dp * log(1.0_dp - rand1))
+
+    thermal_noise = det%noise_level * gauss_r * cos(2.0_dp * PI * rand2)
+    shot_noise = sqrt(abs(signal_intensity)) * det%noise_level * gauss_r * sin(2.0_dp * PI * rand2)
+
+    ! Combine signal components and amplify
+    measured_signal = det%gain * (response_factor * signal_intensity + &
+                                  shot_noise + thermal_noise + det%dark_current)
+
+    ! The amplifier output cannot go negative
+    measured_signal = max(0.0_dp, measured_signal)
+  end function apply_detector_response
 
 end module signals
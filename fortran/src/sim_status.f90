@@ -0,0 +1,16 @@
+module sim_status
+  ! Status codes reported by the engine instead of stopping the host process.
+  ! The values are mirrored by the SEM_* codes in sem_sim_c.h.
+  implicit none
+  private
+  public :: STATUS_OK, STATUS_NULL_POINTER, STATUS_INVALID_ARGUMENT, STATUS_OUT_OF_MEMORY
+  public :: STATUS_NOT_INITIALIZED, STATUS_NO_DATA, STATUS_LIMIT_EXCEEDED
+
+  integer, parameter :: STATUS_OK = 0
+  integer, parameter :: STATUS_NULL_POINTER = 1      ! Checked on the C side
+  integer, parameter :: STATUS_INVALID_ARGUMENT = 2  ! Out of range or inconsistent input
+  integer, parameter :: STATUS_OUT_OF_MEMORY = 3
+  integer, parameter :: STATUS_NOT_INITIALIZED = 4   ! Context not set up by init_simulation
+  integer, parameter :: STATUS_NO_DATA = 5           ! No run has produced the requested results
+  integer, parameter :: STATUS_LIMIT_EXCEEDED = 6    ! A fixed-size table is full
+end module sim_status
@@ -0,0 +1,188 @@
+//! Engine-independent description of a run's output: electron exits, image
+//! channels, trajectories and line profiles.
+
+use serde::{Deserialize, Serialize};
+
+/// What became of a primary electron at the end of its trajectory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ElectronFate {
+    /// Slowed below the cutoff energy inside the sample.
+    Absorbed,
+    /// Left the sample through its top surface.
+    Backscattered,
+    /// Left the sample through its bottom surface.
+    Transmitted,
+}
+
+/// Final state of one primary electron.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ElectronExit {
+    /// Exit position (or stopping point for absorbed electrons) in nm.
+    pub position: [f64; 3],
+    /// Energy in keV at exit or absorption.
+    pub energy_kev: f64,
+    /// Unit direction of travel at exit.
+    pub direction: [f64; 3],
+    pub fate: ElectronFate,
+}
+
+/// Per-electron scattering results from the simulation.
+pub type ScatterData = Vec<ElectronExit>;
+
+/// Detector channel of a simulated image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageChannel {
+    /// Escaped secondary electrons per primary.
+    Se = 0,
+    /// Backscattered energy fraction per primary.
+    Bse = 1,
+    /// Output of the configured detector per primary.
+    Detector = 2,
+    /// Electrons transmitted through the bottom of the sample per primary.
+    Transmitted = 3,
+    /// Backscattered electrons per primary, whatever their energy.
+    Backscattered = 4,
+}
+
+/// SE, BSE, detector, transmission and backscatter images filled during the
+/// same scan, stored row-major.
+#[derive(Clone, Debug, Default)]
+pub struct ImageChannels {
+    pub se: Vec<f64>,
+    /// Backscattered energy fraction; see `backscattered` for the electron count.
+    pub bse: Vec<f64>,
+    pub detector: Vec<f64>,
+    /// Zero everywhere unless the sample has a free bottom surface.
+    pub transmitted: Vec<f64>,
+    /// Backscattered electrons, the backscatter coefficient η of each pixel.
+    pub backscattered: Vec<f64>,
+    pub width: usize,
+    pub height: usize,
+    /// Primary electrons simulated per pixel; every channel is averaged over them.
+    pub electrons_per_pixel: usize,
+}
+
+impl ImageChannels {
+    /// Primary electrons simulated for the whole image.
+    pub fn total_electrons(&self) -> u64 {
+        self.electrons_per_pixel as u64 * self.width as u64 * self.height as u64
+    }
+}
+
+/// Raster scanned by the beam: image size and where the field lies on the sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanRaster {
+    pub width: i32,
+    pub height: i32,
+    /// Horizontal field width in nm; pixels are square.
+    pub field_of_view_nm: f64,
+    /// Centre of the raster on the sample (x, y) in nm.
+    pub offset_nm: (f64, f64),
+    /// Rotation of the raster about the beam axis, degrees counter-clockwise.
+    pub rotation_deg: f64,
+}
+
+impl ScanRaster {
+    /// Pixel size in nm.
+    pub fn pixel_size_nm(&self) -> f64 {
+        self.field_of_view_nm / self.width as f64
+    }
+}
+
+/// Probe the engine forms, as set or as predicted by the column model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Probe {
+    /// Spot diameter (FWHM) in nm.
+    pub spot_size_nm: f64,
+    /// Convergence semi-angle in rad.
+    pub convergence_rad: f64,
+    /// Beam current in nA.
+    pub current_na: f64,
+}
+
+/// What happened at a recorded trajectory vertex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrajectoryEvent {
+    /// Beam entry point on the surface.
+    Entry,
+    /// Elastic scattering event inside the sample.
+    Elastic,
+    /// Crossed the top surface on the way out.
+    Backscattered,
+    /// Slowed below the cutoff energy.
+    Absorbed,
+    /// Crossed the bottom surface on the way out.
+    Transmitted,
+    /// Crossed into another region of the sample.
+    Boundary,
+}
+
+/// One point along a recorded electron trajectory.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TrajectoryVertex {
+    /// Position in nm (z is depth below the surface).
+    pub position: [f64; 3],
+    /// Energy in keV after reaching this point.
+    pub energy_kev: f64,
+    pub event: TrajectoryEvent,
+}
+
+/// Full paths of the electrons traced during a run, one polyline per electron.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Trajectories {
+    pub electrons: Vec<Vec<TrajectoryVertex>>,
+}
+
+impl Trajectories {
+    pub fn is_empty(&self) -> bool {
+        self.electrons.is_empty()
+    }
+}
+
+/// BSE and SE intensity profile from a line scan.
+#[derive(Clone, Debug)]
+pub struct LineProfile {
+    /// Distance of each beam position from the start of the line, in nm.
+    pub positions: Vec<f64>,
+    /// Backscattered electrons per primary at each position.
+    pub bse: Vec<f64>,
+    /// Escaping secondary electrons per primary at each position.
+    pub se: Vec<f64>,
+}
+
+impl LineProfile {
+    /// Edge resolution of a signal as the 20–80 % rise distance in nm.
+    ///
+    /// Returns `None` if the profile is flat or never crosses both levels.
+    pub fn edge_width(signal: &[f64], positions: &[f64]) -> Option<f64> {
+        let (min, max) = signal.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(min, max), &v| (min.min(v), max.max(v)),
+        );
+        if max - min <= 0.0 {
+            return None;
+        }
+        // Linearly interpolated position where the signal first crosses `level`
+        let crossing = |level: f64| {
+            let level = min + level * (max - min);
+            signal.windows(2).zip(positions.windows(2)).find_map(|(s, p)| {
+                let (lo, hi) = (s[0].min(s[1]), s[0].max(s[1]));
+                if level < lo || level > hi || s[0] == s[1] {
+                    return None;
+                }
+                Some(p[0] + (level - s[0]) / (s[1] - s[0]) * (p[1] - p[0]))
+            })
+        };
+        Some((crossing(0.8)? - crossing(0.2)?).abs())
+    }
+
+    /// 20–80 % edge width of the BSE profile in nm.
+    pub fn bse_edge_width(&self) -> Option<f64> {
+        Self::edge_width(&self.bse, &self.positions)
+    }
+
+    /// 20–80 % edge width of the SE profile in nm.
+    pub fn se_edge_width(&self) -> Option<f64> {
+        Self::edge_width(&self.se, &self.positions)
+    }
+}
@@ -0,0 +1,261 @@
+//! Deterministic stand-in engine for testing result handling, image formation
+//! and export without a native library or Monte Carlo noise.
+
+use std::sync::atomic::Ordering;
+
+use super::{
+    check_beam, check_probe, Capabilities, CancelFlag, ElectronExit, ElectronFate, ImageChannels, LineProfile, Probe,
+    ProgressCallback, ScanRaster, ScatterData, SimulationBackend, TrajectoryEvent, TrajectoryVertex, Trajectories,
+};
+use crate::error::SimError;
+use crate::imaging::detector::DetectorConfig;
+use crate::materials::Material;
+use crate::sample::{HeightMap, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
+
+/// Engine that fills every channel with closed-form values instead of
+/// tracking electrons.
+///
+/// The SE channel is a ramp from 0.1 at the first pixel to 1.0 at the last,
+/// the BSE channel the backscatter coefficient of the material's effective atomic
+/// number (Reuter's fit), and the detector channel their sum weighted by the
+/// detector efficiencies. Each pixel and line-scan point yields one backscattered
+/// exit record at its centre; sample geometry and height maps are ignored. Invalid settings
+/// are rejected like the real engines reject them.
+pub struct MockBackend {
+    energy_kev: f64,
+    current_na: f64,
+    scan: ScanRaster,
+    distance_mm: f64,
+    probe: Probe,
+    electrons_per_pixel: usize,
+    backscatter: f64,
+    detector: DetectorConfig,
+    trajectory_limit: usize,
+    cancel: Option<CancelFlag>,
+    progress: Option<ProgressCallback>,
+    channels: ImageChannels,
+    scatter: ScatterData,
+    trajectories: Trajectories,
+}
+
+impl MockBackend {
+    /// SE yield of pixel `index` of `count`.
+    fn se_ramp(index: usize, count: usize) -> f64 {
+        0.1 + 0.9 * index as f64 / count.saturating_sub(1).max(1) as f64
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    fn report_progress(&self, completed: usize, total: usize) {
+        if let Some(callback) = &self.progress {
+            callback(completed as u64, total as u64);
+        }
+    }
+}
+
+impl SimulationBackend for MockBackend {
+    fn new(energy: f64, current: f64, scan: &ScanRaster, distance: f64) -> Result<Self, SimError> {
+        check_beam(energy, current, scan, distance)?;
+        Ok(MockBackend {
+            energy_kev: energy,
+            current_na: current,
+            scan: *scan,
+            distance_mm: distance,
+            probe: Probe { spot_size_nm: 5.0, convergence_rad: 1.5e-3, current_na: current },
+            electrons_per_pixel: 1,
+            backscatter: 0.0,
+            detector: DetectorConfig::default(),
+            trajectory_limit: 0,
+            cancel: None,
+            progress: None,
+            channels: ImageChannels::default(),
+            scatter: Vec::new(),
+            trajectories: Trajectories::default(),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { name: "mock", cancellation: true, geometry: false, trajectories: true }
+    }
+
+    fn set_probe(&mut self, spot_size_nm: f64, convergence_rad: f64, aperture_diameter_um: f64,
+                 _focus_depth_nm: f64, dwell_time_s: f64) -> Result<(), SimError> {
+        check_probe(spot_size_nm, convergence_rad, aperture_diameter_um, dwell_time_s)?;
+        self.probe.spot_size_nm = spot_size_nm;
+        self.probe.convergence_rad = if convergence_rad > 0.0 {
+            convergence_rad
+        } else {
+            0.5 * aperture_diameter_um * 1.0e-3 / self.distance_mm
+        };
+        Ok(())
+    }
+
+    fn set_column(&mut self, column: &ColumnConfig) -> Result<(), SimError> {
+        column.validate()?;
+        match column.target_spot_nm {
+            Some(target) => {
+                self.current_na = column.probe_current_na(self.energy_kev, target, self.probe.convergence_rad)?;
+                self.probe = Probe { spot_size_nm: target, current_na: self.current_na, ..self.probe };
+            }
+            None => {
+                self.probe.spot_size_nm =
+                    column.probe_diameter_nm(self.energy_kev, self.current_na, self.probe.convergence_rad);
+            }
+        }
+        Ok(())
+    }
+
+    fn probe(&self) -> Result<Probe, SimError> {
+        Ok(self.probe)
+    }
+
+    fn set_electrons_per_pixel(&mut self, count: Option<u32>) -> Result<(), SimError> {
+        self.electrons_per_pixel = count.unwrap_or(1).max(1) as usize;
+        Ok(())
+    }
+
+    fn electrons_per_pixel(&self) -> Result<usize, SimError> {
+        Ok(self.electrons_per_pixel)
+    }
+
+    fn set_seed(&mut self, _seed: u64) -> Result<(), SimError> {
+        Ok(())
+    }
+
+    fn set_material(&mut self, material: &Material) -> Result<(), SimError> {
+        material.validate()?;
+        let z = material.effective_atomic_number();
+        self.backscatter = -0.0254 + 0.016 * z - 1.86e-4 * z * z + 8.3e-7 * z * z * z;
+        Ok(())
+    }
+
+    fn set_height_map(&mut self, _map: Option<&HeightMap>) -> Result<(), SimError> {
+        Ok(())
+    }
+
+    fn set_geometry(&mut self, _geometry: Option<&SampleGeometry>) -> Result<(), SimError> {
+        Ok(())
+    }
+
+    fn set_detector(&mut self, detector: &DetectorConfig) -> Result<(), SimError> {
+        detector.validate()?;
+        self.detector = detector.clone();
+        Ok(())
+    }
+
+    fn set_trajectory_recording(&mut self, max_electrons: usize) -> Result<(), SimError> {
+        self.trajectory_limit = max_electrons;
+        Ok(())
+    }
+
+    fn set_cancel_flag(&mut self, flag: CancelFlag) {
+        self.cancel = Some(flag);
+    }
+
+    fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
+    fn run(&mut self) -> Result<(), SimError> {
+        let (width, height) = (self.scan.width.max(0) as usize, self.scan.height.max(0) as usize);
+        let pixels = width * height;
+        self.channels = ImageChannels {
+            se: vec![0.0; pixels],
+            bse: vec![0.0; pixels],
+            detector: vec![0.0; pixels],
+            transmitted: vec![0.0; pixels],
+            backscattered: vec![0.0; pixels],
+            width,
+            height,
+            electrons_per_pixel: self.electrons_per_pixel,
+        };
+        self.scatter.clear();
+        self.trajectories.electrons.clear();
+
+        let pixel_size = self.scan.pixel_size_nm();
+        for j in 0..height {
+            if self.cancelled() {
+                break;
+            }
+            for i in 0..width {
+                let pixel = j * width + i;
+                let se = Self::se_ramp(pixel, pixels);
+                self.channels.se[pixel] = se;
+                self.channels.bse[pixel] = self.backscatter;
+                self.channels.backscattered[pixel] = self.backscatter;
+                self.channels.detector[pixel] =
+                    self.detector.se_efficiency * se + self.detector.bse_efficiency * self.backscatter;
+
+                let position = [
+                    self.scan.offset_nm.0 + (i as f64 + 0.5 - 0.5 * width as f64) * pixel_size,
+                    self.scan.offset_nm.1 + (j as f64 + 0.5 - 0.5 * height as f64) * pixel_size,
+                    0.0,
+                ];
+                let energy_kev = self.backscatter * self.energy_kev;
+                self.scatter.push(ElectronExit {
+                    position,
+                    energy_kev,
+                    direction: [0.0, 0.0, -1.0],
+                    fate: ElectronFate::Backscattered,
+                });
+                if self.trajectories.electrons.len() < self.trajectory_limit {
+                    self.trajectories.electrons.push(vec![
+                        TrajectoryVertex { position, energy_kev: self.energy_kev, event: TrajectoryEvent::Entry },
+                        TrajectoryVertex { position, energy_kev, event: TrajectoryEvent::Backscattered },
+                    ]);
+                }
+            }
+            self.report_progress((j + 1) * width, pixels);
+        }
+        Ok(())
+    }
+
+    fn run_line_scan(&mut self, start: (f64, f64), end: (f64, f64), n_points: i32)
+        -> Result<LineProfile, SimError> {
+        if n_points < 1 {
+            return Err(SimError::InvalidParameter(format!("line scan needs at least one point, got {}", n_points)));
+        }
+        let n_points = n_points as usize;
+        let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+        self.scatter.clear();
+        let mut profile = LineProfile {
+            positions: Vec::with_capacity(n_points),
+            bse: Vec::with_capacity(n_points),
+            se: Vec::with_capacity(n_points),
+        };
+
+        // A cancelled scan keeps the points done so far
+        for point in 0..n_points {
+            if self.cancelled() {
+                break;
+            }
+            let t = if n_points > 1 { point as f64 / (n_points - 1) as f64 } else { 0.0 };
+            self.scatter.push(ElectronExit {
+                position: [start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1), 0.0],
+                energy_kev: self.backscatter * self.energy_kev,
+                direction: [0.0, 0.0, -1.0],
+                fate: ElectronFate::Backscattered,
+            });
+            profile.positions.push(t * length);
+            profile.bse.push(self.backscatter);
+            profile.se.push(Self::se_ramp(point, n_points));
+            self.report_progress(point + 1, n_points);
+        }
+        Ok(profile)
+    }
+
+    fn scatter_data(&self) -> Result<ScatterData, SimError> {
+        Ok(self.scatter.clone())
+    }
+
+    fn trajectories(&self) -> Result<Trajectories, SimError> {
+        Ok(self.trajectories.clone())
+    }
+
+    fn image_channels(&self) -> Result<ImageChannels, SimError> {
+        Ok(self.channels.clone())
+    }
+}
@@ -0,0 +1,173 @@
+//! Monte Carlo engines behind a common interface.
+//!
+//! Two engines implement the same physics: the Fortran library (`fortran`
+//! feature, needs gfortran) and a pure-Rust port (`rust-engine` feature).
+//! `DefaultBackend` is the Rust engine whenever it is enabled, otherwise the
+//! Fortran one. `MockBackend` returns closed-form results for tests.
+//!
+//! Every engine rejects the same invalid settings with `SimError`, so a bad
+//! job fails on its own instead of taking the host process down.
+pub mod data;
+pub mod mock;
+#[cfg(feature = "rust-engine")]
+pub mod native;
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+pub use data::{
+    ElectronExit, ElectronFate, ImageChannel, ImageChannels, LineProfile, Probe, ScanRaster, ScatterData,
+    TrajectoryEvent, TrajectoryVertex, Trajectories,
+};
+pub use mock::MockBackend;
+
+use crate::error::SimError;
+use crate::imaging::detector::DetectorConfig;
+use crate::materials::Material;
+use crate::sample::{HeightMap, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
+
+/// Engine used by `SimulationManager` and `run_line_scan`.
+#[cfg(feature = "rust-engine")]
+pub type DefaultBackend = native::NativeEngine;
+#[cfg(all(feature = "fortran", not(feature = "rust-engine")))]
+pub type DefaultBackend = crate::ffi::wrapper::Simulation;
+
+/// Set to stop a running scan; engines that support cancellation check it
+/// between scan lines and line-scan points.
+pub type CancelFlag = Arc<AtomicBool>;
+
+/// Called with the pixels (or line-scan points) finished so far and the total
+/// of the run in progress, after every scan line and line-scan point.
+pub type ProgressCallback = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// What an engine implements beyond the common interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub name: &'static str,
+    /// Honours the cancel flag during `run` and `run_line_scan`.
+    pub cancellation: bool,
+    /// Simulates height maps and volumetric sample geometry.
+    pub geometry: bool,
+    /// Records electron trajectories.
+    pub trajectories: bool,
+}
+
+/// One independent simulation: beam, sample and detector settings plus the
+/// buffers of its last run. Engines keep no state shared between instances, so
+/// several can run concurrently on different threads.
+pub trait SimulationBackend: Send {
+    /// Creates an engine for a beam of `energy` keV and `current` nA at a
+    /// working distance of `distance` mm, scanning `scan`.
+    fn new(energy: f64, current: f64, scan: &ScanRaster, distance: f64) -> Result<Self, SimError>
+    where
+        Self: Sized;
+
+    /// Which optional features the engine supports.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Sets the focused probe: spot FWHM (nm), convergence semi-angle (rad),
+    /// final aperture (µm), focal plane depth below the surface (nm) and pixel
+    /// dwell time (s). Electrons land with a Gaussian spread about the scan
+    /// position and blur by `convergence × |z - focus|` away from focus.
+    fn set_probe(&mut self, spot_size_nm: f64, convergence_rad: f64, aperture_diameter_um: f64,
+                 focus_depth_nm: f64, dwell_time_s: f64) -> Result<(), SimError>;
+
+    /// Predicts the probe from the electron source and objective lens at the
+    /// convergence set by `set_probe`: either the spot size for the beam current,
+    /// or with a target spot the beam current (and electrons per dwell) for it.
+    fn set_column(&mut self, column: &ColumnConfig) -> Result<(), SimError>;
+
+    /// The probe currently in effect.
+    fn probe(&self) -> Result<Probe, SimError>;
+
+    /// Simulates `count` primary electrons per pixel and per line-scan point
+    /// instead of the dose the beam current delivers in one dwell.
+    fn set_electrons_per_pixel(&mut self, count: Option<u32>) -> Result<(), SimError>;
+
+    /// Primary electrons simulated per pixel with the current settings.
+    fn electrons_per_pixel(&self) -> Result<usize, SimError>;
+
+    /// Selects the random sequence. Every pixel draws from its own stream of
+    /// the seed, so a run is reproducible bit for bit.
+    fn set_seed(&mut self, seed: u64) -> Result<(), SimError>;
+
+    /// Sets the sample material used by the scattering and energy-loss models.
+    ///
+    /// Each constituent element has its own cross-section and the scattering
+    /// element is picked per collision. The material's SE escape depth and
+    /// surface barrier drive the secondary-electron cascade.
+    fn set_material(&mut self, material: &Material) -> Result<(), SimError>;
+
+    /// Sets the sample topography, or restores a flat surface with `None`.
+    fn set_height_map(&mut self, map: Option<&HeightMap>) -> Result<(), SimError>;
+
+    /// Replaces the bulk sample with volumetric regions, or restores it with
+    /// `None`. Call after `set_material`, which clears the region materials.
+    fn set_geometry(&mut self, geometry: Option<&SampleGeometry>) -> Result<(), SimError>;
+
+    /// Sets the detector whose geometry and response form the detector channel.
+    fn set_detector(&mut self, detector: &DetectorConfig) -> Result<(), SimError>;
+
+    /// Records every collision vertex of the first `max_electrons` electrons of
+    /// each subsequent run. Passing 0 disables recording (the default).
+    fn set_trajectory_recording(&mut self, max_electrons: usize) -> Result<(), SimError>;
+
+    /// Stops subsequent runs early once `flag` is set. Pixels not reached stay
+    /// zero. Ignored by engines without the `cancellation` capability.
+    fn set_cancel_flag(&mut self, flag: CancelFlag);
+
+    /// Reports the progress of subsequent runs to `callback`, on the thread
+    /// running them.
+    fn set_progress_callback(&mut self, callback: ProgressCallback);
+
+    /// Scans the whole raster, filling the image channels.
+    fn run(&mut self) -> Result<(), SimError>;
+
+    /// Runs single-spot simulations at `n_points` beam positions from `start` to
+    /// `end` (x, y in nm) and returns the BSE/SE intensity profile.
+    fn run_line_scan(&mut self, start: (f64, f64), end: (f64, f64), n_points: i32)
+        -> Result<LineProfile, SimError>;
+
+    /// Final state of the electrons tracked in the last run (up to 100 000),
+    /// over every point of a line scan.
+    fn scatter_data(&self) -> Result<ScatterData, SimError>;
+
+    /// Trajectories recorded in the last run, if recording was enabled.
+    fn trajectories(&self) -> Result<Trajectories, SimError>;
+
+    /// The SE, BSE, detector and transmission channels of the last scan.
+    fn image_channels(&self) -> Result<ImageChannels, SimError>;
+}
+
+/// Rejects beam and raster settings no engine can simulate.
+pub(crate) fn check_beam(energy: f64, current: f64, scan: &ScanRaster, distance: f64) -> Result<(), SimError> {
+    if energy <= 0.0 || current <= 0.0 || distance <= 0.0 {
+        return Err(SimError::InvalidParameter(format!(
+            "beam of {} keV and {} nA at {} mm needs a positive energy, current and distance",
+            energy, current, distance
+        )));
+    }
+    if scan.width <= 0 || scan.height <= 0 || scan.field_of_view_nm <= 0.0 {
+        return Err(SimError::InvalidParameter(format!(
+            "scan raster of {}×{} px over {} nm must not be empty",
+            scan.width, scan.height, scan.field_of_view_nm
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects probe settings no engine can simulate.
+pub(crate) fn check_probe(spot_size_nm: f64, convergence_rad: f64, aperture_diameter_um: f64,
+                          dwell_time_s: f64) -> Result<(), SimError> {
+    if spot_size_nm < 0.0 || dwell_time_s <= 0.0 {
+        return Err(SimError::InvalidParameter(format!(
+            "probe spot ({} nm) must be >= 0 and dwell time ({} s) > 0",
+            spot_size_nm, dwell_time_s
+        )));
+    }
+    if convergence_rad <= 0.0 && aperture_diameter_um <= 0.0 {
+        return Err(SimError::InvalidParameter("probe needs a convergence angle or an aperture".into()));
+    }
+    Ok(())
+}
@@ -0,0 +1,430 @@
+//! Where electrons enter and leave the sample and which material they travel
+//! through: a bulk sample below a flat or height-mapped surface, or regions
+//! built from primitive shapes.
+
+use super::Vec3;
+use crate::sample::csg::{
+    CompiledGeometry, KIND_BOX, KIND_CYLINDER, KIND_HALF_SPACE, KIND_LAYER, KIND_SPHERE, OP_DIFFERENCE,
+    OP_INTERSECTION, OP_UNION, SHAPE_PARAMS,
+};
+use crate::sample::HeightMap;
+
+/// Bisection steps used to locate a surface crossing once it is bracketed.
+const CROSSING_ITERATIONS: usize = 30;
+/// Upper bound on march steps along one segment.
+const MAX_MARCH_STEPS: f64 = 10000.0;
+const MAX_STACK: usize = 64;
+/// Offset used to step off a boundary when probing the material beyond it (nm).
+const BOUNDARY_EPS: f64 = 1.0e-6;
+/// Fixed-point steps locating where a tilted beam ray meets a height map.
+const ENTRY_ITERATIONS: usize = 8;
+
+type ShapeParams = [f64; SHAPE_PARAMS];
+
+/// Surface heights (nm) on a grid centred on the beam axis, x fastest.
+struct Heights {
+    values: Vec<f64>,
+    nx: usize,
+    ny: usize,
+    spacing: f64,
+    origin: (f64, f64),
+}
+
+/// Constructive-solid-geometry regions, each a slice of a postfix program.
+struct Regions {
+    kinds: Vec<i32>,
+    params: Vec<ShapeParams>,
+    program: Vec<i32>,
+    /// (start, length) of each region's program.
+    spans: Vec<(usize, usize)>,
+    /// Medium index of each region.
+    materials: Vec<usize>,
+    /// Smallest z reached by any primitive.
+    scene_top: f64,
+}
+
+/// First change of material along a straight path.
+pub(crate) struct Boundary {
+    /// Distance to the crossing, `f64::MAX` without one.
+    pub distance: f64,
+    /// Material beyond the crossing, `None` for vacuum.
+    pub material: Option<usize>,
+    /// Cosine between the path and the boundary normal.
+    pub cos_normal: f64,
+}
+
+/// Without regions the sample is a bulk of medium 0 below the surface
+/// z = -h(x, y), with z increasing into the sample. With regions it is made of
+/// the regions alone, later ones taking precedence, and vacuum elsewhere.
+#[derive(Default)]
+pub(crate) struct Geometry {
+    heights: Option<Heights>,
+    height_min: f64,
+    regions: Option<Regions>,
+}
+
+pub(crate) fn along(p: Vec3, d: Vec3, t: f64) -> Vec3 {
+    [p[0] + t * d[0], p[1] + t * d[1], p[2] + t * d[2]]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+impl Geometry {
+    /// Installs a height map, or returns to the flat surface z = 0 with `None`.
+    pub fn set_height_map(&mut self, map: Option<&HeightMap>) {
+        self.heights = map.map(|map| Heights {
+            values: map.heights_nm.clone(),
+            nx: map.width,
+            ny: map.height,
+            spacing: map.pixel_size_nm,
+            origin: (
+                -0.5 * (map.width - 1) as f64 * map.pixel_size_nm,
+                -0.5 * (map.height - 1) as f64 * map.pixel_size_nm,
+            ),
+        });
+        self.height_min = map.map_or(0.0, |m| m.heights_nm.iter().copied().fold(f64::INFINITY, f64::min));
+    }
+
+    /// Installs regions whose materials are numbered from `first_medium`, or
+    /// returns to the bulk sample with `None`.
+    pub fn set_regions(&mut self, compiled: Option<&CompiledGeometry>, first_medium: usize) {
+        self.regions = compiled.map(|c| {
+            let params: Vec<ShapeParams> =
+                c.params.chunks_exact(SHAPE_PARAMS).map(|p| p.try_into().unwrap()).collect();
+            let mut start = 0;
+            let spans = c
+                .region_lengths
+                .iter()
+                .map(|&len| {
+                    let span = (start, len as usize);
+                    start += len as usize;
+                    span
+                })
+                .collect();
+            // Beams start just above the highest primitive
+            let scene_top = c.kinds.iter().zip(&params).map(|(&k, p)| shape_top(k, p)).fold(0.0, f64::min);
+            Regions {
+                kinds: c.kinds.clone(),
+                params,
+                program: c.program.clone(),
+                spans,
+                materials: c.region_materials.iter().map(|m| first_medium + m).collect(),
+                scene_top,
+            }
+        });
+    }
+
+    pub fn has_regions(&self) -> bool {
+        self.regions.is_some()
+    }
+
+    /// Lowest point of the surface (nm above z = 0).
+    pub fn height_min(&self) -> f64 {
+        self.height_min
+    }
+
+    /// Bilinearly interpolated surface height; edge values extend beyond the map.
+    fn surface_height(&self, x: f64, y: f64) -> f64 {
+        let Some(h) = &self.heights else { return 0.0 };
+        // 1-based grid coordinates, as in the Fortran engine
+        let gx = ((x - h.origin.0) / h.spacing + 1.0).clamp(1.0, h.nx as f64);
+        let gy = ((y - h.origin.1) / h.spacing + 1.0).clamp(1.0, h.ny as f64);
+        let i = (gx as usize).min((h.nx - 1).max(1));
+        let j = (gy as usize).min((h.ny - 1).max(1));
+        let (fx, fy) = (gx - i as f64, gy - j as f64);
+        let at = |i: usize, j: usize| h.values[(j - 1) * h.nx + i - 1];
+
+        let mut height = at(i, j);
+        if h.nx > 1 {
+            height += fx * (at(i + 1, j) - at(i, j));
+        }
+        if h.ny > 1 {
+            height += fy * (at(i, j + 1) - at(i, j));
+            if h.nx > 1 {
+                height += fx * fy * (at(i + 1, j + 1) - at(i + 1, j) - at(i, j + 1) + at(i, j));
+            }
+        }
+        height
+    }
+
+    /// Outward unit normal of the surface above (x, y), pointing into the vacuum.
+    fn surface_normal(&self, x: f64, y: f64) -> Vec3 {
+        let Some(map) = &self.heights else { return [0.0, 0.0, -1.0] };
+        // Central differences over one grid spacing
+        let h = 0.5 * map.spacing;
+        let grad_x = (self.surface_height(x + h, y) - self.surface_height(x - h, y)) / (2.0 * h);
+        let grad_y = (self.surface_height(x, y + h) - self.surface_height(x, y - h)) / (2.0 * h);
+        let norm = (1.0 + grad_x * grad_x + grad_y * grad_y).sqrt();
+        [-grad_x / norm, -grad_y / norm, -1.0 / norm]
+    }
+
+    fn is_inside(&self, p: Vec3) -> bool {
+        p[2] + self.surface_height(p[0], p[1]) > 0.0
+    }
+
+    /// Distance along a straight path from a point inside the bulk sample to
+    /// where it first leaves through the surface, or `f64::MAX` if it stays
+    /// inside for `max_distance`. Height maps are marched in half-spacing steps
+    /// and the crossing refined by bisection.
+    fn distance_to_surface(&self, p: Vec3, d: Vec3, max_distance: f64) -> f64 {
+        if max_distance <= 0.0 {
+            return f64::MAX;
+        }
+        let Some(h) = &self.heights else {
+            // Flat surface at z = 0
+            if d[2] < 0.0 {
+                let t_out = -p[2] / d[2];
+                if t_out <= max_distance {
+                    return t_out.max(0.0);
+                }
+            }
+            return f64::MAX;
+        };
+
+        // The whole segment stays below the lowest point of the surface
+        if p[2].min(p[2] + max_distance * d[2]) + self.height_min > 0.0 {
+            return f64::MAX;
+        }
+
+        let steps = (max_distance / (0.5 * h.spacing)).ceil().clamp(1.0, MAX_MARCH_STEPS) as usize;
+        let mut t_in = 0.0;
+        for k in 1..=steps {
+            let mut t_out = max_distance * k as f64 / steps as f64;
+            if !self.is_inside(along(p, d, t_out)) {
+                for _ in 0..CROSSING_ITERATIONS {
+                    let t_mid = 0.5 * (t_in + t_out);
+                    if self.is_inside(along(p, d, t_mid)) {
+                        t_in = t_mid;
+                    } else {
+                        t_out = t_mid;
+                    }
+                }
+                return t_out;
+            }
+            t_in = t_out;
+        }
+        f64::MAX
+    }
+
+    /// Evaluates a region's postfix program at a point.
+    fn region_contains(regions: &Regions, r: usize, p: Vec3) -> bool {
+        let mut stack = [false; MAX_STACK];
+        let mut top = 0;
+        let (start, len) = regions.spans[r];
+        for &op in &regions.program[start..start + len] {
+            if op > 0 {
+                top = (top + 1).min(MAX_STACK);
+                let k = op as usize - 1;
+                stack[top - 1] = shape_contains(regions.kinds[k], &regions.params[k], p);
+            } else if top >= 2 {
+                stack[top - 2] = match op {
+                    OP_UNION => stack[top - 2] || stack[top - 1],
+                    OP_DIFFERENCE => stack[top - 2] && !stack[top - 1],
+                    OP_INTERSECTION => stack[top - 2] && stack[top - 1],
+                    _ => stack[top - 2],
+                };
+                top -= 1;
+            }
+        }
+        top >= 1 && stack[top - 1]
+    }
+
+    /// Medium index at a point, or `None` in vacuum.
+    pub fn material_at(&self, p: Vec3) -> Option<usize> {
+        match &self.regions {
+            None => self.is_inside(p).then_some(0),
+            Some(regions) => (0..regions.spans.len())
+                .rev()
+                .find(|&r| Self::region_contains(regions, r, p))
+                .map(|r| regions.materials[r]),
+        }
+    }
+
+    /// Where a straight path through `current` first enters a different
+    /// material within `max_distance`. Bulk samples are never re-entered from vacuum.
+    pub fn next_boundary(&self, p: Vec3, d: Vec3, max_distance: f64, current: Option<usize>) -> Boundary {
+        let mut boundary = Boundary { distance: f64::MAX, material: current, cos_normal: 1.0 };
+
+        let Some(regions) = &self.regions else {
+            if current.is_none() {
+                return boundary;
+            }
+            let distance = self.distance_to_surface(p, d, max_distance);
+            if distance <= max_distance {
+                let crossing = along(p, d, distance);
+                boundary = Boundary {
+                    distance,
+                    material: None,
+                    cos_normal: dot(d, self.surface_normal(crossing[0], crossing[1])).abs(),
+                };
+            }
+            return boundary;
+        };
+
+        // Candidate crossings of every primitive ahead of the position, nearest first
+        let mut candidates: Vec<(f64, usize)> = Vec::new();
+        for (k, (&kind, params)) in regions.kinds.iter().zip(&regions.params).enumerate() {
+            let (t, n) = shape_crossings(kind, params, p, d);
+            candidates.extend(t[..n].iter().filter(|&&t| t > BOUNDARY_EPS && t <= max_distance).map(|&t| (t, k)));
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // The first candidate beyond which the material changes is the crossing
+        for (t, k) in candidates {
+            let material = self.material_at(along(p, d, t + BOUNDARY_EPS));
+            if material != current {
+                let normal = shape_normal(regions.kinds[k], &regions.params[k], along(p, d, t));
+                return Boundary { distance: t, material, cos_normal: dot(d, normal).abs() };
+            }
+        }
+        boundary
+    }
+
+    /// Point where a beam ray along `d` through `through` first enters the
+    /// sample, and the material there (`None` if it misses the sample).
+    pub fn beam_entry(&self, through: Vec3, d: Vec3) -> (Vec3, Option<usize>) {
+        let Some(regions) = &self.regions else {
+            // Solve z = -h(x, y) along the ray; exact after one step on a flat surface
+            let mut position = through;
+            for _ in 0..ENTRY_ITERATIONS {
+                let t = (-self.surface_height(position[0], position[1]) - through[2]) / d[2];
+                position = along(through, d, t);
+            }
+            return (position, Some(0));
+        };
+
+        // Start in vacuum above the highest primitive and fly to the first region
+        let position = along(through, d, (regions.scene_top - 1.0 - through[2]) / d[2]);
+        let entry = self.next_boundary(position, d, f64::MAX, None);
+        match entry.material {
+            Some(_) => (along(position, d, entry.distance), entry.material),
+            None => (position, None),
+        }
+    }
+}
+
+/// Smallest z reached by a primitive.
+fn shape_top(kind: i32, p: &ShapeParams) -> f64 {
+    match kind {
+        KIND_BOX => p[2],
+        KIND_SPHERE => p[2] - p[3],
+        KIND_CYLINDER => p[2] - p[5].abs() * p[7] - (1.0 - p[5] * p[5]).max(0.0).sqrt() * p[6],
+        _ => p[0],
+    }
+}
+
+fn shape_contains(kind: i32, p: &ShapeParams, point: Vec3) -> bool {
+    match kind {
+        KIND_BOX => (0..3).all(|i| point[i] >= p[i] && point[i] <= p[i + 3]),
+        KIND_SPHERE => (0..3).map(|i| (point[i] - p[i]).powi(2)).sum::<f64>() <= p[3] * p[3],
+        KIND_CYLINDER => {
+            let v = [point[0] - p[0], point[1] - p[1], point[2] - p[2]];
+            let h = dot(v, [p[3], p[4], p[5]]);
+            let radial = (0..3).map(|i| (v[i] - h * p[i + 3]).powi(2)).sum::<f64>();
+            h.abs() <= p[7] && radial <= p[6] * p[6]
+        }
+        KIND_LAYER => point[2] >= p[0] && point[2] <= p[1],
+        KIND_HALF_SPACE => point[2] >= p[0],
+        _ => false,
+    }
+}
+
+/// Unit normal of a primitive's surface at a point on it (sign unspecified).
+fn shape_normal(kind: i32, p: &ShapeParams, point: Vec3) -> Vec3 {
+    match kind {
+        KIND_BOX => {
+            // Face closest to the point
+            let mut nearest = 0;
+            let mut best = f64::INFINITY;
+            for face in 0..6 {
+                let gap = (point[face % 3] - p[face]).abs();
+                if gap < best {
+                    best = gap;
+                    nearest = face % 3;
+                }
+            }
+            let mut normal = [0.0; 3];
+            normal[nearest] = 1.0;
+            normal
+        }
+        KIND_SPHERE => [(point[0] - p[0]) / p[3], (point[1] - p[1]) / p[3], (point[2] - p[2]) / p[3]],
+        KIND_CYLINDER => {
+            let axis = [p[3], p[4], p[5]];
+            let v = [point[0] - p[0], point[1] - p[1], point[2] - p[2]];
+            let h = dot(v, axis);
+            let radial = [v[0] - h * axis[0], v[1] - h * axis[1], v[2] - h * axis[2]];
+            let r = dot(radial, radial).sqrt();
+            if (h.abs() - p[7]).abs() < (r - p[6]).abs() {
+                axis
+            } else if r > 0.0 {
+                [radial[0] / r, radial[1] / r, radial[2] / r]
+            } else {
+                [0.0, 0.0, 1.0]
+            }
+        }
+        _ => [0.0, 0.0, 1.0],
+    }
+}
+
+/// Distances along a ray at which it may cross a primitive's surface. Some lie
+/// on the surface's extension and are discarded by `next_boundary`.
+fn shape_crossings(kind: i32, p: &ShapeParams, o: Vec3, d: Vec3) -> ([f64; 6], usize) {
+    let mut t = [0.0; 6];
+    let mut n = 0;
+    match kind {
+        KIND_BOX => {
+            for i in 0..3 {
+                if d[i] != 0.0 {
+                    t[n] = (p[i] - o[i]) / d[i];
+                    t[n + 1] = (p[i + 3] - o[i]) / d[i];
+                    n += 2;
+                }
+            }
+        }
+        KIND_SPHERE => {
+            let v = [o[0] - p[0], o[1] - p[1], o[2] - p[2]];
+            let b = dot(v, d);
+            let disc = b * b - (dot(v, v) - p[3] * p[3]);
+            if disc >= 0.0 {
+                t[0] = -b - disc.sqrt();
+                t[1] = -b + disc.sqrt();
+                n = 2;
+            }
+        }
+        KIND_CYLINDER => {
+            let axis = [p[3], p[4], p[5]];
+            let v = [o[0] - p[0], o[1] - p[1], o[2] - p[2]];
+            let h0 = dot(v, axis);
+            let dh = dot(d, axis);
+            let o_perp = [v[0] - h0 * axis[0], v[1] - h0 * axis[1], v[2] - h0 * axis[2]];
+            let d_perp = [d[0] - dh * axis[0], d[1] - dh * axis[1], d[2] - dh * axis[2]];
+            let a = dot(d_perp, d_perp);
+            let b = dot(o_perp, d_perp);
+            let c = dot(o_perp, o_perp) - p[6] * p[6];
+            let disc = b * b - a * c;
+            if a > 0.0 && disc >= 0.0 {
+                t[0] = (-b - disc.sqrt()) / a;
+                t[1] = (-b + disc.sqrt()) / a;
+                n = 2;
+            }
+            if dh != 0.0 {
+                t[n] = (p[7] - h0) / dh;
+                t[n + 1] = (-p[7] - h0) / dh;
+                n += 2;
+            }
+        }
+        KIND_LAYER if d[2] != 0.0 => {
+            t[0] = (p[0] - o[2]) / d[2];
+            t[1] = (p[1] - o[2]) / d[2];
+            n = 2;
+        }
+        KIND_HALF_SPACE if d[2] != 0.0 => {
+            t[0] = (p[0] - o[2]) / d[2];
+            n = 1;
+        }
+        _ => {}
+    }
+    (t, n)
+}
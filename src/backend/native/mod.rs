@@ -0,0 +1,544 @@
+//! Pure-Rust Monte Carlo engine, a port of the Fortran library that needs no
+//! Fortran toolchain. It follows the same physics, random streams and scan
+//! order, so results agree with the Fortran engine statistically.
+mod geometry;
+mod physics;
+mod rng;
+mod signals;
+
+use std::f64::consts::PI;
+use std::sync::atomic::Ordering;
+
+use geometry::{along, Geometry};
+use physics::Medium;
+use rng::Rng;
+use signals::Detector;
+
+use super::{
+    check_beam, check_probe, CancelFlag, Capabilities, ElectronExit, ElectronFate, ImageChannels, LineProfile, Probe,
+    ProgressCallback, ScanRaster, ScatterData, SimulationBackend, TrajectoryEvent, TrajectoryVertex, Trajectories,
+};
+use crate::error::SimError;
+use crate::imaging::detector::DetectorConfig;
+use crate::materials::Material;
+use crate::sample::{HeightMap, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
+use crate::simulation::parameters::ELECTRONS_PER_NA_S;
+
+type Vec3 = [f64; 3];
+
+/// Final states kept in the scatter data per run.
+const MAX_RECORDED: u64 = 100_000;
+/// Electrons below this energy (keV) are absorbed.
+const CUTOFF_ENERGY: f64 = 0.1;
+/// Energy (eV) spent per slow secondary excited.
+const SE_EXCITATION_ENERGY: f64 = 15.0;
+/// Escape depths below which no secondary escapes.
+const SE_SAMPLING_DEPTH: f64 = 5.0;
+/// Interfaces a secondary may cross on its way out.
+const MAX_SE_CROSSINGS: usize = 8;
+/// FWHM of a Gaussian in units of its standard deviation.
+const FWHM_TO_SIGMA: f64 = 2.354_820_045_030_949_3;
+
+/// Pure-Rust engine state for one simulation.
+pub struct NativeEngine {
+    beam_energy: f64,
+    beam_current: f64,
+    working_distance: f64,
+    dwell_time: f64,
+    scan: ScanRaster,
+
+    // Focused probe: Gaussian spot in the focal plane, rays from a cone
+    spot_size: f64,
+    convergence: f64,
+    focus_depth: f64,
+
+    electrons_per_pixel: usize,
+    fixed_electrons: bool,
+    seed: u64,
+    rng: Rng,
+
+    /// Medium 0 fills a bulk sample; geometry regions refer to media by index.
+    media: Vec<Medium>,
+    geometry: Geometry,
+    detector: Detector,
+
+    channels: ImageChannels,
+    records: ScatterData,
+    record_capacity: usize,
+    trajectory_limit: usize,
+    trajectories: Trajectories,
+    cancel: Option<CancelFlag>,
+    progress: Option<ProgressCallback>,
+}
+
+impl NativeEngine {
+    /// Sets the dwell and, unless fixed, the electrons per pixel: the charge
+    /// the beam current delivers in one dwell.
+    fn set_dwell_time(&mut self, dwell_time: f64) {
+        self.dwell_time = dwell_time;
+        if !self.fixed_electrons {
+            let count = (self.beam_current * ELECTRONS_PER_NA_S * dwell_time).min(i32::MAX as f64).round();
+            self.electrons_per_pixel = (count as usize).max(1);
+        }
+    }
+
+    /// Empties the scatter data, keeping room for `count` final states or
+    /// `MAX_RECORDED`, whichever is fewer.
+    fn reserve_records(&mut self, count: u64) {
+        self.record_capacity = count.min(MAX_RECORDED) as usize;
+        self.records.clear();
+        self.records.reserve(self.record_capacity);
+    }
+
+    /// Point where a beam electron aimed at (x0, y0) crosses the focal plane,
+    /// and its direction within the convergence cone.
+    fn sample_probe_ray(&mut self, x0: f64, y0: f64) -> (Vec3, Vec3) {
+        // Gaussian landing position (Box-Muller)
+        let radius = self.spot_size / FWHM_TO_SIGMA * (-2.0 * (1.0 - self.rng.uniform()).ln()).sqrt();
+        let angle = 2.0 * PI * self.rng.uniform();
+        let focus = [x0 + radius * angle.cos(), y0 + radius * angle.sin(), self.focus_depth];
+
+        // Uniform aperture illumination: sin(theta) fills a disc of radius sin(alpha)
+        let sin_theta = self.convergence.sin() * self.rng.uniform().sqrt();
+        let phi = 2.0 * PI * self.rng.uniform();
+        let direction = [sin_theta * phi.cos(), sin_theta * phi.sin(), (1.0 - sin_theta * sin_theta).sqrt()];
+        (focus, direction)
+    }
+
+    /// Follows one primary aimed at (x0, y0) until it leaves the sample or
+    /// slows below the cutoff, returning its final state and the number of
+    /// secondaries that escaped along the way.
+    fn track_electron(&mut self, x0: f64, y0: f64) -> (ElectronExit, u32) {
+        let mut energy = self.beam_energy;
+        let mut se_count = 0;
+        let mut fate = ElectronFate::Absorbed;
+
+        let (focus, mut direction) = self.sample_probe_ray(x0, y0);
+        let (mut position, mut material) = self.geometry.beam_entry(focus, direction);
+
+        // Decide whether this electron's path is recorded
+        let trace = (self.trajectories.electrons.len() < self.trajectory_limit).then(|| {
+            self.trajectories.electrons.push(Vec::new());
+            self.trajectories.electrons.len() - 1
+        });
+        let traj = &mut self.trajectories;
+        add_vertex(traj, trace, position, energy, TrajectoryEvent::Entry);
+
+        if material.is_none() {
+            // The beam misses every region
+            fate = ElectronFate::Transmitted;
+            add_vertex(traj, trace, position, energy, TrajectoryEvent::Transmitted);
+        }
+
+        while let Some(current) = material {
+            let medium = &self.media[current];
+            let (mfp, atomic_number) = medium.sample_collision(energy, &mut self.rng);
+            let mut path_length = -mfp * (1.0 - self.rng.uniform()).ln();
+
+            // Stop at the boundary if this step leaves the current material
+            let boundary = self.geometry.next_boundary(position, direction, path_length, material);
+            let crossed = boundary.distance <= path_length;
+            if crossed {
+                path_length = boundary.distance;
+            }
+
+            // Move, losing energy continuously along the path
+            let start = position;
+            position = along(position, direction, path_length);
+            let loss = medium.energy_loss(energy, path_length, &mut self.rng).min(energy);
+            energy -= loss;
+            se_count += self.emit_secondaries(current, start, position, loss);
+
+            let traj = &mut self.trajectories;
+            let mut next_material = boundary.material;
+            if crossed && next_material.is_none() {
+                // Left through a surface: it escapes unless another region lies ahead
+                let ahead = self.geometry.next_boundary(position, direction, f64::MAX, None);
+                if ahead.material.is_none() {
+                    // A bulk sample can only be left through its top surface
+                    if direction[2] < 0.0 || !self.geometry.has_regions() {
+                        fate = ElectronFate::Backscattered;
+                        add_vertex(traj, trace, position, energy, TrajectoryEvent::Backscattered);
+                    } else {
+                        fate = ElectronFate::Transmitted;
+                        add_vertex(traj, trace, position, energy, TrajectoryEvent::Transmitted);
+                    }
+                    break;
+                }
+                add_vertex(traj, trace, position, energy, TrajectoryEvent::Boundary);
+                position = along(position, direction, ahead.distance);
+                next_material = ahead.material;
+            }
+            if energy <= CUTOFF_ENERGY {
+                add_vertex(traj, trace, position, energy, TrajectoryEvent::Absorbed);
+                break;
+            }
+            if crossed {
+                // Carry on in a straight line through the new material
+                material = next_material;
+                add_vertex(traj, trace, position, energy, TrajectoryEvent::Boundary);
+                continue;
+            }
+            add_vertex(traj, trace, position, energy, TrajectoryEvent::Elastic);
+
+            let (theta, phi) = physics::scatter_angles(&mut self.rng, atomic_number, energy);
+            physics::update_direction(&mut direction, theta, phi);
+        }
+
+        (ElectronExit { position, energy_kev: energy, direction, fate }, se_count)
+    }
+
+    /// Releases slow secondaries along a straight step through `material` that
+    /// deposits `energy_loss` keV and returns how many escape. For bulk samples
+    /// only the part of the step within reach of the surface is sampled.
+    fn emit_secondaries(&mut self, material: usize, start: Vec3, finish: Vec3, energy_loss: f64) -> u32 {
+        if energy_loss <= 0.0 {
+            return 0;
+        }
+
+        let (mut t_lo, mut t_hi) = (0.0, 1.0);
+        if !self.geometry.has_regions() {
+            // Points deeper than z_limit are out of reach of even the lowest surface point
+            let max_path = SE_SAMPLING_DEPTH * self.media[material].escape_depth_nm;
+            let z_top = start[2].min(finish[2]);
+            let z_limit = start[2].max(finish[2]).min(max_path - self.geometry.height_min());
+            if z_top >= z_limit {
+                return 0;
+            }
+            if finish[2] != start[2] {
+                let t_a = (z_top - start[2]) / (finish[2] - start[2]);
+                let t_b = (z_limit - start[2]) / (finish[2] - start[2]);
+                t_lo = t_a.min(t_b);
+                t_hi = t_a.max(t_b);
+            }
+        }
+
+        let mut escaped = 0;
+        let generated = physics::generate_secondaries(&mut self.rng, (t_hi - t_lo) * energy_loss, SE_EXCITATION_ENERGY);
+        for _ in 0..generated {
+            let t = t_lo + self.rng.uniform() * (t_hi - t_lo);
+            let mut position = [0, 1, 2].map(|i| start[i] + t * (finish[i] - start[i]));
+            let direction = physics::isotropic_direction(&mut self.rng);
+            let se_energy = physics::sample_se_energy(&mut self.rng, self.media[material].work_function_ev);
+
+            // Follow the secondary out, measuring its path in escape depths
+            let mut current = material;
+            let mut depth = 0.0;
+            for _ in 0..MAX_SE_CROSSINGS {
+                let escape_depth = self.media[current].escape_depth_nm;
+                let max_path = (SE_SAMPLING_DEPTH - depth) * escape_depth;
+                let boundary = self.geometry.next_boundary(position, direction, max_path, Some(current));
+                if boundary.distance > max_path {
+                    break;
+                }
+                depth += boundary.distance / escape_depth;
+                match boundary.material {
+                    None => {
+                        let work_function = self.media[current].work_function_ev;
+                        if physics::se_escapes(&mut self.rng, se_energy, depth, boundary.cos_normal, 1.0, work_function) {
+                            escaped += 1;
+                        }
+                        break;
+                    }
+                    Some(next) => {
+                        position = along(position, direction, boundary.distance);
+                        current = next;
+                    }
+                }
+            }
+        }
+        escaped
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    fn report_progress(&self, completed: usize, total: usize) {
+        if let Some(callback) = &self.progress {
+            callback(completed as u64, total as u64);
+        }
+    }
+
+    fn record_electron(&mut self, exit: ElectronExit) {
+        if self.records.len() < self.record_capacity {
+            self.records.push(exit);
+        }
+    }
+}
+
+/// Appends a trajectory vertex for a traced electron.
+fn add_vertex(trajectories: &mut Trajectories, trace: Option<usize>, position: Vec3, energy_kev: f64,
+              event: TrajectoryEvent) {
+    if let Some(id) = trace {
+        trajectories.electrons[id].push(TrajectoryVertex { position, energy_kev, event });
+    }
+}
+
+impl SimulationBackend for NativeEngine {
+    fn new(energy: f64, current: f64, scan: &ScanRaster, distance: f64) -> Result<Self, SimError> {
+        check_beam(energy, current, scan, distance)?;
+        log::debug!("Initializing Rust engine with {}keV beam energy, {}nA current, {}×{}px over {} nm",
+                 energy, current, scan.width, scan.height, scan.field_of_view_nm);
+        let pixels = scan.width.max(0) as usize * scan.height.max(0) as usize;
+        let mut engine = NativeEngine {
+            beam_energy: energy,
+            beam_current: current,
+            working_distance: distance,
+            dwell_time: 0.0,
+            scan: *scan,
+            spot_size: 0.0,
+            convergence: 0.0,
+            focus_depth: 0.0,
+            electrons_per_pixel: 1,
+            fixed_electrons: false,
+            seed: 0,
+            rng: Rng::new(0, 0),
+            media: Vec::new(),
+            geometry: Geometry::default(),
+            detector: Detector::new(&DetectorConfig::everhart_thornley()),
+            channels: ImageChannels {
+                se: vec![0.0; pixels],
+                bse: vec![0.0; pixels],
+                detector: vec![0.0; pixels],
+                transmitted: vec![0.0; pixels],
+                backscattered: vec![0.0; pixels],
+                width: scan.width.max(0) as usize,
+                height: scan.height.max(0) as usize,
+                electrons_per_pixel: 0,
+            },
+            records: Vec::new(),
+            record_capacity: 0,
+            trajectory_limit: 0,
+            trajectories: Trajectories::default(),
+            cancel: None,
+            progress: None,
+        };
+        // A 5 nm spot focused on the surface, 1 µs dwell, on pure silicon
+        engine.set_probe(5.0, 0.0, 30.0, 0.0, 1.0e-6)?;
+        engine.set_material(&Material::element("Silicon", 14, 28.085, 2.33, 173.0))?;
+        engine.media[0].escape_depth_nm = 2.0;
+        engine.media[0].work_function_ev = 4.05;
+        Ok(engine)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { name: "rust", cancellation: true, geometry: true, trajectories: true }
+    }
+
+    fn set_probe(&mut self, spot_size_nm: f64, convergence_rad: f64, aperture_diameter_um: f64,
+                 focus_depth_nm: f64, dwell_time_s: f64) -> Result<(), SimError> {
+        check_probe(spot_size_nm, convergence_rad, aperture_diameter_um, dwell_time_s)?;
+        self.spot_size = spot_size_nm.max(0.0);
+        self.focus_depth = focus_depth_nm;
+        // Without a convergence, the aperture seen from the focal plane sets it
+        self.convergence = if convergence_rad > 0.0 {
+            convergence_rad
+        } else {
+            0.5 * aperture_diameter_um * 1.0e-3 / self.working_distance
+        };
+        self.set_dwell_time(dwell_time_s);
+        Ok(())
+    }
+
+    fn set_column(&mut self, column: &ColumnConfig) -> Result<(), SimError> {
+        column.validate()?;
+        match column.target_spot_nm {
+            Some(target) if target > 0.0 => {
+                self.beam_current = column.probe_current_na(self.beam_energy, target, self.convergence)?;
+                self.spot_size = target;
+                self.set_dwell_time(self.dwell_time);
+            }
+            _ => {
+                self.spot_size = column.probe_diameter_nm(self.beam_energy, self.beam_current, self.convergence);
+            }
+        }
+        Ok(())
+    }
+
+    fn probe(&self) -> Result<Probe, SimError> {
+        Ok(Probe { spot_size_nm: self.spot_size, convergence_rad: self.convergence, current_na: self.beam_current })
+    }
+
+    fn set_electrons_per_pixel(&mut self, count: Option<u32>) -> Result<(), SimError> {
+        match count.filter(|&n| n > 0) {
+            Some(n) => {
+                self.fixed_electrons = true;
+                self.electrons_per_pixel = n as usize;
+            }
+            None => {
+                self.fixed_electrons = false;
+                self.set_dwell_time(self.dwell_time);
+            }
+        }
+        Ok(())
+    }
+
+    fn electrons_per_pixel(&self) -> Result<usize, SimError> {
+        Ok(self.electrons_per_pixel)
+    }
+
+    fn set_seed(&mut self, seed: u64) -> Result<(), SimError> {
+        self.seed = seed;
+        Ok(())
+    }
+
+    fn set_material(&mut self, material: &Material) -> Result<(), SimError> {
+        material.validate()?;
+        self.media.clear();
+        self.media.push(Medium::new(material));
+        Ok(())
+    }
+
+    fn set_height_map(&mut self, map: Option<&HeightMap>) -> Result<(), SimError> {
+        self.geometry.set_height_map(map);
+        Ok(())
+    }
+
+    fn set_geometry(&mut self, geometry: Option<&SampleGeometry>) -> Result<(), SimError> {
+        if let Some(geometry) = geometry {
+            geometry.validate()?;
+        }
+        let compiled = geometry.map(|g| g.compile());
+        let first_medium = self.media.len();
+        if let Some(compiled) = &compiled {
+            self.media.extend(compiled.materials.iter().map(Medium::new));
+        }
+        self.geometry.set_regions(compiled.as_ref(), first_medium);
+        Ok(())
+    }
+
+    fn set_detector(&mut self, detector: &DetectorConfig) -> Result<(), SimError> {
+        detector.validate()?;
+        self.detector = Detector::new(detector);
+        Ok(())
+    }
+
+    fn set_trajectory_recording(&mut self, max_electrons: usize) -> Result<(), SimError> {
+        self.trajectory_limit = max_electrons;
+        Ok(())
+    }
+
+    fn set_cancel_flag(&mut self, flag: CancelFlag) {
+        self.cancel = Some(flag);
+    }
+
+    fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
+    fn run(&mut self) -> Result<(), SimError> {
+        log::debug!("Starting Monte Carlo simulation");
+        let (width, height) = (self.channels.width, self.channels.height);
+        let n = self.electrons_per_pixel;
+        for channel in [&mut self.channels.se, &mut self.channels.bse, &mut self.channels.detector,
+                        &mut self.channels.transmitted, &mut self.channels.backscattered] {
+            channel.fill(0.0);
+        }
+        self.reserve_records(n as u64 * width as u64 * height as u64);
+        self.trajectories.electrons.clear();
+
+        // Square pixels spanning the field width, scanned in the rotated raster frame
+        let pixel_size = self.scan.pixel_size_nm();
+        let (sin_r, cos_r) = self.scan.rotation_deg.to_radians().sin_cos();
+        for j in 0..height {
+            if self.cancelled() {
+                break;
+            }
+            for i in 0..width {
+                let u = (i as f64 + 0.5 - 0.5 * width as f64) * pixel_size;
+                let v = (j as f64 + 0.5 - 0.5 * height as f64) * pixel_size;
+                let scan_x = self.scan.offset_nm.0 + u * cos_r - v * sin_r;
+                let scan_y = self.scan.offset_nm.1 + u * sin_r + v * cos_r;
+                let pixel = j * width + i;
+                self.rng = Rng::new(self.seed, pixel as u64 + 1);
+
+                // Signals are assigned to the pixel under the beam, wherever the electrons emerge
+                let mut collected = 0.0;
+                for _ in 0..n {
+                    let (exit, se_count) = self.track_electron(scan_x, scan_y);
+                    self.record_electron(exit);
+
+                    self.channels.se[pixel] += se_count as f64;
+                    collected += se_count as f64 * self.detector.se_signal();
+                    match exit.fate {
+                        ElectronFate::Backscattered => {
+                            let fraction = exit.energy_kev / self.beam_energy;
+                            self.channels.bse[pixel] += fraction;
+                            self.channels.backscattered[pixel] += 1.0;
+                            collected += self.detector.bse_signal(exit.direction, fraction);
+                        }
+                        ElectronFate::Transmitted => self.channels.transmitted[pixel] += 1.0,
+                        ElectronFate::Absorbed => {}
+                    }
+                }
+                self.channels.detector[pixel] = self.detector.response(&mut self.rng, collected, self.dwell_time);
+            }
+            self.report_progress((j + 1) * width, width * height);
+        }
+
+        // Express all channels as yields per primary electron
+        for channel in [&mut self.channels.se, &mut self.channels.bse, &mut self.channels.detector,
+                        &mut self.channels.transmitted, &mut self.channels.backscattered] {
+            channel.iter_mut().for_each(|value| *value /= n as f64);
+        }
+        Ok(())
+    }
+
+    fn run_line_scan(&mut self, start: (f64, f64), end: (f64, f64), n_points: i32)
+        -> Result<LineProfile, SimError> {
+        if n_points < 1 {
+            return Err(SimError::InvalidParameter(format!("line scan needs at least one point, got {}", n_points)));
+        }
+        log::debug!("Starting line scan from {:?} to {:?} nm with {} points", start, end, n_points);
+        self.trajectories.electrons.clear();
+        let n_points = n_points as usize;
+        let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+        let n = self.electrons_per_pixel;
+        self.reserve_records(n as u64 * n_points as u64);
+        let mut profile = LineProfile {
+            positions: Vec::with_capacity(n_points),
+            bse: Vec::with_capacity(n_points),
+            se: Vec::with_capacity(n_points),
+        };
+
+        for point in 0..n_points {
+            if self.cancelled() {
+                break;
+            }
+            let t = if n_points > 1 { point as f64 / (n_points - 1) as f64 } else { 0.0 };
+            let x = start.0 + t * (end.0 - start.0);
+            let y = start.1 + t * (end.1 - start.1);
+            self.rng = Rng::new(self.seed, point as u64 + 1);
+
+            // Every electron of the dwell at this beam position
+            let (mut se_total, mut bse_total) = (0u64, 0u64);
+            for _ in 0..n {
+                let (exit, se_count) = self.track_electron(x, y);
+                self.record_electron(exit);
+                se_total += se_count as u64;
+                if exit.fate == ElectronFate::Backscattered {
+                    bse_total += 1;
+                }
+            }
+            profile.positions.push(t * length);
+            profile.bse.push(bse_total as f64 / n as f64);
+            profile.se.push(se_total as f64 / n as f64);
+            self.report_progress(point + 1, n_points);
+        }
+        Ok(profile)
+    }
+
+    fn scatter_data(&self) -> Result<ScatterData, SimError> {
+        Ok(self.records.clone())
+    }
+
+    fn trajectories(&self) -> Result<Trajectories, SimError> {
+        Ok(self.trajectories.clone())
+    }
+
+    fn image_channels(&self) -> Result<ImageChannels, SimError> {
+        Ok(ImageChannels { electrons_per_pixel: self.electrons_per_pixel, ..self.channels.clone() })
+    }
+}
@@ -0,0 +1,192 @@
+//! Scattering, energy loss and secondary emission: screened Rutherford elastic
+//! scattering, the Joy-Luo Bethe stopping power and the Chung-Everhart SE spectrum.
+
+use std::f64::consts::PI;
+
+use super::rng::Rng;
+use super::Vec3;
+use crate::materials::Material;
+
+const AVOGADRO: f64 = 6.022_140_76e23; // mol⁻¹
+const REST_MASS_ENERGY: f64 = 511.0; // keV
+/// Safety cap on secondaries generated per step.
+const MAX_SE: u32 = 100;
+/// Upper limit of the SE spectrum in eV.
+const SE_MAX_ENERGY: f64 = 50.0;
+/// Bisection steps inverting the SE energy distribution.
+const SE_ENERGY_ITERATIONS: usize = 40;
+
+/// One element of a medium, with its weight fraction normalised.
+#[derive(Clone, Debug)]
+struct Element {
+    z: f64,
+    /// Atomic weight in g/mol.
+    a: f64,
+    fraction: f64,
+    /// Mean ionization energy in keV.
+    ionization_kev: f64,
+}
+
+/// A material as the transport loop sees it.
+#[derive(Clone, Debug)]
+pub(crate) struct Medium {
+    elements: Vec<Element>,
+    /// g/cm³
+    density: f64,
+    /// SE attenuation length λ_SE in nm.
+    pub escape_depth_nm: f64,
+    /// Surface barrier for SEs in eV.
+    pub work_function_ev: f64,
+}
+
+impl Medium {
+    pub fn new(material: &Material) -> Self {
+        let total: f64 = material.composition.iter().map(|c| c.weight_fraction).sum();
+        Medium {
+            elements: material
+                .composition
+                .iter()
+                .map(|c| Element {
+                    z: c.atomic_number as f64,
+                    a: c.atomic_weight,
+                    fraction: c.weight_fraction / total,
+                    ionization_kev: c.mean_ionization_ev * 1.0e-3,
+                })
+                .collect(),
+            density: material.density_g_cm3,
+            escape_depth_nm: material.secondary_emission.escape_depth_nm,
+            work_function_ev: material.secondary_emission.work_function_ev,
+        }
+    }
+
+    /// Inverse elastic mean free path of one element in 1/cm.
+    fn inverse_mfp(&self, element: &Element, energy: f64) -> f64 {
+        self.density * AVOGADRO * element.fraction / element.a * elastic_cross_section(element.z, energy)
+    }
+
+    /// Elastic mean free path (nm) at `energy` keV and the atomic number of the
+    /// element hit at the next collision, picked by its share of the cross-section.
+    pub fn sample_collision(&self, energy: f64, rng: &mut Rng) -> (f64, f64) {
+        let total: f64 = self.elements.iter().map(|e| self.inverse_mfp(e, energy)).sum();
+        let mfp = 1.0e7 / total; // cm to nm
+
+        let mut rand = rng.uniform() * total;
+        let mut hit = self.elements.last().map_or(0.0, |e| e.z);
+        for element in &self.elements {
+            rand -= self.inverse_mfp(element, energy);
+            if rand <= 0.0 {
+                hit = element.z;
+                break;
+            }
+        }
+        (mfp, hit)
+    }
+
+    /// Energy (keV) lost over `path_length` nm at `energy` keV from the Joy-Luo
+    /// modified Bethe formula, with ±10 % straggling.
+    pub fn energy_loss(&self, energy: f64, path_length: f64, rng: &mut Rng) -> f64 {
+        let sum: f64 = self
+            .elements
+            .iter()
+            .map(|e| {
+                let k = 0.731 + 0.0688 * e.z.log10();
+                e.fraction * e.z / e.a * (1.166 * (energy + k * e.ionization_kev) / e.ionization_kev).ln()
+            })
+            .sum();
+        let stopping_power = 78500.0 * self.density / energy * sum; // keV/cm
+        stopping_power * path_length * (1.0 + 0.1 * (2.0 * rng.uniform() - 1.0)) * 1.0e-7
+    }
+}
+
+/// Screening parameter of the screened Rutherford cross-section at `energy` keV.
+fn screening_parameter(atomic_number: f64, energy: f64) -> f64 {
+    3.4e-3 * atomic_number.powf(0.67) / energy
+}
+
+/// Screened Rutherford cross-section with relativistic correction, in cm².
+fn elastic_cross_section(atomic_number: f64, energy: f64) -> f64 {
+    let alpha = screening_parameter(atomic_number, energy);
+    5.21e-21 * atomic_number.powi(2) / energy.powi(2) * (4.0 * PI / (alpha * (1.0 + alpha)))
+        * ((energy + REST_MASS_ENERGY) / (energy + 2.0 * REST_MASS_ENERGY)).powi(2)
+}
+
+/// Polar and azimuthal scattering angles of an elastic collision.
+pub(crate) fn scatter_angles(rng: &mut Rng, atomic_number: f64, energy: f64) -> (f64, f64) {
+    let alpha = screening_parameter(atomic_number, energy);
+    let rand = rng.uniform();
+    let theta = (1.0 - 2.0 * alpha * rand / (1.0 + alpha - rand)).acos();
+    (theta, 2.0 * PI * rng.uniform())
+}
+
+/// Rotates a unit direction by (theta, phi) about itself.
+pub(crate) fn update_direction(d: &mut Vec3, theta: f64, phi: f64) {
+    let (sin_t, cos_t) = theta.sin_cos();
+    let (sin_p, cos_p) = phi.sin_cos();
+    let perp = (1.0 - d[2] * d[2]).max(0.0).sqrt();
+
+    *d = if perp < 1.0e-10 {
+        // Travelling along the z-axis: rotate in the fixed frame
+        [sin_t * cos_p, sin_t * sin_p, cos_t.abs().copysign(d[2])]
+    } else {
+        [
+            d[0] * cos_t + sin_t * (d[0] * d[2] * cos_p - d[1] * sin_p) / perp,
+            d[1] * cos_t + sin_t * (d[1] * d[2] * cos_p + d[0] * sin_p) / perp,
+            d[2] * cos_t - perp * sin_t * cos_p,
+        ]
+    };
+}
+
+/// Unit vector uniformly distributed over the sphere.
+pub(crate) fn isotropic_direction(rng: &mut Rng) -> Vec3 {
+    let cos_theta = 2.0 * rng.uniform() - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.uniform();
+    [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta]
+}
+
+/// Poisson-distributed number of slow secondaries excited when `deposited_kev`
+/// is lost, each costing `excitation_ev` on average.
+pub(crate) fn generate_secondaries(rng: &mut Rng, deposited_kev: f64, excitation_ev: f64) -> u32 {
+    let mean = deposited_kev * 1000.0 / excitation_ev;
+    if mean <= 0.0 {
+        return 0;
+    }
+    // Knuth's multiplication method
+    let threshold = (-mean).exp();
+    let mut count = 0;
+    let mut product = rng.uniform();
+    while product > threshold && count < MAX_SE {
+        count += 1;
+        product *= rng.uniform();
+    }
+    count
+}
+
+/// Vacuum kinetic energy (eV) of a secondary from the Chung-Everhart spectrum
+/// N(E) ~ E / (E + φ)⁴, truncated at `SE_MAX_ENERGY`.
+pub(crate) fn sample_se_energy(rng: &mut Rng, work_function: f64) -> f64 {
+    let cdf = |e: f64| 1.0 - work_function.powi(2) * (3.0 * e + work_function) / (e + work_function).powi(3);
+    let target = rng.uniform() * cdf(SE_MAX_ENERGY);
+    let (mut lo, mut hi) = (0.0, SE_MAX_ENERGY);
+    let mut energy = 0.0;
+    for _ in 0..SE_ENERGY_ITERATIONS {
+        energy = 0.5 * (lo + hi);
+        if cdf(energy) < target {
+            lo = energy;
+        } else {
+            hi = energy;
+        }
+    }
+    energy
+}
+
+/// Whether a secondary of `energy` eV reaching the surface after `path` nm
+/// leaves the sample: it must survive attenuation exp(-path / escape_depth) and
+/// meet the surface steeply enough to cross the barrier.
+pub(crate) fn se_escapes(rng: &mut Rng, energy: f64, path: f64, cos_normal: f64, escape_depth: f64,
+                         work_function: f64) -> bool {
+    if cos_normal <= 0.0 || (energy + work_function) * cos_normal.powi(2) <= work_function {
+        return false;
+    }
+    rng.uniform() < (-path / escape_depth).exp()
+}
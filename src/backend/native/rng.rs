@@ -0,0 +1,63 @@
+//! Seeded xoshiro128** streams, seeded the same way as the Fortran engine's
+//! `random_streams` module so each pixel draws from its own reproducible stream.
+
+const GOLDEN32: u32 = 0x9e37_79b9; // 2^32 / golden ratio
+const SEED_ROUNDS: usize = 4;
+const TWO_POW_21: f64 = 2_097_152.0;
+const TWO_POW_M53: f64 = 1.0 / 9_007_199_254_740_992.0;
+
+/// State of one xoshiro128** stream.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng {
+    s: [u32; 4],
+}
+
+impl Rng {
+    /// Starts stream `stream` of the sequence selected by `seed`. Invertible
+    /// mixing rounds spread both keys over the whole state, so distinct
+    /// (seed, stream) pairs always give distinct states.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut s = [seed as u32, (seed >> 32) as u32, stream as u32, (stream >> 32) as u32];
+        for _ in 0..SEED_ROUNDS {
+            for i in 0..4 {
+                s[i] = mix32(s[i].wrapping_add(GOLDEN32.wrapping_mul(i as u32 + 1))) ^ s[(i + 1) % 4];
+            }
+        }
+        // The all-zero state is the generator's only fixed point
+        if s == [0; 4] {
+            s[0] = 1;
+        }
+        Rng { s }
+    }
+
+    /// Uniform deviate in [0, 1) with 53 random bits.
+    pub fn uniform(&mut self) -> f64 {
+        let high = self.next_u32();
+        let low = self.next_u32();
+        (high as f64 * TWO_POW_21 + (low >> 11) as f64) * TWO_POW_M53
+    }
+
+    /// Next 32-bit output of xoshiro128** (Blackman & Vigna).
+    fn next_u32(&mut self) -> u32 {
+        let s = &mut self.s;
+        let word = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 9;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(11);
+        word
+    }
+}
+
+/// MurmurHash3 finaliser: a bijection on 32-bit words with full avalanche.
+fn mix32(x: u32) -> u32 {
+    let mut h = x ^ (x >> 16);
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
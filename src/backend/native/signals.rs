@@ -0,0 +1,72 @@
+//! Detector geometry and response forming the detector channel.
+
+use std::f64::consts::PI;
+
+use super::rng::Rng;
+use super::Vec3;
+use crate::imaging::detector::DetectorConfig;
+
+/// A detector with its acceptance cone derived from the geometry.
+pub(crate) struct Detector {
+    /// Unit vector from the sample to the detector.
+    axis: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+    se_efficiency: f64,
+    bse_efficiency: f64,
+    gain: f64,
+    dark_current: f64,
+    noise_level: f64,
+    time_constant_s: f64,
+}
+
+impl Detector {
+    pub fn new(config: &DetectorConfig) -> Self {
+        // Detector axis, pointing up out of the sample (z increases into the sample)
+        let elevation = config.elevation_deg.to_radians();
+        let azimuth = config.azimuth_deg.to_radians();
+        Detector {
+            axis: [elevation.cos() * azimuth.cos(), elevation.cos() * azimuth.sin(), -elevation.sin()],
+            cos_inner: config.inner_radius_mm.atan2(config.distance_mm).cos(),
+            cos_outer: config.outer_radius_mm.atan2(config.distance_mm).cos(),
+            se_efficiency: config.se_efficiency,
+            bse_efficiency: config.bse_efficiency,
+            gain: config.gain,
+            dark_current: config.dark_current,
+            noise_level: config.noise_level,
+            time_constant_s: config.time_constant_s,
+        }
+    }
+
+    /// Signal of one escaping secondary; the collector field pulls them in
+    /// whatever their direction.
+    pub fn se_signal(&self) -> f64 {
+        self.se_efficiency
+    }
+
+    /// Signal of one backscattered electron, counted only inside the
+    /// acceptance cone and weighted by its exit energy over the beam energy.
+    pub fn bse_signal(&self, direction: Vec3, energy_fraction: f64) -> f64 {
+        let cos_angle = direction[0] * self.axis[0] + direction[1] * self.axis[1] + direction[2] * self.axis[2];
+        if cos_angle <= self.cos_inner && cos_angle >= self.cos_outer {
+            self.bse_efficiency * energy_fraction
+        } else {
+            0.0
+        }
+    }
+
+    /// Amplifier output for the signal collected over one pixel dwell, with
+    /// shot and thermal noise, dark current and the detector's time response.
+    pub fn response(&self, rng: &mut Rng, signal: f64, dwell_time_s: f64) -> f64 {
+        let response_factor = 1.0 - (-dwell_time_s / self.time_constant_s).exp();
+
+        // Box-Muller pair
+        let gauss_r = (-2.0 * (1.0 - rng.uniform()).ln()).sqrt();
+        let angle = 2.0 * PI * rng.uniform();
+        let thermal_noise = self.noise_level * gauss_r * angle.cos();
+        let shot_noise = signal.abs().sqrt() * self.noise_level * gauss_r * angle.sin();
+
+        let measured = self.gain * (response_factor * signal + shot_noise + thermal_noise + self.dark_current);
+        measured.max(0.0)
+    }
+}
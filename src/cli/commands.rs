@@ -0,0 +1,228 @@
+// src/cli/commands.rs
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+
+use QuantFocus::error::SimError;
+use QuantFocus::imaging::export;
+use QuantFocus::materials::{get_preset_material, list_preset_names};
+use QuantFocus::simulation::parameters::SimulationParameters;
+use QuantFocus::simulation::results::SimulationResult;
+use QuantFocus::simulation::sweep::linspace;
+use QuantFocus::simulation::{
+    run_line_scan, JobFile, JobHandle, ParameterSweep, Progress, SimulationManager, SweepAxis,
+};
+
+use super::ParamArgs;
+
+/// Simulate one image and save it as PNG, with the trajectories if the job
+/// file asks for them.
+pub fn run(args: &ParamArgs, output: Option<&Path>) -> Result<(), SimError> {
+    let job = job(args)?;
+    let manager = SimulationManager::new();
+    manager.enqueue(job.params);
+    let result = wait_with_progress(manager.start())
+        .pop()
+        .expect("one job was enqueued")?;
+    let image = output.or(job.output.image.as_deref()).unwrap_or(Path::new("sem.png"));
+    save(&result, image)?;
+    if let Some(path) = &job.output.trajectories_csv {
+        result.save_trajectories_csv(&path.to_string_lossy())?;
+        println!("Wrote {} trajectories to {}", result.trajectories.electrons.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Simulate every point of the grid spanned by `axes`, saving one image per
+/// point and their metrics as `metrics.csv` into `output_dir`.
+pub fn sweep(args: &ParamArgs, axes: Vec<SweepAxis>, output_dir: &Path) -> Result<(), SimError> {
+    let sweep = axes.into_iter().fold(ParameterSweep::new(parameters(args)?), ParameterSweep::with_axis);
+    let handle = SimulationManager::new().start_sweep(&sweep)?;
+    show_progress(|| handle.is_finished(), || handle.progress());
+    let results = handle.wait();
+
+    fs::create_dir_all(output_dir)?;
+    let mut failed = 0;
+    for row in &results.rows {
+        let label = results
+            .axes
+            .iter()
+            .zip(&row.coordinates)
+            .map(|(axis, value)| format!("{}_{}", axis, file_safe(value)))
+            .collect::<Vec<_>>()
+            .join("_");
+        let outcome = match &row.result {
+            Ok(result) => save(result, &output_dir.join(format!("{}.png", label))),
+            Err(e) => Err(e.clone()),
+        };
+        if let Err(e) = outcome {
+            eprintln!("{}: {}", label, e);
+            failed += 1;
+        }
+    }
+    let table = output_dir.join("metrics.csv");
+    results.save_metrics_csv(&table.to_string_lossy())?;
+    println!("Wrote metrics of {} points to {}", results.rows.len(), table.display());
+    if failed > 0 {
+        return Err(SimError::InvalidParameter(format!("{} of {} sweep points failed", failed, results.rows.len())));
+    }
+    Ok(())
+}
+
+/// Simulate a line scan and save its profile as CSV.
+pub fn linescan(args: &ParamArgs, from: (f64, f64), to: (f64, f64), points: i32, output: &Path) -> Result<(), SimError> {
+    let profile = run_line_scan(&parameters(args)?, from, to, points)?;
+    export::save_line_profile_csv(&output.to_string_lossy(), &profile)?;
+    println!("Wrote {} points to {}", profile.positions.len(), output.display());
+    if let Some(width) = profile.bse_edge_width() {
+        println!("BSE edge width (20–80 %): {:.2} nm", width);
+    }
+    Ok(())
+}
+
+/// Print the material presets.
+pub fn list_materials() {
+    println!("{:<20} {:>12} {:>8}", "Name", "g/cm3", "Z_eff");
+    for name in list_preset_names() {
+        let material = get_preset_material(name).expect("listed presets exist");
+        println!("{:<20} {:>12.3} {:>8.2}", name, material.density_g_cm3, material.effective_atomic_number());
+    }
+    println!("Any other element is available by symbol or name, e.g. Au or gold.");
+}
+
+/// Print the size and embedded parameters of a PNG.
+pub fn inspect(file: &Path) -> Result<(), SimError> {
+    let png = export::read_png_metadata(&file.to_string_lossy())?;
+    println!("{}: {}×{} px", file.display(), png.width, png.height);
+    if png.metadata.is_empty() {
+        println!("No simulation metadata");
+    }
+    for (keyword, text) in png.metadata {
+        println!("{:<20} {}", keyword, text);
+    }
+    Ok(())
+}
+
+/// Parameters of the job file, or the defaults, with the given flags applied.
+fn parameters(args: &ParamArgs) -> Result<SimulationParameters, SimError> {
+    job(args).map(|job| job.params)
+}
+
+/// The job file, or the defaults, with the given flags applied. The flags
+/// are job file overrides, so whatever the job derives from them (electron
+/// count from dose, tilted plane from field width) follows the flags.
+fn job(args: &ParamArgs) -> Result<JobFile, SimError> {
+    let name = |value: Option<PossibleValue>| value.map(|v| v.get_name().to_string());
+    let flags = [
+        ("beam.energy_kev", args.energy.map(|v| v.to_string())),
+        ("beam.current_na", args.current.map(|v| v.to_string())),
+        ("beam.distance_mm", args.distance.map(|v| v.to_string())),
+        ("scan.width", args.resolution.map(|v| v.to_string())),
+        ("scan.height", args.height.map(|v| v.to_string())),
+        ("scan.field_of_view_um", args.fov.map(|v| v.to_string())),
+        ("scan.dwell_us", args.dwell.map(|v| v.to_string())),
+        ("scan.electrons_per_pixel", args.electrons.map(|v| v.to_string())),
+        ("seed", args.seed.map(|v| v.to_string())),
+        ("sample.material", args.material.clone()),
+        ("detector.signal", name(args.signal.and_then(|v| v.to_possible_value()))),
+        ("detector.se_weight", args.se_weight.map(|v| v.to_string())),
+        ("detector.preset", name(args.detector.and_then(|v| v.to_possible_value()))),
+    ];
+    let overrides: Vec<(String, String)> = args
+        .overrides
+        .iter()
+        .cloned()
+        .chain(flags.into_iter().filter_map(|(key, value)| Some((key.to_string(), value?))))
+        .collect();
+    match &args.job {
+        Some(path) => JobFile::load_with_overrides(path, &overrides),
+        None => JobFile::from_overrides(&overrides),
+    }
+}
+
+/// Parse a sweep axis given as `name=v1,v2,...` or `name=start:end:count`.
+pub fn parse_axis(text: &str) -> Result<SweepAxis, String> {
+    let (name, values) = text
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=values, got {:?}", text))?;
+    let numbers = || -> Result<Vec<f64>, String> {
+        let parse = |v: &str| v.trim().parse::<f64>().map_err(|_| format!("invalid number {:?}", v));
+        match values.split(':').collect::<Vec<_>>()[..] {
+            [start, end, count] => {
+                let count = count.trim().parse().map_err(|_| format!("invalid point count {:?}", count))?;
+                Ok(linspace(parse(start)?, parse(end)?, count))
+            }
+            _ => values.split(',').map(parse).collect(),
+        }
+    };
+    let integers = |kind: &str| -> Result<Vec<f64>, String> {
+        let values = numbers()?;
+        match values.iter().find(|v| v.fract() != 0.0 || **v < 0.0) {
+            Some(v) => Err(format!("{} must be whole numbers, got {}", kind, v)),
+            None => Ok(values),
+        }
+    };
+    match name.trim() {
+        "energy" => Ok(SweepAxis::Energy(numbers()?)),
+        "current" => Ok(SweepAxis::Current(numbers()?)),
+        "resolution" => Ok(SweepAxis::Resolution(integers("resolutions")?.iter().map(|&v| v as i32).collect())),
+        "tilt" => Ok(SweepAxis::Tilt(numbers()?)),
+        "dwell" => Ok(SweepAxis::DwellTime(numbers()?)),
+        "fov" => Ok(SweepAxis::FieldOfView(numbers()?)),
+        "seed" => Ok(SweepAxis::Seed(integers("seeds")?.iter().map(|&v| v as u64).collect())),
+        "material" => values
+            .split(',')
+            .map(|name| {
+                get_preset_material(name.trim())
+                    .ok_or_else(|| format!("unknown material {}; see `materials list`", name.trim()))
+            })
+            .collect::<Result<_, _>>()
+            .map(SweepAxis::Material),
+        other => Err(format!(
+            "unknown axis {}; expected energy, current, resolution, tilt, material, dwell, fov or seed",
+            other
+        )),
+    }
+}
+
+/// `text` with characters that are awkward in file names replaced.
+fn file_safe(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+        .collect()
+}
+
+/// Wait for a batch, printing its progress and remaining time to stderr.
+fn wait_with_progress(job: JobHandle) -> Vec<Result<SimulationResult, SimError>> {
+    show_progress(|| job.is_finished(), || job.progress());
+    job.wait()
+}
+
+/// Print the progress of a background batch to stderr until it is finished.
+fn show_progress(is_finished: impl Fn() -> bool, progress: impl Fn() -> Progress) {
+    while !is_finished() {
+        thread::sleep(Duration::from_millis(500));
+        let progress = progress();
+        match progress.eta() {
+            Some(eta) => eprintln!("{:5.1}% done, about {:.0} s left", 100.0 * progress.fraction(), eta.as_secs_f64()),
+            None => eprintln!("{:5.1}% done", 100.0 * progress.fraction()),
+        }
+    }
+}
+
+fn save(result: &SimulationResult, output: &Path) -> Result<(), SimError> {
+    result.save_png(&output.to_string_lossy())?;
+    println!("Wrote {}×{} image to {}", result.width, result.height, output.display());
+    if result.sample_ignored {
+        eprintln!("warning: the engine ignores sample geometry, {} shows a flat bulk sample", output.display());
+    }
+    if result.cancelled {
+        eprintln!("warning: the job was cancelled, {} is a partial image", output.display());
+    }
+    Ok(())
+}
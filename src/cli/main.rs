@@ -0,0 +1,176 @@
+// src/cli/main.rs
+
+// Headless front end: everything the SDL viewer does, driven by flags and
+// written to files, so simulations can run on servers without a display.
+
+mod commands;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use QuantFocus::simulation::SweepAxis;
+
+#[derive(Parser)]
+#[command(name = "quantfocus-cli", version, about = "Headless SEM image simulation")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Simulate one image and save it as PNG with its parameters embedded
+    Run {
+        #[command(flatten)]
+        params: ParamArgs,
+        /// PNG file to write [default: the job's output.image, or sem.png]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Simulate one image per point of a grid of parameter values
+    Sweep {
+        #[command(flatten)]
+        params: ParamArgs,
+        /// Axis of the grid as `name=v1,v2,...` or `name=start:end:count`, where
+        /// name is energy, current, resolution, tilt, material, dwell, fov or seed.
+        /// Repeat for a Cartesian grid.
+        #[arg(long = "axis", value_name = "NAME=VALUES", required = true, value_parser = commands::parse_axis)]
+        axes: Vec<SweepAxis>,
+        /// Directory receiving one PNG per point and `metrics.csv`
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+    },
+    /// Step the beam along a line and save the BSE and SE profile as CSV
+    Linescan {
+        #[command(flatten)]
+        params: ParamArgs,
+        /// Start of the line as x,y in nm
+        #[arg(long, value_delimiter = ',', num_args = 2, default_values_t = [-500.0, 0.0])]
+        from: Vec<f64>,
+        /// End of the line as x,y in nm
+        #[arg(long, value_delimiter = ',', num_args = 2, default_values_t = [500.0, 0.0])]
+        to: Vec<f64>,
+        /// Beam positions along the line
+        #[arg(long, default_value_t = 100)]
+        points: i32,
+        /// CSV file to write
+        #[arg(short, long, default_value = "linescan.csv")]
+        output: PathBuf,
+    },
+    /// Browse the material presets
+    Materials {
+        #[command(subcommand)]
+        command: MaterialsCommand,
+    },
+    /// Print the size and simulation parameters embedded in a PNG
+    Inspect {
+        /// PNG written by `run` or `sweep`
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MaterialsCommand {
+    /// List the presets with their density and mean atomic number
+    List,
+}
+
+/// Simulation parameters shared by the simulating subcommands. Flags override
+/// the values of the job file.
+#[derive(Args)]
+struct ParamArgs {
+    /// Job file (TOML, JSON or YAML) describing the beam, scan, sample, detector and output
+    #[arg(long)]
+    job: Option<PathBuf>,
+    /// Override a key of the job file, e.g. beam.energy_kev=15; repeatable
+    #[arg(long = "set", value_name = "KEY=VALUE", requires = "job", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+    /// Beam energy in keV [default: 20]
+    #[arg(long)]
+    energy: Option<f64>,
+    /// Beam current in nA [default: 1]
+    #[arg(long)]
+    current: Option<f64>,
+    /// Image width in pixels [default: 512]
+    #[arg(long)]
+    resolution: Option<i32>,
+    /// Image height in pixels, for rectangular scans
+    #[arg(long)]
+    height: Option<i32>,
+    /// Working distance in mm [default: 10]
+    #[arg(long)]
+    distance: Option<f64>,
+    /// Horizontal field width in µm
+    #[arg(long)]
+    fov: Option<f64>,
+    /// Pixel dwell time in µs
+    #[arg(long)]
+    dwell: Option<f64>,
+    /// Primary electrons per pixel instead of the dose-derived count
+    #[arg(long)]
+    electrons: Option<u32>,
+    /// Seed of the random streams
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Material preset or element symbol, e.g. Silicon, SiO2 or Au
+    #[arg(long)]
+    material: Option<String>,
+    /// Signal rendered into the image
+    #[arg(long, value_enum)]
+    signal: Option<SignalArg>,
+    /// SE share of the mixed signal, from 0 to 1 [default: 0.5]
+    #[arg(long)]
+    se_weight: Option<f64>,
+    /// Detector forming the detector signal
+    #[arg(long, value_enum)]
+    detector: Option<DetectorArg>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SignalArg {
+    Se,
+    Bse,
+    Detector,
+    Transmitted,
+    /// SE and BSE blended by --se-weight
+    Mixed,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DetectorArg {
+    EverhartThornley,
+    InLens,
+    AnnularBse,
+}
+
+fn parse_override(text: &str) -> Result<(String, String), String> {
+    text.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", text))
+}
+
+fn main() -> ExitCode {
+    // Engine diagnostics go to stderr, enabled with RUST_LOG
+    env_logger::init();
+    let cli = Cli::parse();
+    let outcome = match cli.command {
+        Command::Run { params, output } => commands::run(&params, output.as_deref()),
+        Command::Sweep { params, axes, output_dir } => commands::sweep(&params, axes, &output_dir),
+        Command::Linescan { params, from, to, points, output } => {
+            commands::linescan(&params, (from[0], from[1]), (to[0], to[1]), points, &output)
+        }
+        Command::Materials { command: MaterialsCommand::List } => {
+            commands::list_materials();
+            Ok(())
+        }
+        Command::Inspect { file } => commands::inspect(&file),
+    };
+    match outcome {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Error type shared by parameter validation, the engines and export.
+
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong setting up, running or saving a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimError {
+    /// A parameter is out of range or inconsistent; the message names it.
+    InvalidParameter(String),
+    /// The engine could not allocate its context or result buffers.
+    OutOfMemory,
+    /// Results were requested before a run produced them.
+    NoData(&'static str),
+    /// The engine returned buffers that do not match the documented layout.
+    InvalidData(String),
+    /// An engine call failed with a status that has no more specific variant.
+    Engine { call: &'static str, status: i32 },
+    /// Reading or writing a file failed.
+    Io(String),
+    /// A job file is unreadable or invalid; `line` (1-based) locates the
+    /// offending entry when it is known.
+    JobFile { path: String, line: Option<usize>, message: String },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::InvalidParameter(message) => f.write_str(message),
+            SimError::OutOfMemory => f.write_str("simulation engine ran out of memory"),
+            SimError::NoData(what) => write!(f, "no {} available; run the simulation first", what),
+            SimError::InvalidData(message) => write!(f, "engine returned malformed data: {}", message),
+            SimError::Engine { call, status } => write!(f, "{} failed with status {}", call, status),
+            SimError::Io(message) => f.write_str(message),
+            SimError::JobFile { path, line: Some(line), message } => write!(f, "{}:{}: {}", path, line, message),
+            SimError::JobFile { path, line: None, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<io::Error> for SimError {
+    fn from(e: io::Error) -> Self {
+        SimError::Io(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for SimError {
+    fn from(e: image::ImageError) -> Self {
+        SimError::Io(e.to_string())
+    }
+}
@@ -1,92 +1,459 @@
+use std::os::raw::{c_int, c_void};
 use std::ptr;
 use std::slice;
+use std::sync::atomic::Ordering;
 
+use crate::backend::{
+    CancelFlag, Capabilities, ElectronExit, ElectronFate, ImageChannel, ImageChannels, LineProfile, Probe, ProgressCallback,
+    ScanRaster, ScatterData, SimulationBackend, TrajectoryEvent, TrajectoryVertex, Trajectories,
+};
+use crate::error::SimError;
 use crate::ffi::bindings;
+use crate::imaging::detector::DetectorConfig;
+use crate::materials::Material;
+use crate::sample::{HeightMap, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
 
-/// Represents a 2D scattering data result from the simulation.
-pub struct ScatterData {
-    pub data: Vec<f64>,
-    pub rows: usize,
-    pub cols: usize,
+/// Owned handle to an independent simulation context in the Fortran engine.
+///
+/// Every handle carries its own beam parameters and result buffers, so
+/// several simulations can be initialized and run concurrently. The context
+/// is released when the handle is dropped.
+pub struct Simulation {
+    ctx: *mut bindings::sem_sim_context,
+    /// Beam energy in keV, to explain a probe the column cannot form.
+    energy_kev: f64,
+    /// Boxed so the address registered with the engine survives moves.
+    hook: Box<ProgressHook>,
 }
 
-/// Initializes the SEM simulation with the specified parameters.
-///
-/// # Arguments
-/// - `energy` – Beam energy in keV
-/// - `current` – Beam current in nA 
-/// - `resolution` – Image resolution in pixels
-/// - `distance` – Working distance in mm
-pub fn init_simulation(energy: f64, current: f64, resolution: i32, distance: f64) {
-    println!("Initializing simulation with {}keV beam energy, {}nA current, {}px resolution", 
-             energy, current, resolution);
-    unsafe {
-        bindings::c_init_simulation(energy, current, resolution, distance);
+// A context is only reachable through its owning `Simulation`, and the engine
+// keeps no state shared between contexts, so moving it across threads is safe.
+unsafe impl Send for Simulation {}
+
+/// What the engine's progress callback reaches through its user data.
+#[derive(Default)]
+struct ProgressHook {
+    cancel: Option<CancelFlag>,
+    progress: Option<ProgressCallback>,
+}
+
+/// Forwards engine progress to the registered callback and answers with the
+/// cancel flag.
+unsafe extern "C" fn report_progress(user_data: *mut c_void, completed: i64, total: i64) -> c_int {
+    let hook = &*(user_data as *const ProgressHook);
+    if let Some(callback) = &hook.progress {
+        callback(completed as u64, total as u64);
     }
+    hook.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) as c_int
 }
 
-/// Runs the Monte Carlo SEM simulation.
-///
-/// This executes the Fortran backend's scattering and detection loop.
-pub fn run_simulation() {
-    println!("Starting Monte Carlo simulation");
-    unsafe {
-        bindings::c_run_simulation();
+/// Maps a status returned by the engine to an error naming the failed call.
+fn check(call: &'static str, status: i32) -> Result<(), SimError> {
+    match status as u32 {
+        bindings::SEM_OK => Ok(()),
+        bindings::SEM_ERR_INVALID_ARGUMENT => {
+            Err(SimError::InvalidParameter(format!("{} rejected its arguments", call)))
+        }
+        bindings::SEM_ERR_OUT_OF_MEMORY => Err(SimError::OutOfMemory),
+        bindings::SEM_ERR_NO_DATA => Err(SimError::NoData(call)),
+        _ => Err(SimError::Engine { call, status }),
     }
 }
 
-/// Retrieves the simulation's scattering data as a flattened array and dimensions.
-///
-/// Returns a `ScatterData` struct containing the raw values and grid shape.
-pub fn get_scatter_data() -> ScatterData {
-    let mut rows: i32 = 0;
-    let mut cols: i32 = 0;
-    let mut raw_ptr: *mut f64 = ptr::null_mut();
-
-    unsafe {
-        bindings::c_get_scatter_data(&mut raw_ptr, &mut rows, &mut cols);
-        assert!(!raw_ptr.is_null(), "Null pointer returned from Fortran");
-        println!("Received data from Fortran with dimensions: {}×{}", rows, cols);
-
-        let total = (rows * cols) as usize;
-        let data_slice = slice::from_raw_parts(raw_ptr, total);
-        let data_vec = data_slice.to_vec();
-
-        // Ensure dimensions are positive before converting to usize
-        if rows <= 0 || cols <= 0 {
-            panic!("Invalid dimensions from Fortran: {}×{}", rows, cols);
+impl Simulation {
+    /// Adds a material for geometry regions and returns its engine index.
+    fn add_material(&mut self, material: &Material) -> Result<i32, SimError> {
+        let [atomic_numbers, atomic_weights, weight_fractions, mean_ionizations] = composition_arrays(material);
+        let mut index: i32 = 0;
+        check("c_add_material", unsafe {
+            bindings::c_add_material(
+                self.ctx,
+                material.composition.len() as i32,
+                atomic_numbers.as_ptr(),
+                atomic_weights.as_ptr(),
+                weight_fractions.as_ptr(),
+                mean_ionizations.as_ptr(),
+                material.density_g_cm3,
+                &mut index,
+            )
+        })?;
+        self.set_secondary_emission(index, material)?;
+        Ok(index)
+    }
+
+    fn set_secondary_emission(&mut self, index: i32, material: &Material) -> Result<(), SimError> {
+        check("c_set_secondary_emission", unsafe {
+            bindings::c_set_secondary_emission(
+                self.ctx,
+                index,
+                material.secondary_emission.escape_depth_nm,
+                material.secondary_emission.work_function_ev,
+            )
+        })
+    }
+
+    /// Retrieves the profile of the most recent line scan.
+    pub fn line_data(&self) -> Result<LineProfile, SimError> {
+        const ROWS: usize = 3; // (distance, BSE, SE) per point
+        let mut points: i32 = 0;
+        let mut raw_ptr: *mut f64 = ptr::null_mut();
+
+        unsafe {
+            check("c_get_line_data", bindings::c_get_line_data(self.ctx, &mut raw_ptr, &mut points))?;
+            if raw_ptr.is_null() || points <= 0 {
+                return Err(SimError::InvalidData(format!("line scan of {} points", points)));
+            }
+
+            let data = slice::from_raw_parts(raw_ptr, ROWS * points as usize);
+            Ok(LineProfile {
+                positions: data.chunks_exact(ROWS).map(|p| p[0]).collect(),
+                bse: data.chunks_exact(ROWS).map(|p| p[1]).collect(),
+                se: data.chunks_exact(ROWS).map(|p| p[2]).collect(),
+            })
         }
+    }
 
-        let result = ScatterData {
-            data: data_vec,
-            rows: rows as usize,
-            cols: cols as usize,
-        };
-        println!("Converted to ScatterData with dimensions: {}×{}", result.rows, result.cols);
-        result
+    /// Gets one channel of the 2D SEM image from the simulation.
+    pub fn image_data(&self, channel: ImageChannel) -> Result<(Vec<f64>, usize, usize), SimError> {
+        let mut width: i32 = 0;
+        let mut height: i32 = 0;
+        let mut raw_ptr: *mut f64 = ptr::null_mut();
+
+        unsafe {
+            check(
+                "c_get_image_data",
+                bindings::c_get_image_data(self.ctx, channel as i32, &mut raw_ptr, &mut width, &mut height),
+            )?;
+            log::debug!("Received image data from Fortran with dimensions: {}×{}", width, height);
+
+            if raw_ptr.is_null() || width <= 0 || height <= 0 {
+                return Err(SimError::InvalidData(format!("image of {}×{} pixels", width, height)));
+            }
+
+            let total = (width * height) as usize;
+            let data_vec = slice::from_raw_parts(raw_ptr, total).to_vec();
+
+            Ok((data_vec, width as usize, height as usize))
+        }
     }
 }
 
-/// Gets the 2D SEM image data from the simulation.
-pub fn get_image_data() -> (Vec<f64>, usize, usize) {
-    let mut width: i32 = 0;
-    let mut height: i32 = 0;
-    let mut raw_ptr: *mut f64 = ptr::null_mut();
+impl SimulationBackend for Simulation {
+    fn new(energy: f64, current: f64, scan: &ScanRaster, distance: f64) -> Result<Self, SimError> {
+        log::debug!("Initializing simulation with {}keV beam energy, {}nA current, {}×{}px over {} nm",
+                 energy, current, scan.width, scan.height, scan.field_of_view_nm);
+        let ctx = unsafe { bindings::c_create_simulation() };
+        if ctx.is_null() {
+            return Err(SimError::OutOfMemory);
+        }
+        // Owned from here on, so an init failure still frees the context
+        let mut simulation = Simulation { ctx, energy_kev: energy, hook: Box::default() };
+        check("c_init_simulation", unsafe {
+            bindings::c_init_simulation(
+                ctx,
+                energy,
+                current,
+                scan.width,
+                scan.height,
+                distance,
+                scan.field_of_view_nm,
+                scan.offset_nm.0,
+                scan.offset_nm.1,
+                scan.rotation_deg,
+            )
+        })?;
+        let user_data = &mut *simulation.hook as *mut ProgressHook as *mut c_void;
+        check("c_set_progress_callback", unsafe {
+            bindings::c_set_progress_callback(ctx, Some(report_progress), user_data)
+        })?;
+        Ok(simulation)
+    }
 
-    unsafe {
-        bindings::c_get_image_data(&mut raw_ptr, &mut width, &mut height);
-        assert!(!raw_ptr.is_null(), "Null pointer returned from Fortran");
-        println!("Received image data from Fortran with dimensions: {}×{}", width, height);
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { name: "fortran", cancellation: true, geometry: true, trajectories: true }
+    }
+
+    fn set_material(&mut self, material: &Material) -> Result<(), SimError> {
+        log::debug!("Using sample material {} (Z_eff={:.2})",
+                 material.name, material.effective_atomic_number());
+        let [atomic_numbers, atomic_weights, weight_fractions, mean_ionizations] = composition_arrays(material);
+        check("c_set_material", unsafe {
+            bindings::c_set_material(
+                self.ctx,
+                material.composition.len() as i32,
+                atomic_numbers.as_ptr(),
+                atomic_weights.as_ptr(),
+                weight_fractions.as_ptr(),
+                mean_ionizations.as_ptr(),
+                material.density_g_cm3,
+            )
+        })?;
+        self.set_secondary_emission(1, material)
+    }
 
-        let total = (width * height) as usize;
-        let data_slice = slice::from_raw_parts(raw_ptr, total);
-        let data_vec = data_slice.to_vec();
+    fn set_probe(&mut self, spot_size_nm: f64, convergence_rad: f64, aperture_diameter_um: f64,
+                     focus_depth_nm: f64, dwell_time_s: f64) -> Result<(), SimError> {
+        check("c_set_probe", unsafe {
+            bindings::c_set_probe(self.ctx, spot_size_nm, convergence_rad, aperture_diameter_um,
+                                  focus_depth_nm, dwell_time_s)
+        })
+    }
 
-        // Ensure dimensions are positive
-        if width <= 0 || height <= 0 {
-            panic!("Invalid dimensions from Fortran: {}×{}", width, height);
+    fn set_column(&mut self, column: &ColumnConfig) -> Result<(), SimError> {
+        column.validate()?;
+        if let Some(target) = column.target_spot_nm {
+            column.probe_current_na(self.energy_kev, target, self.probe()?.convergence_rad)?;
         }
+        check("c_set_column", unsafe {
+            bindings::c_set_column(
+                self.ctx,
+                column.reduced_brightness,
+                column.energy_spread_ev,
+                column.cs_mm,
+                column.cc_mm,
+                column.target_spot_nm.unwrap_or(0.0),
+            )
+        })
+    }
+
+    fn probe(&self) -> Result<Probe, SimError> {
+        let mut probe = Probe { spot_size_nm: 0.0, convergence_rad: 0.0, current_na: 0.0 };
+        check("c_get_probe", unsafe {
+            bindings::c_get_probe(self.ctx, &mut probe.spot_size_nm, &mut probe.convergence_rad,
+                                  &mut probe.current_na)
+        })?;
+        Ok(probe)
+    }
+
+    fn set_electrons_per_pixel(&mut self, count: Option<u32>) -> Result<(), SimError> {
+        let count = count.map_or(0, |n| n.min(i32::MAX as u32) as i32);
+        check("c_set_electrons_per_pixel", unsafe { bindings::c_set_electrons_per_pixel(self.ctx, count) })
+    }
+
+    fn set_seed(&mut self, seed: u64) -> Result<(), SimError> {
+        check("c_set_seed", unsafe { bindings::c_set_seed(self.ctx, seed) })
+    }
+
+    fn electrons_per_pixel(&self) -> Result<usize, SimError> {
+        let mut count: i32 = 0;
+        check("c_get_electrons_per_pixel", unsafe {
+            bindings::c_get_electrons_per_pixel(self.ctx, &mut count)
+        })?;
+        Ok(count.max(0) as usize)
+    }
 
-        (data_vec, width as usize, height as usize)
+    fn set_detector(&mut self, detector: &DetectorConfig) -> Result<(), SimError> {
+        log::debug!("Using {} detector (solid angle {:.3} sr)", detector.name, detector.solid_angle_sr());
+        check("c_set_detector", unsafe {
+            bindings::c_set_detector(
+                self.ctx,
+                detector.elevation_deg,
+                detector.azimuth_deg,
+                detector.distance_mm,
+                detector.inner_radius_mm,
+                detector.outer_radius_mm,
+                detector.se_efficiency,
+                detector.bse_efficiency,
+                detector.gain,
+                detector.dark_current,
+                detector.noise_level,
+                detector.time_constant_s,
+            )
+        })
     }
+
+    fn set_height_map(&mut self, map: Option<&HeightMap>) -> Result<(), SimError> {
+        let status = unsafe {
+            match map {
+                Some(map) => {
+                    log::debug!("Using {}×{} height map at {} nm/pixel", map.width, map.height, map.pixel_size_nm);
+                    bindings::c_set_height_map(
+                        self.ctx,
+                        map.heights_nm.as_ptr(),
+                        map.width as i32,
+                        map.height as i32,
+                        map.pixel_size_nm,
+                    )
+                }
+                None => bindings::c_set_height_map(self.ctx, ptr::null(), 0, 0, 1.0),
+            }
+        };
+        check("c_set_height_map", status)
+    }
+
+    fn set_geometry(&mut self, geometry: Option<&SampleGeometry>) -> Result<(), SimError> {
+        let Some(geometry) = geometry else {
+            return check("c_set_geometry", unsafe {
+                bindings::c_set_geometry(self.ctx, 0, ptr::null(), ptr::null(), 0, ptr::null(), 0,
+                                         ptr::null(), ptr::null())
+            });
+        };
+        log::debug!("Using sample geometry with {} regions", geometry.regions.len());
+        let compiled = geometry.compile();
+        let indices = compiled
+            .materials
+            .iter()
+            .map(|m| self.add_material(m))
+            .collect::<Result<Vec<i32>, SimError>>()?;
+        let region_materials: Vec<i32> = compiled.region_materials.iter().map(|&m| indices[m]).collect();
+        check("c_set_geometry", unsafe {
+            bindings::c_set_geometry(
+                self.ctx,
+                compiled.kinds.len() as i32,
+                compiled.kinds.as_ptr(),
+                compiled.params.as_ptr(),
+                compiled.program.len() as i32,
+                compiled.program.as_ptr(),
+                compiled.region_lengths.len() as i32,
+                compiled.region_lengths.as_ptr(),
+                region_materials.as_ptr(),
+            )
+        })
+    }
+
+    fn set_trajectory_recording(&mut self, max_electrons: usize) -> Result<(), SimError> {
+        check("c_set_trajectory_recording", unsafe {
+            bindings::c_set_trajectory_recording(self.ctx, max_electrons.min(i32::MAX as usize) as i32)
+        })
+    }
+
+    /// The engine checks the flag through its progress callback after every
+    /// scan line and line-scan point.
+    fn set_cancel_flag(&mut self, flag: CancelFlag) {
+        self.hook.cancel = Some(flag);
+    }
+
+    fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.hook.progress = Some(callback);
+    }
+
+    /// Executes the Fortran scattering and detection loop against this
+    /// context's buffers only.
+    fn run(&mut self) -> Result<(), SimError> {
+        log::debug!("Starting Monte Carlo simulation");
+        check("c_run_simulation", unsafe { bindings::c_run_simulation(self.ctx) })
+    }
+
+    fn run_line_scan(&mut self, start: (f64, f64), end: (f64, f64), n_points: i32)
+        -> Result<LineProfile, SimError> {
+        log::debug!("Starting line scan from {:?} to {:?} nm with {} points", start, end, n_points);
+        check("c_run_line_scan", unsafe {
+            bindings::c_run_line_scan(self.ctx, start.0, start.1, end.0, end.1, n_points)
+        })?;
+        self.line_data()
+    }
+
+    fn scatter_data(&self) -> Result<ScatterData, SimError> {
+        const ROWS: i32 = 8; // (x, y, z, energy, dx, dy, dz, fate) per electron
+        let mut rows: i32 = 0;
+        let mut cols: i32 = 0;
+        let mut raw_ptr: *mut f64 = ptr::null_mut();
+
+        unsafe {
+            check("c_get_scatter_data", bindings::c_get_scatter_data(self.ctx, &mut raw_ptr, &mut rows, &mut cols))?;
+            log::debug!("Received data from Fortran with dimensions: {}×{}", rows, cols);
+            if cols == 0 {
+                return Ok(Vec::new());
+            }
+
+            // Ensure dimensions match the record layout before converting to usize
+            if raw_ptr.is_null() || rows != ROWS || cols < 0 {
+                return Err(SimError::InvalidData(format!("scatter records of {}×{}", rows, cols)));
+            }
+
+            let total = (rows * cols) as usize;
+            let electrons: ScatterData = slice::from_raw_parts(raw_ptr, total)
+                .chunks_exact(ROWS as usize)
+                .map(|r| ElectronExit {
+                    position: [r[0], r[1], r[2]],
+                    energy_kev: r[3],
+                    direction: [r[4], r[5], r[6]],
+                    fate: match r[7] as i32 {
+                        1 => ElectronFate::Backscattered,
+                        2 => ElectronFate::Transmitted,
+                        _ => ElectronFate::Absorbed,
+                    },
+                })
+                .collect();
+            log::debug!("Converted {} electron exit records", electrons.len());
+            Ok(electrons)
+        }
+    }
+
+    fn trajectories(&self) -> Result<Trajectories, SimError> {
+        const ROWS: i32 = 6; // (electron, x, y, z, energy, event) per vertex
+        let mut rows: i32 = 0;
+        let mut vertices: i32 = 0;
+        let mut raw_ptr: *mut f64 = ptr::null_mut();
+
+        unsafe {
+            check(
+                "c_get_trajectory_data",
+                bindings::c_get_trajectory_data(self.ctx, &mut raw_ptr, &mut rows, &mut vertices),
+            )?;
+            if vertices == 0 {
+                return Ok(Trajectories::default());
+            }
+            if raw_ptr.is_null() || rows != ROWS || vertices < 0 {
+                return Err(SimError::InvalidData(format!("trajectory vertices of {}×{}", rows, vertices)));
+            }
+
+            // Vertices of one electron are contiguous; start a new polyline when the id changes
+            let mut electrons: Vec<Vec<TrajectoryVertex>> = Vec::new();
+            let mut current_id = None;
+            for r in slice::from_raw_parts(raw_ptr, (rows * vertices) as usize).chunks_exact(ROWS as usize) {
+                let id = r[0] as i64;
+                if current_id != Some(id) {
+                    electrons.push(Vec::new());
+                    current_id = Some(id);
+                }
+                let event = match r[5] as i32 {
+                    0 => TrajectoryEvent::Entry,
+                    2 => TrajectoryEvent::Backscattered,
+                    3 => TrajectoryEvent::Absorbed,
+                    4 => TrajectoryEvent::Transmitted,
+                    5 => TrajectoryEvent::Boundary,
+                    _ => TrajectoryEvent::Elastic,
+                };
+                electrons.last_mut().unwrap().push(TrajectoryVertex {
+                    position: [r[1], r[2], r[3]],
+                    energy_kev: r[4],
+                    event,
+                });
+            }
+            log::debug!("Converted {} recorded trajectories", electrons.len());
+            Ok(Trajectories { electrons })
+        }
+    }
+
+    fn image_channels(&self) -> Result<ImageChannels, SimError> {
+        let (se, width, height) = self.image_data(ImageChannel::Se)?;
+        let (bse, _, _) = self.image_data(ImageChannel::Bse)?;
+        let (detector, _, _) = self.image_data(ImageChannel::Detector)?;
+        let (transmitted, _, _) = self.image_data(ImageChannel::Transmitted)?;
+        let (backscattered, _, _) = self.image_data(ImageChannel::Backscattered)?;
+        let electrons_per_pixel = self.electrons_per_pixel()?;
+        Ok(ImageChannels { se, bse, detector, transmitted, backscattered, width, height, electrons_per_pixel })
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        unsafe {
+            bindings::c_free_simulation(self.ctx);
+        }
+    }
+}
+
+/// Atomic numbers, atomic weights, weight fractions and mean ionization
+/// energies of a material's constituents, as passed to the engine.
+fn composition_arrays(material: &Material) -> [Vec<f64>; 4] {
+    let c = &material.composition;
+    [
+        c.iter().map(|c| c.atomic_number as f64).collect(),
+        c.iter().map(|c| c.atomic_weight).collect(),
+        c.iter().map(|c| c.weight_fraction).collect(),
+        c.iter().map(|c| c.mean_ionization_ev).collect(),
+    ]
 }
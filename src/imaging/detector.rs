@@ -0,0 +1,130 @@
+//! Detector geometry and response used to form the detector image channel.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimError;
+
+/// Electron detector seen by the simulated beam spot.
+///
+/// Secondaries are pulled in by the collector field with `se_efficiency`;
+/// backscattered electrons count only if they leave the sample inside the
+/// cone subtended by the detector's active area.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DetectorConfig {
+    pub name: String,
+    /// Take-off angle of the detector axis above the sample plane, in degrees.
+    pub elevation_deg: f64,
+    /// Direction of the detector around the beam axis, in degrees from +x.
+    pub azimuth_deg: f64,
+    /// Distance from the beam spot to the detector face in mm.
+    pub distance_mm: f64,
+    /// Radius of the central hole of an annular detector in mm (0 for a full disc).
+    pub inner_radius_mm: f64,
+    /// Outer radius of the active area in mm.
+    pub outer_radius_mm: f64,
+    /// Fraction of escaping secondaries collected.
+    pub se_efficiency: f64,
+    /// Detection efficiency for backscattered electrons inside the acceptance cone.
+    pub bse_efficiency: f64,
+    pub gain: f64,
+    /// Dark signal per pixel, in collected-electron units.
+    pub dark_current: f64,
+    /// Standard deviation of the base noise, in collected-electron units.
+    pub noise_level: f64,
+    /// Response time constant in seconds.
+    pub time_constant_s: f64,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        Self::everhart_thornley()
+    }
+}
+
+impl DetectorConfig {
+    /// Side-mounted Everhart–Thornley detector with a biased collector grid:
+    /// collects most secondaries plus BSE flying towards it.
+    pub fn everhart_thornley() -> Self {
+        DetectorConfig {
+            name: "Everhart-Thornley".into(),
+            elevation_deg: 30.0,
+            azimuth_deg: 0.0,
+            distance_mm: 25.0,
+            inner_radius_mm: 0.0,
+            outer_radius_mm: 7.5,
+            se_efficiency: 0.85,
+            bse_efficiency: 0.9,
+            gain: 1000.0,
+            dark_current: 0.005,
+            noise_level: 0.02,
+            time_constant_s: 100.0e-9,
+        }
+    }
+
+    /// In-lens (through-the-lens) detector: secondaries are drawn up the column
+    /// by the immersion field, and only BSE close to the beam axis reach it.
+    pub fn in_lens() -> Self {
+        DetectorConfig {
+            name: "In-lens".into(),
+            elevation_deg: 90.0,
+            distance_mm: 10.0,
+            inner_radius_mm: 0.5,
+            outer_radius_mm: 1.5,
+            se_efficiency: 0.95,
+            bse_efficiency: 0.5,
+            ..Self::everhart_thornley()
+        }
+    }
+
+    /// Annular solid-state BSE detector under the pole piece: blind to slow
+    /// secondaries, large solid angle for backscattered electrons.
+    pub fn annular_bse() -> Self {
+        DetectorConfig {
+            name: "Annular BSE".into(),
+            elevation_deg: 90.0,
+            distance_mm: 5.0,
+            inner_radius_mm: 2.0,
+            outer_radius_mm: 10.0,
+            se_efficiency: 0.0,
+            bse_efficiency: 0.9,
+            gain: 100.0,
+            time_constant_s: 1.0e-6,
+            ..Self::everhart_thornley()
+        }
+    }
+
+    /// Solid angle subtended by the active area at the beam spot, in steradians.
+    pub fn solid_angle_sr(&self) -> f64 {
+        let cos_inner = self.inner_radius_mm.atan2(self.distance_mm).cos();
+        let cos_outer = self.outer_radius_mm.atan2(self.distance_mm).cos();
+        2.0 * std::f64::consts::PI * (cos_inner - cos_outer)
+    }
+
+    /// Check that the geometry and response values are physically meaningful.
+    pub fn validate(&self) -> Result<(), SimError> {
+        if self.distance_mm <= 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "detector distance_mm ({}) must be > 0",
+                self.distance_mm
+            )));
+        }
+        if self.inner_radius_mm < 0.0 || self.outer_radius_mm <= self.inner_radius_mm {
+            return Err(SimError::InvalidParameter(format!(
+                "detector radii ({} mm, {} mm) must satisfy 0 <= inner < outer",
+                self.inner_radius_mm, self.outer_radius_mm
+            )));
+        }
+        for (name, value) in [("se_efficiency", self.se_efficiency), ("bse_efficiency", self.bse_efficiency)] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(SimError::InvalidParameter(format!("detector {} ({}) out of range [0, 1]", name, value)));
+            }
+        }
+        if self.gain <= 0.0 || self.time_constant_s <= 0.0 {
+            return Err(SimError::InvalidParameter("detector gain and time_constant_s must be > 0".into()));
+        }
+        if self.dark_current < 0.0 || self.noise_level < 0.0 {
+            return Err(SimError::InvalidParameter("detector dark_current and noise_level must be >= 0".into()));
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,18 @@
 //! Image export utilities for SEM simulator.
 
 use std::fs::File;
-use std::io::{self, BufWriter};
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
 
-use image::{ImageBuffer, ImageError, Luma};
-use png::{Encoder, ColorType, BitDepth};
+use image::{ImageBuffer, Luma};
+use png::{Decoder, Encoder, ColorType, BitDepth};
 
+use serde::Serialize;
+
+use crate::backend::{LineProfile, TrajectoryEvent, Trajectories};
+use crate::error::SimError;
 use crate::simulation::parameters::SimulationParameters;
+use crate::simulation::sweep::{ImageMetrics, SweepResults};
 
 /// Save a raw 8-bit grayscale buffer as a PNG file at the given path.
 ///
@@ -18,19 +23,25 @@ use crate::simulation::parameters::SimulationParameters;
 /// - `height`: Image height in pixels.
 ///
 /// # Errors
-/// Returns `ImageError` if the underlying image crate fails.
+/// Returns `SimError::InvalidParameter` if the buffer length does not match
+/// the dimensions, or `SimError::Io` if the image crate fails.
 pub fn save_png(
     path: &str,
     buffer: &[u8],
     width: u32,
     height: u32,
-) -> Result<(), ImageError> {
+) -> Result<(), SimError> {
     // Create an ImageBuffer from the raw buffer
     let img: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_raw(width, height, buffer.to_vec())
-        .expect("Buffer length does not match width*height");
+        .ok_or_else(|| {
+            SimError::InvalidParameter(format!(
+                "Buffer length {} does not match {}×{}",
+                buffer.len(), width, height
+            ))
+        })?;
 
     // Save using the image crate
-    img.save(path)
+    Ok(img.save(path)?)
 }
 
 /// Save a raw 8-bit grayscale buffer as a PNG file with embedded metadata tEXt chunks.
@@ -63,8 +74,23 @@ pub fn save_png_with_metadata(
     // Add tEXt chunks for metadata
     encoder.add_text_chunk("Energy_keV".into(), params.energy_kev.to_string())?;
     encoder.add_text_chunk("Current_nA".into(), params.current_na.to_string())?;
-    encoder.add_text_chunk("Resolution".into(), params.resolution.to_string())?;
+    let (image_width, image_height) = params.image_size();
+    encoder.add_text_chunk("Resolution".into(), format!("{}x{}", image_width, image_height))?;
+    encoder.add_text_chunk("FieldOfView_um".into(), params.field_of_view_um.to_string())?;
+    encoder.add_text_chunk("ScanRotation_deg".into(), params.scan_rotation_deg.to_string())?;
+    encoder.add_text_chunk("SpotSize_nm".into(), params.spot_size_nm.to_string())?;
+    encoder.add_text_chunk("Convergence_mrad".into(), params.convergence_semi_angle_mrad().to_string())?;
+    encoder.add_text_chunk("DwellTime_us".into(), params.dwell_time_us.to_string())?;
+    encoder.add_text_chunk("ElectronsPerPixel".into(), params.primaries_per_pixel().to_string())?;
+    encoder.add_text_chunk("Dose_e_per_nm2".into(), params.dose_e_per_nm2().to_string())?;
+    encoder.add_text_chunk("Seed".into(), params.seed.to_string())?;
+    if let Some(column) = &params.column {
+        encoder.add_text_chunk("Source".into(), format!("{:?}", column.source))?;
+    }
     encoder.add_text_chunk("Distance_mm".into(), params.distance_mm.to_string())?;
+    encoder.add_text_chunk("Material".into(), params.material.name.clone())?;
+    encoder.add_text_chunk("Signal".into(), format!("{:?}", params.signal))?;
+    encoder.add_text_chunk("Detector".into(), params.detector.name.clone())?;
 
     let mut writer = encoder.write_header()?;
 
@@ -73,3 +99,118 @@ pub fn save_png_with_metadata(
 
     Ok(())
 }
+
+/// Size and metadata of a saved PNG.
+pub struct PngMetadata {
+    pub width: u32,
+    pub height: u32,
+    /// `(keyword, text)` of every tEXt chunk, in file order.
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Read the size and text chunks of a PNG written by `save_png_with_metadata`.
+pub fn read_png_metadata(path: &str) -> Result<PngMetadata, SimError> {
+    let reader = Decoder::new(File::open(path)?)
+        .read_info()
+        .map_err(|e| SimError::Io(format!("Cannot read PNG {}: {}", path, e)))?;
+    let info = reader.info();
+    let metadata = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect();
+    Ok(PngMetadata { width: info.width, height: info.height, metadata })
+}
+
+/// Save recorded electron trajectories as CSV with one row per vertex.
+///
+/// Columns: `electron,vertex,x_nm,y_nm,z_nm,energy_kev,event`.
+pub fn save_trajectories_csv(path: &str, trajectories: &Trajectories) -> Result<(), io::Error> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "electron,vertex,x_nm,y_nm,z_nm,energy_kev,event")?;
+    for (i, electron) in trajectories.electrons.iter().enumerate() {
+        for (j, v) in electron.iter().enumerate() {
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{:?}",
+                i, j, v.position[0], v.position[1], v.position[2], v.energy_kev, v.event
+            )?;
+        }
+    }
+    w.flush()
+}
+
+/// Save a line-scan profile as CSV with one row per beam position.
+///
+/// Columns: `distance_nm,bse,se`.
+pub fn save_line_profile_csv(path: &str, profile: &LineProfile) -> Result<(), io::Error> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "distance_nm,bse,se")?;
+    for ((distance, bse), se) in profile.positions.iter().zip(&profile.bse).zip(&profile.se) {
+        writeln!(w, "{},{},{}", distance, bse, se)?;
+    }
+    w.flush()
+}
+
+/// Save the metrics of a sweep as CSV with one row per job.
+///
+/// Columns: one per axis, then
+/// `mean_intensity,bse_coefficient,bse_energy_fraction,contrast,cancelled,error`;
+/// `cancelled` is true for partial images of cancelled jobs, and failed jobs
+/// leave the metrics empty and give the error.
+pub fn save_sweep_metrics_csv(path: &str, sweep: &SweepResults) -> Result<(), io::Error> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for axis in &sweep.axes {
+        write!(w, "{},", axis)?;
+    }
+    writeln!(w, "mean_intensity,bse_coefficient,bse_energy_fraction,contrast,cancelled,error")?;
+    for row in &sweep.rows {
+        for value in &row.coordinates {
+            write!(w, "{},", csv_field(value))?;
+        }
+        match &row.result {
+            Ok(result) => {
+                let m = ImageMetrics::of(result);
+                writeln!(
+                    w,
+                    "{},{},{},{},{},",
+                    m.mean_intensity, m.bse_coefficient, m.bse_energy_fraction, m.contrast, result.cancelled
+                )?;
+            }
+            Err(e) => writeln!(w, ",,,,,{}", csv_field(&e.to_string()))?,
+        }
+    }
+    w.flush()
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// One trajectory written as parallel arrays, convenient for plotting polylines.
+#[derive(Serialize)]
+struct TrajectoryPolyline<'a> {
+    points: Vec<[f64; 3]>,
+    energies_kev: Vec<f64>,
+    events: Vec<&'a TrajectoryEvent>,
+}
+
+/// Save recorded electron trajectories as a JSON array of polylines.
+pub fn save_trajectories_json(path: &str, trajectories: &Trajectories) -> Result<(), io::Error> {
+    let polylines: Vec<TrajectoryPolyline> = trajectories
+        .electrons
+        .iter()
+        .map(|electron| TrajectoryPolyline {
+            points: electron.iter().map(|v| v.position).collect(),
+            energies_kev: electron.iter().map(|v| v.energy_kev).collect(),
+            events: electron.iter().map(|v| &v.event).collect(),
+        })
+        .collect();
+    let w = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(w, &polylines).map_err(io::Error::from)
+}
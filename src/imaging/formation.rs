@@ -1,5 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ImageChannels;
+use crate::error::SimError;
 use crate::imaging::Lut;
 
+/// Which detector signal forms the displayed image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum DetectorSignal {
+    /// Secondary electrons: topography and edge contrast.
+    Se,
+    /// Backscattered electrons: atomic-number contrast.
+    Bse,
+    /// Weighted sum of both channels, each scaled to its own maximum first.
+    Mixed { se_weight: f64 },
+    /// Output of the configured detector model.
+    #[default]
+    Detector,
+    /// Electrons transmitted through a thin sample (STEM-in-SEM bright field).
+    Transmitted,
+}
+
+/// Select or combine image channels into one signal image.
+pub fn detector_signal(channels: &ImageChannels, signal: DetectorSignal) -> Vec<f64> {
+    let (se, bse) = (&channels.se, &channels.bse);
+    match signal {
+        DetectorSignal::Se => se.to_vec(),
+        DetectorSignal::Bse => bse.to_vec(),
+        DetectorSignal::Detector => channels.detector.to_vec(),
+        DetectorSignal::Transmitted => channels.transmitted.to_vec(),
+        DetectorSignal::Mixed { se_weight } => {
+            let w = se_weight.clamp(0.0, 1.0);
+            let scale = |data: &Vec<f64>| {
+                let max = data.iter().cloned().fold(0.0, f64::max);
+                if max > 0.0 { 1.0 / max } else { 0.0 }
+            };
+            let (se_scale, bse_scale) = (scale(se), scale(bse));
+            se.iter()
+                .zip(bse.iter())
+                .map(|(&s, &b)| w * s * se_scale + (1.0 - w) * b * bse_scale)
+                .collect()
+        }
+    }
+}
+
 /// Converts a floating-point simulation buffer into an 8-bit grayscale buffer,
 /// downscaling if needed to fit SDL's texture limits.
 pub fn to_grayscale_bytes(
@@ -8,16 +51,25 @@ pub fn to_grayscale_bytes(
     width: usize,
     gamma: f64,
     lut: Option<&Lut>,
-) -> (Vec<u8>, usize, usize) {  // Return buffer and its dimensions
-    assert!(gamma > 0.0, "Gamma must be greater than zero");
-    assert_eq!(data.len(), width * height, "Data length does not match width*height");
-    assert!(width > 0 && height > 0, "Dimensions must be greater than zero");
+) -> Result<(Vec<u8>, usize, usize), SimError> {  // Return buffer and its dimensions
+    if gamma <= 0.0 {
+        return Err(SimError::InvalidParameter(format!("Gamma ({}) must be greater than zero", gamma)));
+    }
+    if width == 0 || height == 0 {
+        return Err(SimError::InvalidParameter(format!("Image dimensions {}×{} must not be zero", width, height)));
+    }
+    if data.len() != width * height {
+        return Err(SimError::InvalidParameter(format!(
+            "Data length {} does not match {}×{}",
+            data.len(), width, height
+        )));
+    }
 
     // Constants for dimension limits
     const MAX_DIM: usize = 4096;  // Quarter of SDL's limit for safety
     const MIN_DIM: usize = 32;    // Minimum dimension to prevent degenerate cases
 
-    println!("Converting image data with dimensions {}×{}", width, height);
+    log::debug!("Converting image data with dimensions {}×{}", width, height);
 
     // Find data range for normalization
     let (min, max) = data.iter().fold(
@@ -44,11 +96,11 @@ pub fn to_grayscale_bytes(
         }
     }
 
-    // If dimensions are already within bounds, return as-is
-    if width <= MAX_DIM && height <= MAX_DIM && 
-       width >= MIN_DIM && height >= MIN_DIM {
-        println!("Image dimensions within bounds: {}×{}", width, height);
-        return (grayscale, width, height);
+    // If dimensions are already within bounds, return as-is; small images are
+    // never resampled
+    if width <= MAX_DIM && height <= MAX_DIM {
+        log::debug!("Image dimensions within bounds: {}×{}", width, height);
+        return Ok((grayscale, width, height));
     }
 
     // 2) Calculate new dimensions that preserve aspect ratio
@@ -66,8 +118,8 @@ pub fn to_grayscale_bytes(
         (new_width, new_height)
     };
 
-    println!(
-        "⚠️ Downscaling image from {}×{} to {}×{} to fit SDL limits",
+    log::info!(
+        "Downscaling image from {}×{} to {}×{} to fit display limits",
         width, height, new_width, new_height
     );
 
@@ -89,10 +141,10 @@ pub fn to_grayscale_bytes(
         }
     }
 
-    println!("Final downscaled dimensions: {}×{}", new_width, new_height);
-    println!("Final buffer size: {}", downscaled.len());
+    log::debug!("Final downscaled dimensions: {}×{}", new_width, new_height);
+    log::debug!("Final buffer size: {}", downscaled.len());
     
-    (downscaled, new_width, new_height)
+    Ok((downscaled, new_width, new_height))
 }
 
 
@@ -2,6 +2,7 @@
 
 pub mod formation;
 pub mod export;
+pub mod detector;
 
 /// Lookup table type: mapping 0..=255 to new 0..=255 values
 pub type Lut = [u8; 256];
@@ -1,11 +1,19 @@
+#[cfg(not(any(feature = "fortran", feature = "rust-engine")))]
+compile_error!("enable the `fortran` or the `rust-engine` feature to get a simulation engine");
+
+pub mod backend;
+pub mod error;
+#[cfg(feature = "fortran")]
 pub mod ffi;
 pub mod simulation;
 pub mod materials;
 pub mod imaging;
+pub mod sample;
 
 #[cfg(test)]
 mod tests {
     use super::simulation::parameters::SimulationParameters;
+    use super::simulation::column::{ColumnConfig, ElectronSource};
 
     #[test]
     fn test_simulation_param_creation() {
@@ -17,4 +25,681 @@ mod tests {
         assert_eq!(params.resolution, 256);
         assert_eq!(params.distance_mm, 10.0);
     }
+
+    #[test]
+    fn test_simulation_param_seed() {
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0).unwrap();
+        assert_eq!(params.seed, 0);
+        assert_eq!(params.with_seed(42).seed, 42);
+    }
+
+    #[test]
+    fn test_scan_raster() {
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0)
+            .unwrap()
+            .with_image_size(400, 300)
+            .unwrap()
+            .with_magnification(25_400.0)
+            .unwrap()
+            .with_scan_offset(1.5, -2.0)
+            .with_scan_rotation(30.0);
+        assert_eq!(params.image_size(), (400, 300));
+        assert!((params.field_of_view_um - 5.0).abs() < 1e-12);
+
+        let raster = params.scan_raster();
+        assert_eq!((raster.width, raster.height), (400, 300));
+        assert!((raster.pixel_size_nm() - 12.5).abs() < 1e-9);
+        assert_eq!(raster.offset_nm, (1500.0, -2000.0));
+        assert!(params.clone().with_field_of_view(0.0).is_err());
+        assert!(params.with_image_size(0, 10).is_err());
+    }
+
+    #[test]
+    fn test_probe_parameters() {
+        // 30 µm aperture at 10 mm: 1.5 mrad, so a 5 nm spot stays sharp over ~3.3 µm
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0).unwrap();
+        assert!((params.convergence_semi_angle_mrad() - 1.5).abs() < 1e-12);
+        assert!((params.depth_of_field_um() - 5.0 / 1.5).abs() < 1e-12);
+
+        // Halving the working distance doubles the convergence angle
+        let short_wd = SimulationParameters::new(20.0, 5.0, 256, 5.0).unwrap();
+        assert!((short_wd.convergence_semi_angle_mrad() - 3.0).abs() < 1e-12);
+
+        let fixed = params.clone().with_convergence(10.0).unwrap().with_spot_size(2.0).unwrap();
+        assert_eq!(fixed.convergence_semi_angle_mrad(), 10.0);
+        assert!(params.clone().with_dwell_time(0.0).is_err());
+        assert!(params.with_convergence(-1.0).is_err());
+    }
+
+    #[test]
+    fn test_electrons_per_pixel() {
+        // 5 nA for 1 µs delivers ~31 200 electrons per pixel, with no cap
+        let params = SimulationParameters::new(20.0, 5.0, 512, 10.0).unwrap();
+        assert_eq!(params.primaries_per_pixel(), 31208);
+
+        // 10 µm over 512 px: ~19.5 nm pixels, so 1 e/nm² needs ~381 primaries
+        let dosed = params.clone().with_dose(1.0).unwrap();
+        assert_eq!(dosed.primaries_per_pixel(), 381);
+        assert!((dosed.dose_e_per_nm2() - 1.0).abs() < 0.01);
+        assert_eq!(params.clone().with_electrons_per_pixel(200).unwrap().primaries_per_pixel(), 200);
+        assert!(params.with_electrons_per_pixel(0).is_err());
+    }
+
+    #[test]
+    fn test_column_sources() {
+        // Field emitters are orders of magnitude brighter and less chromatic
+        let tungsten = ColumnConfig::new(ElectronSource::Tungsten);
+        let feg = ColumnConfig::new(ElectronSource::SchottkyFeg);
+        assert!(feg.reduced_brightness > 100.0 * tungsten.reduced_brightness);
+        assert!(feg.energy_spread_ev < tungsten.energy_spread_ev);
+
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0).unwrap();
+        let params = params.with_column(feg.clone().with_target_spot(2.0)).unwrap();
+        assert_eq!(params.column.as_ref().unwrap().target_spot_nm, Some(2.0));
+        assert!(feg.clone().with_target_spot(0.0).validate().is_err());
+        assert!(feg.clone().with_aberrations(-1.0, 1.0).validate().is_err());
+
+        // A target spot sets the current; one below the aberration limit is refused
+        use crate::backend::{MockBackend, SimulationBackend};
+        let smallest = feg.smallest_spot_nm(20.0, 1.5e-3);
+        let mut mock = MockBackend::new(20.0, 5.0, &params.scan_raster(), 10.0).unwrap();
+        mock.set_probe(5.0, 1.5e-3, 30.0, 0.0, 1.0e-6).unwrap();
+        mock.set_column(&feg.clone().with_target_spot(2.0 * smallest)).unwrap();
+        let probe = mock.probe().unwrap();
+        assert_eq!(probe.spot_size_nm, 2.0 * smallest);
+        assert!(probe.current_na > 0.0);
+        let err = mock.set_column(&feg.with_target_spot(0.5 * smallest)).unwrap_err();
+        assert!(err.to_string().contains(&format!("{:.3} nm", smallest)));
+        assert!(MockBackend::new(20.0, 0.0, &params.scan_raster(), 10.0).is_err());
+    }
+
+    #[test]
+    fn test_simulation_param_material() {
+        let copper = crate::materials::get_preset_material("copper").unwrap();
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0).unwrap();
+        assert_eq!(params.material.name, "Silicon");
+        let params = params.with_material(copper);
+        assert_eq!(params.material.effective_atomic_number(), 29.0);
+        assert!(params.material.mean_ionization_ev() > 0.0);
+    }
+
+    #[test]
+    fn test_compound_material() {
+        let sio2 = crate::materials::get_preset_material("SiO2").unwrap();
+        assert!(sio2.is_compound());
+        let total: f64 = sio2.composition.iter().map(|c| c.weight_fraction).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        // Si carries ~46.7 wt% of SiO2
+        let si = sio2.composition.iter().find(|c| c.atomic_number == 14).unwrap();
+        assert!((si.weight_fraction - 0.467).abs() < 1e-3);
+        let z = sio2.effective_atomic_number();
+        assert!(z > 8.0 && z < 14.0);
+    }
+
+    #[test]
+    fn test_line_profile_edge_width() {
+        use crate::backend::LineProfile;
+
+        let profile = LineProfile {
+            positions: vec![0.0, 10.0, 20.0, 30.0, 40.0],
+            bse: vec![0.1, 0.1, 0.2, 0.3, 0.3],
+            se: vec![1.0; 5],
+        };
+        // 20 % (0.14) at 14 nm and 80 % (0.26) at 26 nm
+        assert!((profile.bse_edge_width().unwrap() - 12.0).abs() < 1e-9);
+        assert!(profile.se_edge_width().is_none());
+    }
+
+    #[test]
+    fn test_element_lookup_and_autofill() {
+        use crate::materials::custom::CustomMaterialSpec;
+
+        let gold = crate::materials::get_preset_material("Au").unwrap();
+        assert_eq!(gold.name, "Gold");
+        assert_eq!(gold.effective_atomic_number(), 79.0);
+        assert!(crate::materials::get_preset_material("gold").is_some());
+        // Symbols of preset elements give the preset, secondary emission included
+        let silicon = crate::materials::get_preset_material("Si").unwrap();
+        assert_eq!(silicon.name, "Silicon");
+        assert_eq!(silicon.secondary_emission.escape_depth_nm, 2.0);
+        assert_eq!(silicon.secondary_emission.work_function_ev, 4.05);
+
+        // Fermium has no tabulated ionization energy: Berger–Seltzer fills it in
+        let fermium = crate::materials::elements::element_by_number(100).unwrap();
+        assert!(fermium.ionization_ev.is_none());
+        assert!(fermium.mean_ionization_ev() > 900.0);
+
+        let spec: CustomMaterialSpec =
+            serde_json::from_str(r#"{"name": "thin gold", "atomic_number": 79, "density_g_cm3": 18.0}"#)
+                .unwrap();
+        let material = spec.try_into_material().unwrap();
+        assert_eq!(material.density_g_cm3, 18.0);
+        assert_eq!(material.composition[0].atomic_weight, 196.97);
+        assert_eq!(material.composition[0].mean_ionization_ev, 790.0);
+    }
+
+    #[test]
+    fn test_detector_signal_mixing() {
+        use crate::backend::ImageChannels;
+        use crate::imaging::formation::{detector_signal, DetectorSignal};
+
+        let channels = ImageChannels {
+            se: vec![0.0, 2.0, 4.0],
+            bse: vec![0.5, 0.25, 0.0],
+            detector: vec![1.0, 1.0, 1.0],
+            transmitted: vec![0.0; 3],
+            backscattered: vec![0.5, 0.25, 0.0],
+            width: 3,
+            height: 1,
+            electrons_per_pixel: 100,
+        };
+        assert_eq!(detector_signal(&channels, DetectorSignal::Se), channels.se);
+        assert_eq!(detector_signal(&channels, DetectorSignal::Bse), channels.bse);
+        assert_eq!(detector_signal(&channels, DetectorSignal::Detector), channels.detector);
+        // Each channel is scaled to its maximum before weighting
+        let mixed = detector_signal(&channels, DetectorSignal::Mixed { se_weight: 0.5 });
+        assert_eq!(mixed, vec![0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_detector_presets() {
+        use crate::imaging::detector::DetectorConfig;
+
+        let et = DetectorConfig::everhart_thornley();
+        let abse = DetectorConfig::annular_bse();
+        for detector in [&et, &DetectorConfig::in_lens(), &abse] {
+            assert!(detector.validate().is_ok());
+            assert!(detector.solid_angle_sr() > 0.0);
+        }
+        // The annular BSE detector sits close under the pole piece and sees no SEs
+        assert!(abse.solid_angle_sr() > et.solid_angle_sr());
+        assert_eq!(abse.se_efficiency, 0.0);
+
+        let params = SimulationParameters::new(20.0, 5.0, 256, 10.0).unwrap();
+        let bad = DetectorConfig { outer_radius_mm: 0.0, ..DetectorConfig::in_lens() };
+        assert!(params.with_detector(bad).is_err());
+    }
+
+    #[test]
+    fn test_height_map_parsing() {
+        use crate::sample::HeightMap;
+
+        let map = HeightMap::parse_csv("# step edge\n0, 0, 50\n0 0 50\n", 10.0).unwrap();
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.heights_nm[2], 50.0);
+        assert_eq!(map.extent_nm(), (20.0, 10.0));
+        assert!(HeightMap::parse_csv("0, 1\n2\n", 10.0).is_err());
+        assert!(HeightMap::new(2, 2, 0.0, vec![0.0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_trajectory_csv_export() {
+        use crate::backend::{TrajectoryEvent, TrajectoryVertex, Trajectories};
+
+        let vertex = |z: f64, energy_kev: f64, event| TrajectoryVertex {
+            position: [0.0, 0.0, z],
+            energy_kev,
+            event,
+        };
+        let trajectories = Trajectories {
+            electrons: vec![
+                vec![vertex(0.0, 20.0, TrajectoryEvent::Entry), vertex(50.0, 19.5, TrajectoryEvent::Elastic)],
+                vec![vertex(0.0, 20.0, TrajectoryEvent::Entry), vertex(0.0, 18.0, TrajectoryEvent::Backscattered)],
+            ],
+        };
+        let path = std::env::temp_dir().join("quantfocus_trajectories_test.csv");
+        crate::imaging::export::save_trajectories_csv(path.to_str().unwrap(), &trajectories).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "electron,vertex,x_nm,y_nm,z_nm,energy_kev,event");
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "1,1,0,0,0,18,Backscattered");
+    }
+
+    #[test]
+    fn test_sample_geometry_regions() {
+        use crate::materials::get_preset_material;
+        use crate::sample::{SampleGeometry, Shape};
+
+        // Copper sphere buried in a silicon substrate with a trench cut into it
+        let substrate = Shape::Difference {
+            base: Box::new(Shape::HalfSpace { top_nm: 0.0 }),
+            subtract: vec![Shape::Box { min_nm: [-50.0, -1e6, -1.0], max_nm: [50.0, 1e6, 100.0] }],
+        };
+        let geometry = SampleGeometry::new()
+            .with_region(substrate, get_preset_material("Silicon").unwrap())
+            .with_region(
+                Shape::Sphere { center_nm: [200.0, 0.0, 100.0], radius_nm: 50.0 },
+                get_preset_material("Copper").unwrap(),
+            );
+        assert!(geometry.validate().is_ok());
+        assert!(geometry.material_at([0.0, 0.0, 50.0]).is_none());
+        assert_eq!(geometry.material_at([0.0, 0.0, 150.0]).unwrap().name, "Silicon");
+        assert_eq!(geometry.material_at([200.0, 0.0, 120.0]).unwrap().name, "Copper");
+        assert!(geometry.material_at([200.0, 0.0, -10.0]).is_none());
+
+        let compiled = geometry.compile();
+        assert_eq!(compiled.kinds.len(), 3);
+        assert_eq!(compiled.program, vec![1, 2, -2, 3]);
+        assert_eq!(compiled.region_materials, vec![0, 1]);
+
+        // A lens where two spheres overlap
+        let lens = Shape::Intersection {
+            shapes: vec![
+                Shape::Sphere { center_nm: [0.0, 0.0, 60.0], radius_nm: 50.0 },
+                Shape::Sphere { center_nm: [0.0, 0.0, 120.0], radius_nm: 50.0 },
+            ],
+        };
+        let geometry = geometry.with_region(lens, get_preset_material("Gold").unwrap());
+        assert_eq!(geometry.material_at([0.0, 0.0, 90.0]).unwrap().name, "Gold");
+        assert!(geometry.material_at([0.0, 0.0, 50.0]).is_none());
+        assert_eq!(geometry.compile().program, vec![1, 2, -2, 3, 4, 5, -3]);
+        assert!(Shape::Intersection { shapes: vec![] }.validate().is_err());
+
+        let json = serde_json::to_string(&geometry).unwrap();
+        assert!(json.contains("\"type\":\"half_space\""));
+        assert!(json.contains("\"type\":\"intersection\""));
+        let bad = SampleGeometry::new()
+            .with_region(Shape::Layer { top_nm: 10.0, bottom_nm: 5.0 }, get_preset_material("Silicon").unwrap());
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_layer_stack_geometry() {
+        use crate::materials::get_preset_material;
+        use crate::sample::LayerStack;
+
+        // 20 nm gold on 50 nm silicon, free-standing
+        let stack = LayerStack::new()
+            .with_layer(20.0, get_preset_material("Au").unwrap())
+            .with_layer(50.0, get_preset_material("Silicon").unwrap());
+        assert!(stack.is_free_standing());
+        assert_eq!(stack.total_thickness_nm(), 70.0);
+
+        let geometry = stack.to_geometry();
+        assert!(geometry.material_at([0.0, 0.0, -1.0]).is_none());
+        assert_eq!(geometry.material_at([0.0, 0.0, 10.0]).unwrap().name, "Gold");
+        assert_eq!(geometry.material_at([1e4, 0.0, 60.0]).unwrap().name, "Silicon");
+        assert!(geometry.material_at([0.0, 0.0, 71.0]).is_none());
+
+        let params = SimulationParameters::new(30.0, 1.0, 64, 5.0)
+            .unwrap()
+            .with_layer_stack(stack.with_substrate(get_preset_material("Silicon").unwrap()))
+            .unwrap();
+        let geometry = params.sample_geometry().unwrap();
+        assert_eq!(geometry.material_at([0.0, 0.0, 1e6]).unwrap().name, "Silicon");
+        let empty_layer = LayerStack::new().with_layer(0.0, get_preset_material("Au").unwrap());
+        assert!(params.with_layer_stack(empty_layer).is_err());
+    }
+
+    #[test]
+    fn test_mock_backend_results() {
+        use crate::backend::{MockBackend, SimulationBackend};
+        use crate::imaging::formation::DetectorSignal;
+        use crate::materials::get_preset_material;
+        use crate::simulation::SimulationManager;
+
+        let params = SimulationParameters::new(20.0, 1.0, 32, 10.0)
+            .unwrap()
+            .with_material(get_preset_material("Au").unwrap())
+            .with_trajectories(3);
+        let manager = SimulationManager::<MockBackend>::with_backend();
+        manager.enqueue(params.clone());
+        let result = manager.run_all().pop().unwrap().unwrap();
+
+        // SE ramps from the first pixel to the last; gold backscatters about half
+        assert_eq!((result.width, result.height), (32, 32));
+        assert!(!result.sample_ignored);
+        assert_eq!(result.scatter.len(), 32 * 32);
+        assert_eq!(result.trajectories.electrons.len(), 3);
+        let se = result.render(DetectorSignal::Se).unwrap();
+        assert_eq!((se[0], se[32 * 32 - 1]), (0, 255));
+        assert!((result.channels.bse[0] - 0.48).abs() < 0.02);
+
+        let path = std::env::temp_dir().join("quantfocus_mock_backend_test.png");
+        result.save_png(path.to_str().unwrap()).unwrap();
+        let saved = image::open(&path).unwrap().into_luma8();
+        let png = crate::imaging::export::read_png_metadata(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(saved.dimensions(), (32, 32));
+        assert!(png.metadata.contains(&("Material".to_string(), "Gold".to_string())));
+
+        let mut mock = MockBackend::new(20.0, 1.0, &params.scan_raster(), 10.0).unwrap();
+        assert!(!mock.capabilities().geometry);
+        let profile = mock.run_line_scan((0.0, 0.0), (30.0, 40.0), 6).unwrap();
+        assert_eq!(profile.positions, vec![0.0, 10.0, 20.0, 30.0, 40.0, 50.0]);
+        assert_eq!(mock.scatter_data().unwrap()[5].position, [30.0, 40.0, 0.0]);
+
+        // Scans wider than the display limit are downscaled along with their size
+        manager.enqueue(params.with_trajectories(0).with_image_size(5000, 40).unwrap());
+        let wide = manager.run_all().pop().unwrap().unwrap();
+        assert_eq!((wide.channels.width, wide.width, wide.height), (5000, 4096, 33));
+        assert_eq!(wide.image_buffer.len(), wide.width * wide.height);
+        wide.save_png(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_invalid_job_fails_alone() {
+        use crate::backend::MockBackend;
+        use crate::error::SimError;
+        use crate::imaging::formation::to_grayscale_bytes;
+        use crate::simulation::SimulationManager;
+
+        let params = SimulationParameters::new(20.0, 1.0, 8, 10.0).unwrap();
+        let mut bad = params.clone();
+        bad.energy_kev = -1.0;
+        let manager = SimulationManager::<MockBackend>::with_backend();
+        manager.enqueue(bad);
+        manager.enqueue(params);
+        let results = manager.run_all();
+        assert!(matches!(results[0], Err(SimError::InvalidParameter(_))));
+        assert_eq!(results[1].as_ref().unwrap().width, 8);
+
+        assert!(to_grayscale_bytes(&[0.0; 3], 2, 2, 1.0, None).is_err());
+    }
+
+    #[test]
+    fn test_job_progress_and_cancel() {
+        use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+        use std::sync::Arc;
+        use crate::backend::{MockBackend, SimulationBackend};
+        use crate::simulation::SimulationManager;
+
+        let params = SimulationParameters::new(20.0, 1.0, 16, 10.0).unwrap();
+        let manager = SimulationManager::<MockBackend>::with_backend();
+        manager.enqueue(params.clone());
+        manager.enqueue(params.clone().with_image_size(8, 8).unwrap());
+        let job = manager.start();
+        while !job.is_finished() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let progress = job.progress();
+        assert_eq!((progress.completed, progress.total), (16 * 16 + 8 * 8, 16 * 16 + 8 * 8));
+        assert_eq!(progress.eta(), Some(std::time::Duration::ZERO));
+        let results = job.wait();
+        assert!(results.iter().all(|r| !r.as_ref().unwrap().cancelled));
+
+        // Cancelling one batch leaves the others running; a cancelled job's
+        // image is marked whenever its last line is missing
+        manager.enqueue(params.clone().with_image_size(512, 512).unwrap());
+        let first = manager.start();
+        manager.enqueue(params.clone());
+        let second = manager.start();
+        first.cancel();
+        for result in first.wait() {
+            let result = result.unwrap();
+            assert_eq!(result.cancelled, *result.channels.backscattered.last().unwrap() == 0.0);
+        }
+        assert!(!second.wait()[0].as_ref().unwrap().cancelled);
+
+        // A cancelled engine stops before the next line and reports nothing further
+        let mut mock = MockBackend::new(20.0, 1.0, &params.scan_raster(), 10.0).unwrap();
+        let reported = Arc::new(AtomicU64::new(0));
+        let counter = reported.clone();
+        mock.set_progress_callback(Arc::new(move |done, total| {
+            assert_eq!(total, 16 * 16);
+            counter.store(done, Ordering::Relaxed);
+        }));
+        mock.run().unwrap();
+        assert_eq!(reported.load(Ordering::Relaxed), 16 * 16);
+        mock.set_cancel_flag(Arc::new(AtomicBool::new(true)));
+        reported.store(0, Ordering::Relaxed);
+        mock.run().unwrap();
+        assert_eq!(reported.load(Ordering::Relaxed), 0);
+        assert!(mock.image_channels().unwrap().se.iter().all(|&se| se == 0.0));
+        assert!(mock.run_line_scan((0.0, 0.0), (100.0, 0.0), 10).unwrap().positions.is_empty());
+        assert_eq!(reported.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_parameter_sweep() {
+        use crate::backend::MockBackend;
+        use crate::materials::get_preset_material;
+        use crate::simulation::sweep::linspace;
+        use crate::simulation::{ParameterSweep, SimulationManager, SweepAxis};
+
+        assert_eq!(linspace(10.0, 20.0, 3), vec![10.0, 15.0, 20.0]);
+        let base = SimulationParameters::new(20.0, 1.0, 8, 10.0).unwrap();
+        let materials = vec![get_preset_material("Silicon").unwrap(), get_preset_material("Au").unwrap()];
+        let sweep = ParameterSweep::new(base.clone())
+            .with_axis(SweepAxis::Material(materials))
+            .with_axis(SweepAxis::Energy(linspace(10.0, 20.0, 2)))
+            .with_axis(SweepAxis::Tilt(vec![0.0, 45.0]));
+        let points = sweep.points().unwrap();
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[3].coordinates, vec!["Silicon", "20", "45"]);
+        let map = points[3].params.height_map.as_ref().unwrap();
+        let (extent, _) = map.extent_nm();
+        assert!((map.heights_nm[map.width - 1] - map.heights_nm[0] - extent).abs() < 1e-6);
+
+        let results = SimulationManager::<MockBackend>::with_backend().run_sweep(&sweep).unwrap();
+        assert_eq!(results.axes, vec!["material", "energy_kev", "tilt_deg"]);
+        assert_eq!(results.failures(), 0);
+        // The mock engine has no geometry, so the tilted planes are flagged
+        assert!(results.rows.iter().all(|row| row.result.as_ref().unwrap().sample_ignored));
+        let silicon = results.rows[0].metrics().unwrap();
+        let gold = results.rows[4].metrics().unwrap();
+        assert!(gold.bse_coefficient > silicon.bse_coefficient);
+        assert!(gold.contrast > 0.0);
+
+        let path = std::env::temp_dir().join("quantfocus_sweep_test.csv");
+        results.save_metrics_csv(path.to_str().unwrap()).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let header = "material,energy_kev,tilt_deg,mean_intensity,bse_coefficient,bse_energy_fraction,contrast,cancelled,error";
+        assert!(csv.starts_with(&format!("{}\n", header)));
+        assert_eq!(csv.lines().count(), 9);
+
+        let invalid = ParameterSweep::new(base.clone()).with_axis(SweepAxis::Energy(vec![20.0, 500.0]));
+        assert!(invalid.points().is_err());
+        assert!(ParameterSweep::new(base).with_axis(SweepAxis::Seed(vec![])).points().is_err());
+    }
+
+    #[test]
+    fn test_job_files() {
+        use crate::error::SimError;
+        use crate::imaging::formation::DetectorSignal;
+        use crate::simulation::JobFile;
+
+        let dir = std::env::temp_dir().join("quantfocus_job_file_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, text: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, text).unwrap();
+            path
+        };
+        write("base.toml", "version = 1\nseed = 3\n\n[beam]\nenergy_kev = 20\ncurrent_na = 2\n\n[scan]\nwidth = 64\n");
+        let job = write(
+            "gold.yaml",
+            "version: 1\ninclude: [base.toml]\nbeam:\n  energy_kev: ${QUANTFOCUS_TEST_UNSET:-12}\n\
+             sample:\n  material: Au\n  tilt_deg: 30\ndetector:\n  signal: bse\noutput:\n  image: gold.png\n",
+        );
+        let loaded = JobFile::load(&job).unwrap();
+        assert_eq!((loaded.params.energy_kev, loaded.params.current_na), (12.0, 2.0));
+        assert_eq!((loaded.params.resolution, loaded.params.seed), (64, 3));
+        assert_eq!(loaded.params.material.name, "Gold");
+        assert_eq!(loaded.params.signal, DetectorSignal::Bse);
+        assert!(loaded.params.height_map.is_some());
+        assert_eq!(loaded.output.image.unwrap().to_str(), Some("gold.png"));
+        let overridden = JobFile::load_with_overrides(&job, &[("beam.energy_kev".into(), "5".into())]).unwrap();
+        assert_eq!(overridden.params.energy_kev, 5.0);
+
+        // Overrides replace alternatives from the files and keep derived values in step
+        let rect = write(
+            "rect.toml",
+            "version = 1\n[scan]\nwidth = 64\nheight = 32\ndose_e_per_nm2 = 0.01\n\n\
+             [detector]\nsignal = \"mixed\"\nse_weight = 0.3\n",
+        );
+        let set = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|&(key, value)| (key.to_string(), value.to_string())).collect()
+        };
+        let base = JobFile::load(&rect).unwrap().params;
+        let wider = JobFile::load_with_overrides(&rect, &set(&[("scan.width", "128")])).unwrap().params;
+        assert_eq!(wider.image_size(), (128, 64));
+        assert!(wider.electrons_per_pixel < base.electrons_per_pixel);
+        let counted = set(&[("scan.electrons_per_pixel", "4"), ("detector.signal", "bse")]);
+        let counted = JobFile::load_with_overrides(&rect, &counted).unwrap().params;
+        assert_eq!((counted.electrons_per_pixel, counted.signal), (Some(4), DetectorSignal::Bse));
+        let defaults = JobFile::from_overrides(&set(&[("scan.width", "64"), ("seed", "9")])).unwrap().params;
+        assert_eq!((defaults.image_size(), defaults.seed), ((64, 64), 9));
+        assert!(matches!(
+            JobFile::from_overrides(&set(&[("beam.energy_kev", "500")])),
+            Err(SimError::InvalidParameter(_))
+        ));
+
+        // Errors name the file and line of the offending entry
+        let line_of_error = |name: &str, text: &str| match JobFile::load(write(name, text)).unwrap_err() {
+            SimError::JobFile { path, line, .. } => {
+                assert!(path.ends_with(name));
+                line
+            }
+            e => panic!("unexpected error {:?}", e),
+        };
+        assert_eq!(line_of_error("range.toml", "include = [\"base.toml\"]\n[beam]\nenergy_kev = 500\n"), Some(3));
+        assert_eq!(line_of_error("typo.toml", "version = 1\n[scan]\nwidht = 5\n"), Some(3));
+        assert_eq!(line_of_error("env.toml", "version = 1\nseed = ${QUANTFOCUS_TEST_UNSET}\n"), Some(2));
+        assert_eq!(line_of_error("version.json", "{\n  \"version\": 2\n}\n"), Some(2));
+        assert_eq!(line_of_error("unversioned.toml", "seed = 1\n"), None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_job_file_samples() {
+        use crate::materials::get_preset_material;
+        use crate::sample::{LayerStack, SampleGeometry, Shape};
+        use crate::simulation::JobFile;
+
+        let dir = std::env::temp_dir().join("quantfocus_job_file_sample_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let load = |name: &str, job: serde_json::Value| {
+            let path = dir.join(name);
+            std::fs::write(&path, job.to_string()).unwrap();
+            JobFile::load(&path)
+        };
+        fn json<T: serde::Serialize>(value: &T) -> serde_json::Value {
+            serde_json::to_value(value).unwrap()
+        }
+
+        // Each table reads back as the value it was written from
+        let geometry = SampleGeometry::new().with_region(
+            Shape::Intersection {
+                shapes: vec![
+                    Shape::HalfSpace { top_nm: 0.0 },
+                    Shape::Sphere { center_nm: [0.0, 0.0, 0.0], radius_nm: 100.0 },
+                ],
+            },
+            get_preset_material("Au").unwrap(),
+        );
+        let layers = LayerStack::new()
+            .with_layer(20.0, get_preset_material("Au").unwrap())
+            .with_substrate(get_preset_material("Silicon").unwrap());
+        let column = ColumnConfig::new(ElectronSource::LaB6).with_target_spot(10.0);
+        let job = serde_json::json!({
+            "version": 1,
+            "beam": { "column": column },
+            "sample": { "geometry": geometry },
+        });
+        let loaded = load("geometry.json", job).unwrap().params;
+        assert_eq!(json(&loaded.geometry.unwrap()), json(&geometry));
+        assert_eq!(json(&loaded.column.unwrap()), json(&column));
+        let loaded = load("layers.json", serde_json::json!({ "version": 1, "sample": { "layers": layers } }));
+        assert_eq!(json(&loaded.unwrap().params.layer_stack.unwrap()), json(&layers));
+
+        // A raw height map is a grid of little-endian f32 heights
+        let raw: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0].iter().flat_map(|h| h.to_le_bytes()).collect();
+        std::fs::write(dir.join("surface.raw"), raw).unwrap();
+        let map = serde_json::json!({ "path": "surface.raw", "pixel_size_nm": 2.0, "width": 3, "height": 2 });
+        let loaded = load("raw.json", serde_json::json!({ "version": 1, "sample": { "height_map": map } }));
+        assert_eq!(loaded.unwrap().params.height_map.unwrap().heights_nm, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let both = serde_json::json!({ "version": 1, "sample": { "geometry": geometry, "layers": layers } });
+        assert!(load("both.json", both).is_err());
+        let bad = serde_json::json!({ "version": 1, "sample": { "layers": LayerStack::new() } });
+        assert!(load("empty.json", bad).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(feature = "rust-engine")]
+    #[test]
+    fn test_rust_engine_reproducible() {
+        use crate::backend::ElectronFate;
+        use crate::simulation::SimulationManager;
+
+        let params = SimulationParameters::new(5.0, 1.0, 4, 10.0)
+            .unwrap()
+            .with_field_of_view(0.1)
+            .unwrap()
+            .with_electrons_per_pixel(50)
+            .unwrap()
+            .with_seed(7);
+        let manager = SimulationManager::new();
+        manager.enqueue(params.clone());
+        manager.enqueue(params.clone());
+        manager.enqueue(params.clone().with_seed(8));
+        let results: Vec<_> = manager.run_all().into_iter().map(Result::unwrap).collect();
+
+        // Same seed, same image; another seed, another image
+        assert_eq!(results[0].channels.se, results[1].channels.se);
+        assert_eq!(results[0].channels.bse, results[1].channels.bse);
+        assert_ne!(results[0].channels.se, results[2].channels.se);
+
+        // Silicon at 5 keV backscatters a small fraction and transmits nothing
+        let scatter = &results[0].scatter;
+        assert_eq!(scatter.len(), 4 * 4 * 50);
+        let backscattered = scatter.iter().filter(|e| e.fate == ElectronFate::Backscattered).count();
+        assert!(backscattered > 0 && backscattered < scatter.len() / 2);
+
+        // η counts those electrons; the BSE channel weights them by energy
+        let metrics = crate::simulation::sweep::ImageMetrics::of(&results[0]);
+        assert!((metrics.bse_coefficient - backscattered as f64 / scatter.len() as f64).abs() < 1e-12);
+        assert!(metrics.bse_energy_fraction < metrics.bse_coefficient);
+        assert!(scatter.iter().all(|e| e.fate != ElectronFate::Transmitted));
+        assert!(results[0].channels.se.iter().all(|&se| se > 0.0));
+
+        // A line scan keeps the exits of every point, not just the last
+        use crate::backend::native::NativeEngine;
+        use crate::backend::SimulationBackend;
+        let mut engine = NativeEngine::new(5.0, 1.0, &params.scan_raster(), 10.0).unwrap();
+        engine.set_electrons_per_pixel(Some(20)).unwrap();
+        engine.run_line_scan((-50.0, 0.0), (50.0, 0.0), 5).unwrap();
+        assert_eq!(engine.scatter_data().unwrap().len(), 5 * 20);
+    }
+
+    #[cfg(feature = "rust-engine")]
+    #[test]
+    fn test_rust_engine_intersection() {
+        use crate::backend::ElectronFate;
+        use crate::materials::get_preset_material;
+        use crate::sample::{SampleGeometry, Shape};
+        use crate::simulation::SimulationManager;
+
+        // A substrate clipped to a block either under the beam or beside it
+        let clipped = |x_min: f64, x_max: f64| {
+            let shape = Shape::Intersection {
+                shapes: vec![
+                    Shape::HalfSpace { top_nm: 0.0 },
+                    Shape::Box { min_nm: [x_min, -1e4, -1.0], max_nm: [x_max, 1e4, 1e6] },
+                ],
+            };
+            SimulationParameters::new(5.0, 1.0, 2, 10.0)
+                .unwrap()
+                .with_field_of_view(0.01)
+                .unwrap()
+                .with_electrons_per_pixel(50)
+                .unwrap()
+                .with_geometry(SampleGeometry::new().with_region(shape, get_preset_material("Silicon").unwrap()))
+                .unwrap()
+        };
+        let manager = SimulationManager::new();
+        manager.enqueue(clipped(-1e4, 1e4));
+        manager.enqueue(clipped(1e4, 2e4));
+        let results: Vec<_> = manager.run_all().into_iter().map(Result::unwrap).collect();
+        let backscattered =
+            |i: usize| results[i].scatter.iter().filter(|e| e.fate == ElectronFate::Backscattered).count();
+        assert!(backscattered(0) > 0);
+        assert_eq!(backscattered(1), 0);
+    }
 }
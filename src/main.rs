@@ -3,14 +3,32 @@ mod ui {
     pub mod visualizer;
 }
 
+use std::path::PathBuf;
+
 use ui::app::run_simulation;
-use ui::visualizer::display_image;
+use ui::visualizer::{display_image, display_interaction_volume};
 
+/// Usage: `QuantFocus [JOB_FILE]`, where the optional job file is TOML, JSON
+/// or YAML.
 fn main() {
-    let (width, height, buffer) = run_simulation();
-    if let Err(e) = display_image("SEM Simulation Output", width, height, &buffer) {
+    env_logger::init();
+    let job_file = std::env::args_os().nth(1).map(PathBuf::from);
+    let result = match run_simulation(job_file.as_deref()) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Simulation failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (width, height) = (result.width as u32, result.height as u32);
+    if let Err(e) = display_image("SEM Simulation Output", width, height, &result.image_buffer) {
         eprintln!("Image display failed: {:?}", e);
     }
+    if !result.trajectories.is_empty() {
+        if let Err(e) = display_interaction_volume("Interaction Volume (X–Z)", &result.trajectories) {
+            eprintln!("Trajectory display failed: {:?}", e);
+        }
+    }
 }
 
 
@@ -1,37 +1,90 @@
 //! Custom material creation and parsing from user input (e.g., JSON).
 
 use serde::{Deserialize, Serialize};
-use crate::materials::Material;
+use crate::error::SimError;
+use crate::materials::elements::{element_by_number, find_element};
+use crate::materials::{Material, SecondaryEmission};
 
+/// User-supplied description of a pure-element material.
+///
+/// Any property left out is filled in from the periodic table, using
+/// `atomic_number` if given or else `name` as an element symbol or name.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CustomMaterialSpec {
-    /// Name of the material.
+    /// Name of the material (a pure element).
     pub name: String,
     /// Atomic number (Z) of the material. Must be between 1 and 100.
-    pub atomic_number: u8,
+    pub atomic_number: Option<u8>,
+    /// Atomic weight in g/mol. Must be > 0.
+    pub atomic_weight: Option<f64>,
     /// Density in g/cm³. Must be > 0.
-    pub density_g_cm3: f64,
+    pub density_g_cm3: Option<f64>,
+    /// Mean ionization energy in eV. Must be > 0.
+    pub mean_ionization_ev: Option<f64>,
+    /// SE escape depth in nm. Defaults to a metal-like surface. Must be > 0.
+    pub se_escape_depth_nm: Option<f64>,
+    /// SE surface barrier (work function) in eV. Defaults to a metal-like surface. Must be > 0.
+    pub work_function_ev: Option<f64>,
 }
 
 impl CustomMaterialSpec {
-    pub fn try_into_material(self) -> Result<Material, String> {
+    pub fn try_into_material(self) -> Result<Material, SimError> {
         if self.name.trim().is_empty() {
-            return Err("Material name cannot be empty".into());
+            return Err(SimError::InvalidParameter("Material name cannot be empty".into()));
         }
-        if !(1..=100).contains(&self.atomic_number) {
-            return Err(
-                format!("atomic_number ({}) must be between 1 and 100", self.atomic_number)
-            );
+        let element = match self.atomic_number {
+            Some(z) => element_by_number(z).ok_or_else(|| {
+                SimError::InvalidParameter(format!("atomic_number ({}) must be between 1 and 100", z))
+            })?,
+            None => find_element(self.name.trim()).ok_or_else(|| {
+                SimError::InvalidParameter(format!(
+                    "atomic_number is required: {} is not an element symbol or name",
+                    self.name
+                ))
+            })?,
+        };
+
+        let atomic_weight = self.atomic_weight.unwrap_or(element.atomic_weight);
+        let density_g_cm3 = self.density_g_cm3.unwrap_or(element.density_g_cm3);
+        let mean_ionization_ev = self
+            .mean_ionization_ev
+            .unwrap_or_else(|| element.mean_ionization_ev());
+
+        if atomic_weight <= 0.0 {
+            return Err(SimError::InvalidParameter(
+                format!("atomic_weight ({}) must be > 0", atomic_weight)
+            ));
+        }
+        if density_g_cm3 <= 0.0 {
+            return Err(SimError::InvalidParameter(
+                format!("density_g_cm3 ({}) must be > 0", density_g_cm3)
+            ));
+        }
+        if mean_ionization_ev <= 0.0 {
+            return Err(SimError::InvalidParameter(
+                format!("mean_ionization_ev ({}) must be > 0", mean_ionization_ev)
+            ));
+        }
+        let defaults = SecondaryEmission::default();
+        let se_escape_depth_nm = self.se_escape_depth_nm.unwrap_or(defaults.escape_depth_nm);
+        let work_function_ev = self.work_function_ev.unwrap_or(defaults.work_function_ev);
+        if se_escape_depth_nm <= 0.0 {
+            return Err(SimError::InvalidParameter(
+                format!("se_escape_depth_nm ({}) must be > 0", se_escape_depth_nm)
+            ));
         }
-        if self.density_g_cm3 <= 0.0 {
-            return Err(
-                format!("density_g_cm3 ({}) must be > 0", self.density_g_cm3)
-            );
+        if work_function_ev <= 0.0 {
+            return Err(SimError::InvalidParameter(
+                format!("work_function_ev ({}) must be > 0", work_function_ev)
+            ));
         }
-        Ok(Material {
-            name: self.name,
-            atomic_number: self.atomic_number,
-            density_g_cm3: self.density_g_cm3,
-        })
+        Ok(Material::element(
+            &self.name,
+            element.atomic_number,
+            atomic_weight,
+            density_g_cm3,
+            mean_ionization_ev,
+        )
+        .with_secondary_emission(se_escape_depth_nm, work_function_ev))
     }
 }
@@ -0,0 +1,157 @@
+//! Periodic-table data (Z = 1–100) used to build pure and compound materials.
+
+/// Physical data for a single chemical element.
+#[derive(Clone, Copy, Debug)]
+pub struct Element {
+    pub symbol: &'static str,
+    pub name: &'static str,
+    pub atomic_number: u8,
+    /// Standard atomic weight in g/mol.
+    pub atomic_weight: f64,
+    /// Density of the pure element in g/cm³ (gases at STP).
+    pub density_g_cm3: f64,
+    /// Tabulated mean ionization energy in eV (ICRU 37), where available.
+    pub ionization_ev: Option<f64>,
+}
+
+impl Element {
+    /// Mean ionization energy in eV, falling back to the Berger–Seltzer
+    /// formula for elements without a tabulated value.
+    pub fn mean_ionization_ev(&self) -> f64 {
+        self.ionization_ev
+            .unwrap_or_else(|| berger_seltzer_ionization_ev(self.atomic_number))
+    }
+}
+
+/// Berger–Seltzer estimate of the mean ionization energy in eV.
+pub fn berger_seltzer_ionization_ev(atomic_number: u8) -> f64 {
+    let z = atomic_number as f64;
+    9.76 * z + 58.5 * z.powf(-0.19)
+}
+
+const fn el(
+    symbol: &'static str,
+    name: &'static str,
+    atomic_number: u8,
+    atomic_weight: f64,
+    density_g_cm3: f64,
+    ionization_ev: Option<f64>,
+) -> Element {
+    Element { symbol, name, atomic_number, atomic_weight, density_g_cm3, ionization_ev }
+}
+
+/// Elements 1–100, ordered by atomic number.
+pub static ELEMENTS: &[Element] = &[
+    el("H", "Hydrogen", 1, 1.008, 0.0000899, Some(19.2)),
+    el("He", "Helium", 2, 4.0026, 0.0001785, Some(41.8)),
+    el("Li", "Lithium", 3, 6.94, 0.534, Some(40.0)),
+    el("Be", "Beryllium", 4, 9.0122, 1.85, Some(63.7)),
+    el("B", "Boron", 5, 10.81, 2.34, Some(76.0)),
+    el("C", "Carbon", 6, 12.011, 2.0, Some(78.0)),
+    el("N", "Nitrogen", 7, 14.007, 0.00125, Some(82.0)),
+    el("O", "Oxygen", 8, 15.999, 0.00143, Some(95.0)),
+    el("F", "Fluorine", 9, 18.998, 0.0017, Some(115.0)),
+    el("Ne", "Neon", 10, 20.180, 0.0009, Some(137.0)),
+    el("Na", "Sodium", 11, 22.990, 0.971, Some(149.0)),
+    el("Mg", "Magnesium", 12, 24.305, 1.738, Some(156.0)),
+    el("Al", "Aluminium", 13, 26.982, 2.699, Some(166.0)),
+    el("Si", "Silicon", 14, 28.085, 2.33, Some(173.0)),
+    el("P", "Phosphorus", 15, 30.974, 1.82, Some(173.0)),
+    el("S", "Sulfur", 16, 32.06, 2.07, Some(180.0)),
+    el("Cl", "Chlorine", 17, 35.45, 0.00321, Some(174.0)),
+    el("Ar", "Argon", 18, 39.948, 0.00178, Some(188.0)),
+    el("K", "Potassium", 19, 39.098, 0.862, Some(190.0)),
+    el("Ca", "Calcium", 20, 40.078, 1.55, Some(191.0)),
+    el("Sc", "Scandium", 21, 44.956, 2.985, Some(216.0)),
+    el("Ti", "Titanium", 22, 47.867, 4.54, Some(233.0)),
+    el("V", "Vanadium", 23, 50.942, 6.11, Some(245.0)),
+    el("Cr", "Chromium", 24, 51.996, 7.19, Some(257.0)),
+    el("Mn", "Manganese", 25, 54.938, 7.44, Some(272.0)),
+    el("Fe", "Iron", 26, 55.845, 7.874, Some(286.0)),
+    el("Co", "Cobalt", 27, 58.933, 8.90, Some(297.0)),
+    el("Ni", "Nickel", 28, 58.693, 8.902, Some(311.0)),
+    el("Cu", "Copper", 29, 63.546, 8.96, Some(322.0)),
+    el("Zn", "Zinc", 30, 65.38, 7.133, Some(330.0)),
+    el("Ga", "Gallium", 31, 69.723, 5.904, Some(334.0)),
+    el("Ge", "Germanium", 32, 72.630, 5.323, Some(350.0)),
+    el("As", "Arsenic", 33, 74.922, 5.73, Some(347.0)),
+    el("Se", "Selenium", 34, 78.971, 4.81, Some(348.0)),
+    el("Br", "Bromine", 35, 79.904, 3.12, Some(343.0)),
+    el("Kr", "Krypton", 36, 83.798, 0.00373, Some(352.0)),
+    el("Rb", "Rubidium", 37, 85.468, 1.532, Some(363.0)),
+    el("Sr", "Strontium", 38, 87.62, 2.54, Some(366.0)),
+    el("Y", "Yttrium", 39, 88.906, 4.469, Some(379.0)),
+    el("Zr", "Zirconium", 40, 91.224, 6.506, Some(393.0)),
+    el("Nb", "Niobium", 41, 92.906, 8.57, Some(417.0)),
+    el("Mo", "Molybdenum", 42, 95.95, 10.22, Some(424.0)),
+    el("Tc", "Technetium", 43, 98.0, 11.5, Some(428.0)),
+    el("Ru", "Ruthenium", 44, 101.07, 12.41, Some(441.0)),
+    el("Rh", "Rhodium", 45, 102.91, 12.41, Some(449.0)),
+    el("Pd", "Palladium", 46, 106.42, 12.02, Some(470.0)),
+    el("Ag", "Silver", 47, 107.87, 10.5, Some(470.0)),
+    el("Cd", "Cadmium", 48, 112.41, 8.65, Some(469.0)),
+    el("In", "Indium", 49, 114.82, 7.31, Some(488.0)),
+    el("Sn", "Tin", 50, 118.71, 7.31, Some(488.0)),
+    el("Sb", "Antimony", 51, 121.76, 6.691, Some(487.0)),
+    el("Te", "Tellurium", 52, 127.60, 6.24, Some(485.0)),
+    el("I", "Iodine", 53, 126.90, 4.93, Some(491.0)),
+    el("Xe", "Xenon", 54, 131.29, 0.00589, Some(482.0)),
+    el("Cs", "Caesium", 55, 132.91, 1.873, Some(488.0)),
+    el("Ba", "Barium", 56, 137.33, 3.5, Some(491.0)),
+    el("La", "Lanthanum", 57, 138.91, 6.145, Some(501.0)),
+    el("Ce", "Cerium", 58, 140.12, 6.77, Some(523.0)),
+    el("Pr", "Praseodymium", 59, 140.91, 6.773, Some(535.0)),
+    el("Nd", "Neodymium", 60, 144.24, 7.008, Some(546.0)),
+    el("Pm", "Promethium", 61, 145.0, 7.264, Some(560.0)),
+    el("Sm", "Samarium", 62, 150.36, 7.52, Some(574.0)),
+    el("Eu", "Europium", 63, 151.96, 5.244, Some(580.0)),
+    el("Gd", "Gadolinium", 64, 157.25, 7.901, Some(591.0)),
+    el("Tb", "Terbium", 65, 158.93, 8.23, Some(614.0)),
+    el("Dy", "Dysprosium", 66, 162.50, 8.551, Some(628.0)),
+    el("Ho", "Holmium", 67, 164.93, 8.795, Some(650.0)),
+    el("Er", "Erbium", 68, 167.26, 9.066, Some(658.0)),
+    el("Tm", "Thulium", 69, 168.93, 9.321, Some(674.0)),
+    el("Yb", "Ytterbium", 70, 173.05, 6.9, Some(684.0)),
+    el("Lu", "Lutetium", 71, 174.97, 9.841, Some(694.0)),
+    el("Hf", "Hafnium", 72, 178.49, 13.31, Some(705.0)),
+    el("Ta", "Tantalum", 73, 180.95, 16.654, Some(718.0)),
+    el("W", "Tungsten", 74, 183.84, 19.3, Some(727.0)),
+    el("Re", "Rhenium", 75, 186.21, 21.02, Some(736.0)),
+    el("Os", "Osmium", 76, 190.23, 22.57, Some(746.0)),
+    el("Ir", "Iridium", 77, 192.22, 22.42, Some(757.0)),
+    el("Pt", "Platinum", 78, 195.08, 21.45, Some(790.0)),
+    el("Au", "Gold", 79, 196.97, 19.32, Some(790.0)),
+    el("Hg", "Mercury", 80, 200.59, 13.546, Some(800.0)),
+    el("Tl", "Thallium", 81, 204.38, 11.85, Some(810.0)),
+    el("Pb", "Lead", 82, 207.2, 11.35, Some(823.0)),
+    el("Bi", "Bismuth", 83, 208.98, 9.747, Some(823.0)),
+    el("Po", "Polonium", 84, 209.0, 9.32, Some(830.0)),
+    el("At", "Astatine", 85, 210.0, 6.4, Some(825.0)),
+    el("Rn", "Radon", 86, 222.0, 0.00973, Some(794.0)),
+    el("Fr", "Francium", 87, 223.0, 1.87, Some(827.0)),
+    el("Ra", "Radium", 88, 226.0, 5.0, Some(826.0)),
+    el("Ac", "Actinium", 89, 227.0, 10.07, Some(841.0)),
+    el("Th", "Thorium", 90, 232.04, 11.72, Some(847.0)),
+    el("Pa", "Protactinium", 91, 231.04, 15.37, Some(878.0)),
+    el("U", "Uranium", 92, 238.03, 18.95, Some(890.0)),
+    el("Np", "Neptunium", 93, 237.0, 20.25, Some(902.0)),
+    el("Pu", "Plutonium", 94, 244.0, 19.84, Some(921.0)),
+    el("Am", "Americium", 95, 243.0, 13.67, Some(934.0)),
+    el("Cm", "Curium", 96, 247.0, 13.51, Some(939.0)),
+    el("Bk", "Berkelium", 97, 247.0, 14.78, Some(952.0)),
+    el("Cf", "Californium", 98, 251.0, 15.1, Some(966.0)),
+    el("Es", "Einsteinium", 99, 252.0, 8.84, None),
+    el("Fm", "Fermium", 100, 257.0, 9.7, None),
+];
+
+/// Look up an element by atomic number.
+pub fn element_by_number(atomic_number: u8) -> Option<&'static Element> {
+    ELEMENTS.get((atomic_number as usize).checked_sub(1)?)
+}
+
+/// Look up an element by symbol (e.g. `"Au"`) or name (e.g. `"gold"`), ignoring case.
+pub fn find_element(key: &str) -> Option<&'static Element> {
+    ELEMENTS
+        .iter()
+        .find(|e| e.symbol.eq_ignore_ascii_case(key) || e.name.eq_ignore_ascii_case(key))
+}
@@ -1,27 +1,226 @@
 //! Materials subsystem root: predefined and custom material definitions.
 pub mod presets;
 pub mod custom;
+pub mod elements;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::SimError;
+use elements::{find_element, Element};
+
+/// One element of a material's composition.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Constituent {
+    pub atomic_number: u8,
+    /// Atomic weight (A) in g/mol.
+    pub atomic_weight: f64,
+    /// Fraction of the material's mass contributed by this element.
+    pub weight_fraction: f64,
+    /// Mean ionization energy (J) of the element in eV.
+    pub mean_ionization_ev: f64,
+}
+
+/// Surface properties that decide how many secondary electrons escape.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SecondaryEmission {
+    /// SE attenuation length λ_SE in nm: ~1 nm in metals, 10 nm or more in insulators.
+    pub escape_depth_nm: f64,
+    /// Barrier an SE must cross to leave: work function, or electron affinity for
+    /// semiconductors and insulators, in eV.
+    pub work_function_ev: f64,
+}
+
+impl Default for SecondaryEmission {
+    /// Typical metal surface.
+    fn default() -> Self {
+        SecondaryEmission {
+            escape_depth_nm: 1.0,
+            work_function_ev: 4.5,
+        }
+    }
+}
+
+/// A sample material: a pure element or a compound given by weight fractions.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Material {
     pub name: String,
-    pub atomic_number: u8,
+    /// Elemental composition; a pure element has a single entry.
+    pub composition: Vec<Constituent>,
     pub density_g_cm3: f64,
+    #[serde(default)]
+    pub secondary_emission: SecondaryEmission,
+}
+
+impl Material {
+    /// Build a pure-element material.
+    pub fn element(
+        name: &str,
+        atomic_number: u8,
+        atomic_weight: f64,
+        density_g_cm3: f64,
+        mean_ionization_ev: f64,
+    ) -> Self {
+        Material {
+            name: name.to_string(),
+            composition: vec![Constituent {
+                atomic_number,
+                atomic_weight,
+                weight_fraction: 1.0,
+                mean_ionization_ev,
+            }],
+            density_g_cm3,
+            secondary_emission: SecondaryEmission::default(),
+        }
+    }
+
+    /// Build a pure-element material from the periodic-table entry.
+    pub fn from_element(element: &Element) -> Self {
+        Self::element(
+            element.name,
+            element.atomic_number,
+            element.atomic_weight,
+            element.density_g_cm3,
+            element.mean_ionization_ev(),
+        )
+    }
+
+    /// Build a compound from `(element symbol, weight fraction)` pairs.
+    ///
+    /// Fractions are normalized to sum to one.
+    pub fn from_weight_fractions(
+        name: &str,
+        fractions: &[(&str, f64)],
+        density_g_cm3: f64,
+    ) -> Result<Self, SimError> {
+        if fractions.is_empty() {
+            return Err(SimError::InvalidParameter(format!("Material {} has no elements", name)));
+        }
+        if density_g_cm3 <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("density_g_cm3 ({}) must be > 0", density_g_cm3)));
+        }
+        let total: f64 = fractions.iter().map(|&(_, w)| w).sum();
+        if fractions.iter().any(|&(_, w)| w < 0.0) || total <= 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "Weight fractions of {} must be non-negative with a positive sum",
+                name
+            )));
+        }
+
+        let composition = fractions
+            .iter()
+            .map(|&(symbol, w)| {
+                let element = find_element(symbol)
+                    .ok_or_else(|| SimError::InvalidParameter(format!("Unknown element {} in {}", symbol, name)))?;
+                Ok(Constituent {
+                    atomic_number: element.atomic_number,
+                    atomic_weight: element.atomic_weight,
+                    weight_fraction: w / total,
+                    mean_ionization_ev: element.mean_ionization_ev(),
+                })
+            })
+            .collect::<Result<Vec<_>, SimError>>()?;
+
+        Ok(Material {
+            name: name.to_string(),
+            composition,
+            density_g_cm3,
+            secondary_emission: SecondaryEmission::default(),
+        })
+    }
+
+    /// Build a compound from its formula given as `(element symbol, atom count)` pairs,
+    /// e.g. `[("Si", 1), ("O", 2)]` for SiO2.
+    pub fn from_stoichiometry(
+        name: &str,
+        atoms: &[(&str, u32)],
+        density_g_cm3: f64,
+    ) -> Result<Self, SimError> {
+        let fractions = atoms
+            .iter()
+            .map(|&(symbol, count)| {
+                let element = find_element(symbol)
+                    .ok_or_else(|| SimError::InvalidParameter(format!("Unknown element {} in {}", symbol, name)))?;
+                Ok((symbol, count as f64 * element.atomic_weight))
+            })
+            .collect::<Result<Vec<_>, SimError>>()?;
+        Self::from_weight_fractions(name, &fractions, density_g_cm3)
+    }
+
+    /// Set the SE escape depth (nm) and surface barrier (eV).
+    pub fn with_secondary_emission(mut self, escape_depth_nm: f64, work_function_ev: f64) -> Self {
+        self.secondary_emission = SecondaryEmission {
+            escape_depth_nm,
+            work_function_ev,
+        };
+        self
+    }
+
+    /// Check that the composition, density and surface properties can be simulated.
+    pub fn validate(&self) -> Result<(), SimError> {
+        let total: f64 = self.composition.iter().map(|c| c.weight_fraction).sum();
+        if self.composition.is_empty() || total <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("Material {} has no elements", self.name)));
+        }
+        if self.density_g_cm3 <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("density_g_cm3 ({}) must be > 0", self.density_g_cm3)));
+        }
+        let invalid = |c: &Constituent| {
+            c.atomic_number == 0 || c.atomic_weight <= 0.0 || c.weight_fraction < 0.0 || c.mean_ionization_ev <= 0.0
+        };
+        if let Some(c) = self.composition.iter().find(|c| invalid(c)) {
+            return Err(SimError::InvalidParameter(format!(
+                "Element Z={} of {} needs Z, atomic weight and ionization energy > 0 and a fraction >= 0",
+                c.atomic_number, self.name
+            )));
+        }
+        let se = self.secondary_emission;
+        if se.escape_depth_nm <= 0.0 || se.work_function_ev <= 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "SE escape depth ({} nm) and work function ({} eV) of {} must be > 0",
+                se.escape_depth_nm, se.work_function_ev, self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether the material contains more than one element.
+    pub fn is_compound(&self) -> bool {
+        self.composition.len() > 1
+    }
+
+    /// Mass-fraction weighted mean atomic number, which governs backscatter contrast.
+    pub fn effective_atomic_number(&self) -> f64 {
+        self.composition
+            .iter()
+            .map(|c| c.weight_fraction * c.atomic_number as f64)
+            .sum()
+    }
+
+    /// Mean ionization energy of the material in eV (Bragg additivity rule).
+    pub fn mean_ionization_ev(&self) -> f64 {
+        let (weighted_log, norm) = self.composition.iter().fold((0.0, 0.0), |(acc, norm), c| {
+            let electrons = c.weight_fraction * c.atomic_number as f64 / c.atomic_weight;
+            (acc + electrons * c.mean_ionization_ev.ln(), norm + electrons)
+        });
+        (weighted_log / norm).exp()
+    }
 }
 
 /// Retrieve a preset material by name (case-insensitive).
 ///
+/// Named presets are searched first; any other element can be requested by
+/// symbol or name (e.g. `"Au"` or `"gold"`) from the periodic table. A symbol
+/// of an element with a preset (e.g. `"Si"`) gives that preset.
 /// Returns a cloned `Material` if found, or `None` otherwise.
 pub fn get_preset_material(name: &str) -> Option<Material> {
-    presets::PRESETS
-        .iter()
-        .find(|m| m.name.eq_ignore_ascii_case(name))
-        .cloned()
+    let preset = |name: &str| presets::PRESETS.iter().find(|m| m.name.eq_ignore_ascii_case(name)).cloned();
+    preset(name).or_else(|| {
+        let element = find_element(name)?;
+        preset(element.name).or_else(|| Some(Material::from_element(element)))
+    })
 }
 
-/// List all available preset material names.
+/// List the names of the curated presets (elements are also resolvable by symbol).
 pub fn list_preset_names() -> Vec<&'static str> {
     presets::PRESETS.iter().map(|m| m.name.as_str()).collect()
 }
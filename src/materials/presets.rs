@@ -1,23 +1,27 @@
 use once_cell::sync::Lazy;
+use crate::materials::elements::find_element;
 use crate::materials::Material;
 
-/// List of built-in materials (Cu, Si, C).
+/// List of built-in materials: elements (Cu, Si, C) and common compounds (SiO2, GaAs, Fe2O3).
+///
+/// Each preset carries its SE escape depth and surface barrier (work function or
+/// electron affinity); other elements use the metal-like default.
 pub static PRESETS: Lazy<Vec<Material>> = Lazy::new(|| {
+    let element = |symbol: &str| {
+        Material::from_element(find_element(symbol).expect("element present in periodic table"))
+    };
     vec![
-        Material {
-            name: "Copper".to_string(),
-            atomic_number: 29,
-            density_g_cm3: 8.96,
-        },
-        Material {
-            name: "Silicon".to_string(),
-            atomic_number: 14,
-            density_g_cm3: 2.33,
-        },
-        Material {
-            name: "Carbon".to_string(),
-            atomic_number: 6,
-            density_g_cm3: 2.0,
-        },
+        element("Cu").with_secondary_emission(1.0, 4.65),
+        element("Si").with_secondary_emission(2.0, 4.05),
+        element("C").with_secondary_emission(3.0, 5.0),
+        Material::from_stoichiometry("SiO2", &[("Si", 1), ("O", 2)], 2.65)
+            .expect("valid SiO2 preset")
+            .with_secondary_emission(10.0, 0.9),
+        Material::from_stoichiometry("GaAs", &[("Ga", 1), ("As", 1)], 5.32)
+            .expect("valid GaAs preset")
+            .with_secondary_emission(2.5, 4.07),
+        Material::from_stoichiometry("Fe2O3", &[("Fe", 2), ("O", 3)], 5.24)
+            .expect("valid Fe2O3 preset")
+            .with_secondary_emission(4.0, 4.7),
     ]
 });
@@ -0,0 +1,296 @@
+//! Volumetric samples built from primitive shapes with constructive solid geometry.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimError;
+use crate::materials::Material;
+
+/// Deepest operand stack the engine evaluates per region.
+const MAX_STACK_DEPTH: usize = 64;
+
+// Primitive kinds and postfix operators understood by the engine.
+pub(crate) const KIND_BOX: i32 = 1;
+pub(crate) const KIND_SPHERE: i32 = 2;
+pub(crate) const KIND_CYLINDER: i32 = 3;
+pub(crate) const KIND_LAYER: i32 = 4;
+pub(crate) const KIND_HALF_SPACE: i32 = 5;
+pub(crate) const OP_UNION: i32 = -1;
+pub(crate) const OP_DIFFERENCE: i32 = -2;
+pub(crate) const OP_INTERSECTION: i32 = -3;
+
+/// Parameters passed to the engine per primitive.
+pub(crate) const SHAPE_PARAMS: usize = 8;
+
+/// A solid made of primitives and boolean operations.
+///
+/// Coordinates are in nm with the beam axis at x = y = 0 and z increasing into
+/// the sample, so z = 0 is the nominal surface and positive z is depth.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    /// Axis-aligned box between two corners.
+    Box { min_nm: [f64; 3], max_nm: [f64; 3] },
+    Sphere { center_nm: [f64; 3], radius_nm: f64 },
+    /// Capped cylinder centred on `center_nm`, extending `length_nm / 2` either
+    /// way along `axis` (normalised by the engine).
+    Cylinder { center_nm: [f64; 3], axis: [f64; 3], radius_nm: f64, length_nm: f64 },
+    /// Slab between two depths, unbounded in x and y.
+    Layer { top_nm: f64, bottom_nm: f64 },
+    /// Everything below `top_nm`, e.g. a substrate.
+    HalfSpace { top_nm: f64 },
+    /// Points inside any of the shapes.
+    Union { shapes: Vec<Shape> },
+    /// Points inside `base` but in none of the `subtract` shapes.
+    Difference { base: Box<Shape>, subtract: Vec<Shape> },
+    /// Points inside every one of the shapes.
+    Intersection { shapes: Vec<Shape> },
+}
+
+impl Shape {
+    /// Whether a point (nm) lies inside the shape, surfaces included.
+    pub fn contains(&self, p: [f64; 3]) -> bool {
+        match self {
+            Shape::Box { min_nm, max_nm } => (0..3).all(|i| p[i] >= min_nm[i] && p[i] <= max_nm[i]),
+            Shape::Sphere { center_nm, radius_nm } => {
+                (0..3).map(|i| (p[i] - center_nm[i]).powi(2)).sum::<f64>() <= radius_nm * radius_nm
+            }
+            Shape::Cylinder { center_nm, axis, radius_nm, length_nm } => {
+                let norm = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+                let v = [p[0] - center_nm[0], p[1] - center_nm[1], p[2] - center_nm[2]];
+                let h = (v[0] * axis[0] + v[1] * axis[1] + v[2] * axis[2]) / norm;
+                let radial_sq = v[0] * v[0] + v[1] * v[1] + v[2] * v[2] - h * h;
+                h.abs() <= length_nm / 2.0 && radial_sq <= radius_nm * radius_nm
+            }
+            Shape::Layer { top_nm, bottom_nm } => p[2] >= *top_nm && p[2] <= *bottom_nm,
+            Shape::HalfSpace { top_nm } => p[2] >= *top_nm,
+            Shape::Union { shapes } => shapes.iter().any(|s| s.contains(p)),
+            Shape::Difference { base, subtract } => {
+                base.contains(p) && !subtract.iter().any(|s| s.contains(p))
+            }
+            Shape::Intersection { shapes } => shapes.iter().all(|s| s.contains(p)),
+        }
+    }
+
+    /// Check dimensions and that the engine can evaluate the shape.
+    pub fn validate(&self) -> Result<(), SimError> {
+        match self {
+            Shape::Box { min_nm, max_nm } => {
+                if (0..3).any(|i| min_nm[i] >= max_nm[i]) {
+                    return Err(SimError::InvalidParameter(format!(
+                        "box min {:?} must be below max {:?} on every axis",
+                        min_nm, max_nm
+                    )));
+                }
+            }
+            Shape::Sphere { radius_nm, .. } => {
+                if *radius_nm <= 0.0 {
+                    return Err(SimError::InvalidParameter(format!("sphere radius_nm ({}) must be > 0", radius_nm)));
+                }
+            }
+            Shape::Cylinder { axis, radius_nm, length_nm, .. } => {
+                if axis.iter().all(|a| *a == 0.0) {
+                    return Err(SimError::InvalidParameter("cylinder axis must not be zero".into()));
+                }
+                if *radius_nm <= 0.0 || *length_nm <= 0.0 {
+                    return Err(SimError::InvalidParameter(format!(
+                        "cylinder radius_nm ({}) and length_nm ({}) must be > 0",
+                        radius_nm, length_nm
+                    )));
+                }
+            }
+            Shape::Layer { top_nm, bottom_nm } => {
+                if top_nm >= bottom_nm {
+                    return Err(SimError::InvalidParameter(format!(
+                        "layer top_nm ({}) must be above bottom_nm ({})",
+                        top_nm, bottom_nm
+                    )));
+                }
+            }
+            Shape::HalfSpace { .. } => {}
+            Shape::Union { shapes } | Shape::Intersection { shapes } => {
+                if shapes.is_empty() {
+                    let name = if matches!(self, Shape::Union { .. }) { "union" } else { "intersection" };
+                    return Err(SimError::InvalidParameter(format!("{} needs at least one shape", name)));
+                }
+                for shape in shapes {
+                    shape.validate()?;
+                }
+            }
+            Shape::Difference { base, subtract } => {
+                base.validate()?;
+                for shape in subtract {
+                    shape.validate()?;
+                }
+            }
+        }
+        if self.stack_depth() > MAX_STACK_DEPTH {
+            return Err(SimError::InvalidParameter(format!(
+                "shape is nested too deeply (more than {} levels)",
+                MAX_STACK_DEPTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// Operand stack needed to evaluate the shape's postfix program.
+    fn stack_depth(&self) -> usize {
+        match self {
+            Shape::Union { shapes } | Shape::Intersection { shapes } => shapes
+                .iter()
+                .enumerate()
+                .map(|(i, s)| s.stack_depth() + i.min(1))
+                .max()
+                .unwrap_or(1),
+            Shape::Difference { base, subtract } => subtract
+                .iter()
+                .map(|s| s.stack_depth() + 1)
+                .chain(std::iter::once(base.stack_depth()))
+                .max()
+                .unwrap_or(1),
+            _ => 1,
+        }
+    }
+
+    /// Append the shape's primitives and postfix program.
+    fn compile(&self, primitives: &mut Vec<(i32, [f64; SHAPE_PARAMS])>, program: &mut Vec<i32>) {
+        let mut primitive = |kind: i32, params: &[f64], program: &mut Vec<i32>| {
+            let mut p = [0.0; SHAPE_PARAMS];
+            p[..params.len()].copy_from_slice(params);
+            primitives.push((kind, p));
+            program.push(primitives.len() as i32);
+        };
+        match self {
+            Shape::Box { min_nm, max_nm } => {
+                primitive(KIND_BOX, &[min_nm[0], min_nm[1], min_nm[2], max_nm[0], max_nm[1], max_nm[2]], program)
+            }
+            Shape::Sphere { center_nm, radius_nm } => {
+                primitive(KIND_SPHERE, &[center_nm[0], center_nm[1], center_nm[2], *radius_nm], program)
+            }
+            Shape::Cylinder { center_nm, axis, radius_nm, length_nm } => {
+                let norm = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+                primitive(
+                    KIND_CYLINDER,
+                    &[
+                        center_nm[0], center_nm[1], center_nm[2],
+                        axis[0] / norm, axis[1] / norm, axis[2] / norm,
+                        *radius_nm, length_nm / 2.0,
+                    ],
+                    program,
+                )
+            }
+            Shape::Layer { top_nm, bottom_nm } => primitive(KIND_LAYER, &[*top_nm, *bottom_nm], program),
+            Shape::HalfSpace { top_nm } => primitive(KIND_HALF_SPACE, &[*top_nm], program),
+            Shape::Union { shapes } => {
+                for (i, shape) in shapes.iter().enumerate() {
+                    shape.compile(primitives, program);
+                    if i > 0 {
+                        program.push(OP_UNION);
+                    }
+                }
+            }
+            Shape::Intersection { shapes } => {
+                for (i, shape) in shapes.iter().enumerate() {
+                    shape.compile(primitives, program);
+                    if i > 0 {
+                        program.push(OP_INTERSECTION);
+                    }
+                }
+            }
+            Shape::Difference { base, subtract } => {
+                base.compile(primitives, program);
+                for shape in subtract {
+                    shape.compile(primitives, program);
+                    program.push(OP_DIFFERENCE);
+                }
+            }
+        }
+    }
+}
+
+/// A shape filled with one material.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Region {
+    pub shape: Shape,
+    pub material: Material,
+}
+
+/// Volumetric sample made of material regions; everything outside them is vacuum.
+///
+/// Later regions take precedence where regions overlap, so a buried particle is
+/// a substrate region followed by the particle's region.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SampleGeometry {
+    pub regions: Vec<Region>,
+}
+
+/// Flat arrays describing a geometry to the engine.
+pub(crate) struct CompiledGeometry {
+    pub kinds: Vec<i32>,
+    /// `SHAPE_PARAMS` values per primitive.
+    pub params: Vec<f64>,
+    pub program: Vec<i32>,
+    pub region_lengths: Vec<i32>,
+    /// Index into `materials` of each region's material.
+    pub region_materials: Vec<usize>,
+    /// Distinct materials, matched by name.
+    pub materials: Vec<Material>,
+}
+
+impl SampleGeometry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a region on top of the existing ones.
+    pub fn with_region(mut self, shape: Shape, material: Material) -> Self {
+        self.regions.push(Region { shape, material });
+        self
+    }
+
+    /// Material at a point (nm), or `None` in vacuum.
+    pub fn material_at(&self, p: [f64; 3]) -> Option<&Material> {
+        self.regions.iter().rev().find(|r| r.shape.contains(p)).map(|r| &r.material)
+    }
+
+    /// Check every region's shape.
+    pub fn validate(&self) -> Result<(), SimError> {
+        if self.regions.is_empty() {
+            return Err(SimError::InvalidParameter("Sample geometry has no regions".into()));
+        }
+        for (i, region) in self.regions.iter().enumerate() {
+            region.shape.validate().map_err(|e| {
+                SimError::InvalidParameter(format!("region {} ({}): {}", i, region.material.name, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn compile(&self) -> CompiledGeometry {
+        let mut primitives = Vec::new();
+        let mut program = Vec::new();
+        let mut region_lengths = Vec::with_capacity(self.regions.len());
+        let mut region_materials = Vec::with_capacity(self.regions.len());
+        let mut materials: Vec<Material> = Vec::new();
+        for region in &self.regions {
+            let start = program.len();
+            region.shape.compile(&mut primitives, &mut program);
+            region_lengths.push((program.len() - start) as i32);
+            let index = match materials.iter().position(|m| m.name == region.material.name) {
+                Some(index) => index,
+                None => {
+                    materials.push(region.material.clone());
+                    materials.len() - 1
+                }
+            };
+            region_materials.push(index);
+        }
+        CompiledGeometry {
+            kinds: primitives.iter().map(|(kind, _)| *kind).collect(),
+            params: primitives.iter().flat_map(|(_, p)| p.iter().copied()).collect(),
+            program,
+            region_lengths,
+            region_materials,
+            materials,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Surface height maps loaded from images, text grids or raw binary files.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimError;
+
+/// Surface topography sampled on a regular grid centred on the beam axis.
+///
+/// Heights are in nm above the nominal surface (positive = raised). Electrons
+/// enter and leave the sample through the interpolated surface.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeightMap {
+    pub width: usize,
+    pub height: usize,
+    /// Grid spacing in nm per pixel.
+    pub pixel_size_nm: f64,
+    /// Heights in nm, row-major (x fastest), `width * height` values.
+    pub heights_nm: Vec<f64>,
+}
+
+impl HeightMap {
+    /// Build a height map from row-major heights in nm.
+    pub fn new(width: usize, height: usize, pixel_size_nm: f64, heights_nm: Vec<f64>) -> Result<Self, SimError> {
+        if width == 0 || height == 0 {
+            return Err(SimError::InvalidParameter(format!("Height map must not be empty ({}×{})", width, height)));
+        }
+        if heights_nm.len() != width * height {
+            return Err(SimError::InvalidParameter(format!(
+                "Height map has {} values, expected {}×{}={}",
+                heights_nm.len(), width, height, width * height
+            )));
+        }
+        if pixel_size_nm <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("pixel_size_nm ({}) must be > 0", pixel_size_nm)));
+        }
+        if heights_nm.iter().any(|h| !h.is_finite()) {
+            return Err(SimError::InvalidParameter("Height map contains non-finite values".into()));
+        }
+        Ok(HeightMap { width, height, pixel_size_nm, heights_nm })
+    }
+
+    /// Load a 16-bit grayscale PNG, mapping 0..=65535 linearly onto 0..=`height_range_nm`.
+    pub fn from_png16<P: AsRef<Path>>(path: P, pixel_size_nm: f64, height_range_nm: f64) -> Result<Self, SimError> {
+        let path = path.as_ref();
+        let img = image::open(path)
+            .map_err(|e| SimError::Io(format!("Cannot read height map {}: {}", path.display(), e)))?
+            .into_luma16();
+        let (width, height) = img.dimensions();
+        let heights = img
+            .into_raw()
+            .into_iter()
+            .map(|v| v as f64 / u16::MAX as f64 * height_range_nm)
+            .collect();
+        Self::new(width as usize, height as usize, pixel_size_nm, heights)
+    }
+
+    /// Load a text grid of heights in nm: one image row per line, values separated
+    /// by commas or whitespace. Blank lines and lines starting with `#` are skipped.
+    pub fn from_csv<P: AsRef<Path>>(path: P, pixel_size_nm: f64) -> Result<Self, SimError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| SimError::Io(format!("Cannot read height map {}: {}", path.display(), e)))?;
+        Self::parse_csv(&text, pixel_size_nm)
+    }
+
+    /// Parse the text grid format accepted by [`HeightMap::from_csv`].
+    pub fn parse_csv(text: &str, pixel_size_nm: f64) -> Result<Self, SimError> {
+        let mut width = 0;
+        let mut rows = 0;
+        let mut heights = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let row = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(|v| {
+                    v.parse::<f64>()
+                        .map_err(|_| {
+                            SimError::InvalidParameter(format!("Invalid height {:?} on line {}", v, line_no + 1))
+                        })
+                })
+                .collect::<Result<Vec<_>, SimError>>()?;
+            if rows == 0 {
+                width = row.len();
+            } else if row.len() != width {
+                return Err(SimError::InvalidParameter(format!(
+                    "Line {} has {} values, expected {}",
+                    line_no + 1, row.len(), width
+                )));
+            }
+            heights.extend(row);
+            rows += 1;
+        }
+        Self::new(width, rows, pixel_size_nm, heights)
+    }
+
+    /// Load a raw grid of little-endian `f32` heights in nm, row-major.
+    pub fn from_raw_f32<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        pixel_size_nm: f64,
+    ) -> Result<Self, SimError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|e| SimError::Io(format!("Cannot read height map {}: {}", path.display(), e)))?;
+        if bytes.len() != width * height * 4 {
+            return Err(SimError::InvalidParameter(format!(
+                "Raw height map {} has {} bytes, expected {}×{}×4={}",
+                path.display(), bytes.len(), width, height, width * height * 4
+            )));
+        }
+        let heights = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Self::new(width, height, pixel_size_nm, heights)
+    }
+
+    /// A flat surface tilted by `tilt_deg` about the y axis, rising towards +x,
+    /// sampled over a square `size_nm` wide. The surface levels off beyond it.
+    pub fn tilted_plane(tilt_deg: f64, size_nm: f64) -> Result<Self, SimError> {
+        const POINTS: usize = 65;
+        if tilt_deg.is_nan() || tilt_deg.abs() >= 90.0 {
+            return Err(SimError::InvalidParameter(format!("Sample tilt ({}°) must be within ±90°", tilt_deg)));
+        }
+        if size_nm <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("Tilted plane size ({} nm) must be > 0", size_nm)));
+        }
+        let spacing = size_nm / (POINTS - 1) as f64;
+        let slope = tilt_deg.to_radians().tan();
+        let row: Vec<f64> = (0..POINTS).map(|i| slope * (i as f64 - 0.5 * (POINTS - 1) as f64) * spacing).collect();
+        Self::new(POINTS, POINTS, spacing, row.repeat(POINTS))
+    }
+
+    /// Physical extent of the map in nm (x, y).
+    pub fn extent_nm(&self) -> (f64, f64) {
+        (
+            (self.width - 1) as f64 * self.pixel_size_nm,
+            (self.height - 1) as f64 * self.pixel_size_nm,
+        )
+    }
+}
@@ -0,0 +1,86 @@
+//! Thin films and multilayer stacks, optionally on a substrate.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimError;
+use crate::materials::Material;
+use crate::sample::csg::{SampleGeometry, Shape};
+
+/// One film of a stack.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Layer {
+    pub thickness_nm: f64,
+    pub material: Material,
+}
+
+/// Films stacked from the top surface (z = 0) downwards, unbounded in x and y.
+///
+/// Without a substrate the stack is a free-standing film: electrons that get
+/// through it leave from the bottom and form the transmitted image channel.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LayerStack {
+    /// Layers in order from the top surface down.
+    pub layers: Vec<Layer>,
+    /// Semi-infinite material below the last layer, if any.
+    #[serde(default)]
+    pub substrate: Option<Material>,
+}
+
+impl LayerStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a layer below the existing ones.
+    pub fn with_layer(mut self, thickness_nm: f64, material: Material) -> Self {
+        self.layers.push(Layer { thickness_nm, material });
+        self
+    }
+
+    /// Put the stack on a semi-infinite substrate.
+    pub fn with_substrate(mut self, material: Material) -> Self {
+        self.substrate = Some(material);
+        self
+    }
+
+    /// Whether electrons can leave through the bottom of the stack.
+    pub fn is_free_standing(&self) -> bool {
+        self.substrate.is_none()
+    }
+
+    /// Combined thickness of all layers in nm.
+    pub fn total_thickness_nm(&self) -> f64 {
+        self.layers.iter().map(|l| l.thickness_nm).sum()
+    }
+
+    /// Check that the stack has material and every layer a positive thickness.
+    pub fn validate(&self) -> Result<(), SimError> {
+        if self.layers.is_empty() && self.substrate.is_none() {
+            return Err(SimError::InvalidParameter("Layer stack has neither layers nor a substrate".into()));
+        }
+        for (i, layer) in self.layers.iter().enumerate() {
+            if layer.thickness_nm <= 0.0 {
+                return Err(SimError::InvalidParameter(format!(
+                    "layer {} ({}) thickness_nm ({}) must be > 0",
+                    i, layer.material.name, layer.thickness_nm
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The equivalent region geometry: one slab per layer, then the substrate.
+    pub fn to_geometry(&self) -> SampleGeometry {
+        let mut geometry = SampleGeometry::new();
+        let mut top_nm = 0.0;
+        for layer in &self.layers {
+            let bottom_nm = top_nm + layer.thickness_nm;
+            geometry = geometry.with_region(Shape::Layer { top_nm, bottom_nm }, layer.material.clone());
+            top_nm = bottom_nm;
+        }
+        if let Some(substrate) = &self.substrate {
+            geometry = geometry.with_region(Shape::HalfSpace { top_nm }, substrate.clone());
+        }
+        geometry
+    }
+}
@@ -0,0 +1,9 @@
+//! Sample description beyond its bulk material: surface topography,
+//! layered films and volumetric geometry.
+pub mod height_map;
+pub mod csg;
+pub mod layers;
+
+pub use csg::{Region, SampleGeometry, Shape};
+pub use height_map::HeightMap;
+pub use layers::{Layer, LayerStack};
@@ -0,0 +1,157 @@
+//! Electron-optical column model that predicts the probe size and current.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimError;
+
+const NM_PER_MM: f64 = 1.0e6;
+
+/// Electron gun type, which sets the source brightness and energy spread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ElectronSource {
+    /// Thermionic tungsten hairpin.
+    Tungsten,
+    /// Thermionic lanthanum hexaboride crystal.
+    LaB6,
+    /// Schottky field-emission gun.
+    SchottkyFeg,
+}
+
+impl ElectronSource {
+    /// Typical reduced brightness in A/(m² sr V).
+    pub fn reduced_brightness(self) -> f64 {
+        match self {
+            ElectronSource::Tungsten => 5.0e4,
+            ElectronSource::LaB6 => 5.0e5,
+            ElectronSource::SchottkyFeg => 5.0e7,
+        }
+    }
+
+    /// Typical energy spread (FWHM) in eV.
+    pub fn energy_spread_ev(self) -> f64 {
+        match self {
+            ElectronSource::Tungsten => 2.0,
+            ElectronSource::LaB6 => 1.0,
+            ElectronSource::SchottkyFeg => 0.7,
+        }
+    }
+}
+
+/// Source and objective lens of the microscope.
+///
+/// The engine adds the demagnified source image and the spherical-aberration,
+/// diffraction and chromatic discs in quadrature at the probe's convergence
+/// angle, which follows from the objective aperture and working distance in
+/// `SimulationParameters`. The probe diameter then follows from the beam
+/// current, or with `target_spot_nm` the current from the probe diameter.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColumnConfig {
+    pub source: ElectronSource,
+    /// Reduced brightness in A/(m² sr V).
+    pub reduced_brightness: f64,
+    /// Energy spread (FWHM) of the source in eV.
+    pub energy_spread_ev: f64,
+    /// Spherical aberration coefficient of the objective lens in mm.
+    pub cs_mm: f64,
+    /// Chromatic aberration coefficient of the objective lens in mm.
+    pub cc_mm: f64,
+    /// Probe diameter to form, in nm; the beam current is then the most it can carry.
+    #[serde(default)]
+    pub target_spot_nm: Option<f64>,
+}
+
+impl ColumnConfig {
+    /// A conventional pinhole objective lens with the given gun.
+    pub fn new(source: ElectronSource) -> Self {
+        ColumnConfig {
+            source,
+            reduced_brightness: source.reduced_brightness(),
+            energy_spread_ev: source.energy_spread_ev(),
+            cs_mm: 20.0,
+            cc_mm: 10.0,
+            target_spot_nm: None,
+        }
+    }
+
+    /// Set the objective lens aberration coefficients in mm.
+    pub fn with_aberrations(mut self, cs_mm: f64, cc_mm: f64) -> Self {
+        self.cs_mm = cs_mm;
+        self.cc_mm = cc_mm;
+        self
+    }
+
+    /// Form a probe of `spot_nm` and derive the beam current from it.
+    pub fn with_target_spot(mut self, spot_nm: f64) -> Self {
+        self.target_spot_nm = Some(spot_nm);
+        self
+    }
+
+    /// Check that the source and lens values are physical.
+    pub fn validate(&self) -> Result<(), SimError> {
+        if self.reduced_brightness <= 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "reduced_brightness ({}) must be > 0",
+                self.reduced_brightness
+            )));
+        }
+        if self.energy_spread_ev < 0.0 || self.cs_mm < 0.0 || self.cc_mm < 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "energy_spread_ev ({}), cs_mm ({}) and cc_mm ({}) must be >= 0",
+                self.energy_spread_ev, self.cs_mm, self.cc_mm
+            )));
+        }
+        if let Some(spot) = self.target_spot_nm {
+            if spot <= 0.0 {
+                return Err(SimError::InvalidParameter(format!("target_spot_nm ({} nm) must be > 0", spot)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Diameter in nm of a probe carrying `current_na` at `energy_kev` and
+    /// convergence semi-angle `convergence_rad`: the demagnified source image
+    /// and the aberration and diffraction discs added in quadrature.
+    pub fn probe_diameter_nm(&self, energy_kev: f64, current_na: f64, convergence_rad: f64) -> f64 {
+        // Source image size from the brightness: I = β (π d² / 4) (π α²)
+        let brightness = self.reduced_brightness * energy_kev * 1000.0;
+        let d_gaussian = 2.0 / PI * (current_na * 1.0e-9 / brightness).sqrt() / convergence_rad * 1.0e9;
+        let [d_spherical, d_diffraction, d_chromatic] = self.aberration_discs_nm(energy_kev, convergence_rad);
+        (d_gaussian.powi(2) + d_spherical.powi(2) + d_diffraction.powi(2) + d_chromatic.powi(2)).sqrt()
+    }
+
+    /// Largest current in nA a probe of `diameter_nm` can carry. Fails if the
+    /// diameter is not above the smallest probe the column can form.
+    pub fn probe_current_na(&self, energy_kev: f64, diameter_nm: f64, convergence_rad: f64) -> Result<f64, SimError> {
+        let smallest = self.smallest_spot_nm(energy_kev, convergence_rad);
+        if diameter_nm <= smallest {
+            return Err(SimError::InvalidParameter(format!(
+                "target_spot_nm ({} nm) is not above the smallest probe the column forms at {} keV and {} mrad ({:.3} nm)",
+                diameter_nm, energy_kev, convergence_rad * 1.0e3, smallest
+            )));
+        }
+        let brightness = self.reduced_brightness * energy_kev * 1000.0;
+        let d_gaussian_sq = diameter_nm.powi(2) - smallest.powi(2);
+        Ok(brightness * (PI / 4.0 * d_gaussian_sq * 1.0e-18) * (PI * convergence_rad.powi(2)) * 1.0e9)
+    }
+
+    /// Diameter in nm of a probe carrying no current: the aberration and
+    /// diffraction discs alone, added in quadrature.
+    pub fn smallest_spot_nm(&self, energy_kev: f64, convergence_rad: f64) -> f64 {
+        self.aberration_discs_nm(energy_kev, convergence_rad).iter().map(|d| d * d).sum::<f64>().sqrt()
+    }
+
+    /// Spherical-aberration, diffraction and chromatic disc diameters in nm.
+    fn aberration_discs_nm(&self, energy_kev: f64, convergence_rad: f64) -> [f64; 3] {
+        // Relativistic electron wavelength in nm
+        let volts = energy_kev * 1000.0;
+        let wavelength = 1.226 / (volts * (1.0 + 0.9785e-6 * volts)).sqrt();
+        [
+            0.5 * self.cs_mm * NM_PER_MM * convergence_rad.powi(3),
+            1.22 * wavelength / convergence_rad,
+            self.cc_mm * NM_PER_MM * (self.energy_spread_ev / volts) * convergence_rad,
+        ]
+    }
+}
@@ -0,0 +1,81 @@
+//! Handle to a batch of simulations running in the background.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::backend::CancelFlag;
+use crate::error::SimError;
+use crate::simulation::results::SimulationResult;
+
+/// How far a batch has got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// Pixels finished across all jobs of the batch.
+    pub completed: u64,
+    /// Pixels of all jobs of the batch.
+    pub total: u64,
+    /// Time since the batch started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Finished share of the batch, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.completed as f64 / self.total as f64
+    }
+
+    /// Time left at the rate so far, or `None` before the first scan line is done.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.completed == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.completed) as f64 / self.completed as f64;
+        Some(self.elapsed.mul_f64(remaining))
+    }
+}
+
+/// A batch started by `SimulationManager::start`.
+///
+/// Dropping the handle lets the batch finish unobserved.
+pub struct JobHandle {
+    pub(crate) thread: JoinHandle<Vec<Result<SimulationResult, SimError>>>,
+    pub(crate) completed: Arc<AtomicU64>,
+    pub(crate) total: u64,
+    pub(crate) cancel: CancelFlag,
+    pub(crate) started: Instant,
+}
+
+impl JobHandle {
+    /// Pixels finished so far, updated after every scan line.
+    pub fn progress(&self) -> Progress {
+        Progress {
+            completed: self.completed.load(Ordering::Relaxed).min(self.total),
+            total: self.total,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Asks every job to stop at its next scan line, on engines that support
+    /// cancellation. Cancelled jobs still return their partial images.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether every job has returned, so `wait` will not block.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Blocks until the batch is done and returns its results in queue order.
+    pub fn wait(self) -> Vec<Result<SimulationResult, SimError>> {
+        match self.thread.join() {
+            Ok(results) => results,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
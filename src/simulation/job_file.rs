@@ -0,0 +1,616 @@
+//! Declarative job files: simulation settings kept as TOML, JSON or YAML
+//! next to the experiment instead of in code.
+//!
+//! A job file declares `version = 1` and any of the sections `beam`, `scan`,
+//! `sample`, `detector` and `output`, plus a top-level `seed`; whatever it
+//! leaves out keeps the defaults of `SimulationParameters::new(20, 1, 512, 10)`.
+//!
+//! ```toml
+//! version = 1
+//! include = ["base.toml"]   # loaded first; this file overrides them
+//! seed = 7
+//!
+//! [beam]
+//! energy_kev = "${ENERGY_KEV:-15}"
+//!
+//! [sample]
+//! material = "Au"
+//! tilt_deg = 30
+//! ```
+//!
+//! `sample.geometry`, `sample.layers` and `beam.column` are tables in the
+//! serialized form of `SampleGeometry`, `LayerStack` and `ColumnConfig`, with
+//! their materials written out in full.
+//!
+//! `${NAME}` is replaced by the environment variable `NAME` and
+//! `${NAME:-default}` falls back to `default` when it is unset; `$$` is a
+//! literal `$`. Relative input paths are resolved against the file that sets
+//! them, output paths against the working directory.
+//!
+//! Overrides take precedence over the files. Overriding one of two alternative
+//! keys, e.g. `scan.electrons_per_pixel` against `scan.dose_e_per_nm2`, drops
+//! the other from the files, and overriding `scan.width` alone scales a
+//! `scan.height` from the files to keep the aspect ratio.
+
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use config::{Config, ConfigError, File, FileFormat};
+use serde::Deserialize;
+
+use crate::error::SimError;
+use crate::imaging::detector::DetectorConfig;
+use crate::imaging::formation::DetectorSignal;
+use crate::materials::custom::CustomMaterialSpec;
+use crate::materials::get_preset_material;
+use crate::sample::{HeightMap, LayerStack, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
+use crate::simulation::parameters::SimulationParameters;
+
+/// Schema version this build reads.
+pub const JOB_FILE_VERSION: u32 = 1;
+
+/// Image width of a job that sets none.
+const DEFAULT_WIDTH: i32 = 512;
+
+/// Keys of which a job gives at most one; an override of either replaces the
+/// other.
+const ALTERNATIVES: [(&str, &str); 4] = [
+    ("scan.field_of_view_um", "scan.magnification"),
+    ("scan.electrons_per_pixel", "scan.dose_e_per_nm2"),
+    ("sample.material", "sample.custom_material"),
+    ("sample.height_map", "sample.tilt_deg"),
+];
+
+/// A job file validated into simulation parameters.
+#[derive(Clone, Debug)]
+pub struct JobFile {
+    pub params: SimulationParameters,
+    pub output: JobOutput,
+}
+
+/// Where a job asks its results to be written.
+#[derive(Clone, Debug, Default)]
+pub struct JobOutput {
+    /// PNG receiving the image.
+    pub image: Option<PathBuf>,
+    /// CSV receiving the recorded trajectories.
+    pub trajectories_csv: Option<PathBuf>,
+}
+
+impl JobFile {
+    /// Load a job file and the files it includes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SimError> {
+        Self::load_with_overrides(path, &[])
+    }
+
+    /// Load a job file, then set each dotted key of `overrides` (e.g.
+    /// `("beam.energy_kev", "15")`) over whatever the files say.
+    pub fn load_with_overrides<P: AsRef<Path>>(path: P, overrides: &[(String, String)]) -> Result<Self, SimError> {
+        let mut sources = Vec::new();
+        read_source(path.as_ref(), &mut Vec::new(), &mut sources)?;
+        Self::build(sources, overrides)
+    }
+
+    /// The defaults with each dotted key of `overrides` set, as if loaded
+    /// from a job file declaring nothing but its version.
+    pub fn from_overrides(overrides: &[(String, String)]) -> Result<Self, SimError> {
+        let version = ("version".to_string(), JOB_FILE_VERSION.to_string());
+        Self::build(Vec::new(), &[&[version], overrides].concat())
+    }
+
+    fn build(sources: Vec<Source>, overrides: &[(String, String)]) -> Result<Self, SimError> {
+        let mut job = Job { sources, overrides: overrides.iter().map(|(key, _)| key.clone()).collect() };
+        let mut builder = Config::builder();
+        for source in &job.sources {
+            builder = builder.add_source(File::from_str(&source.text, source.format));
+        }
+
+        // A new width keeps the aspect ratio of a rectangular scan from the files
+        let width = overrides.iter().rev().find(|(key, _)| key == "scan.width");
+        let width = width.and_then(|(_, value)| value.parse::<i64>().ok());
+        if let (Some(width), false) = (width, job.overrides.iter().any(|o| o == "scan.height")) {
+            let files = builder.build_cloned().map_err(|e| job.config_error(e))?;
+            let previous = files.get::<i64>("scan.width").unwrap_or(DEFAULT_WIDTH.into());
+            if let (Ok(height), true) = (files.get::<i64>("scan.height"), previous > 0) {
+                let scaled = (height as f64 * width as f64 / previous as f64).round().max(1.0) as i64;
+                builder = builder.set_override("scan.height", scaled).map_err(|e| job.config_error(e))?;
+                job.overrides.push("scan.height".into());
+            }
+        }
+
+        // Settings the overrides replace, unless overridden themselves
+        let mut dropped = Vec::new();
+        for (key, value) in overrides {
+            for (first, second) in ALTERNATIVES {
+                if key == first {
+                    dropped.push(second);
+                } else if key == second {
+                    dropped.push(first);
+                }
+            }
+            // The SE weight only applies to the mixed signal
+            if key == "detector.signal" && value != "mixed" {
+                dropped.push("detector.se_weight");
+            }
+        }
+        dropped.retain(|key| !job.overrides.iter().any(|o| o == key));
+        for key in dropped {
+            builder = builder.set_override(key, None::<String>).map_err(|e| job.config_error(e))?;
+        }
+        for (key, value) in overrides {
+            builder = builder
+                .set_override(key.as_str(), value.as_str())
+                .map_err(|e| job.error_at(key, format!("Invalid override {}={}: {}", key, value, e)))?;
+        }
+
+        let spec: JobSpec = builder
+            .build()
+            .and_then(Config::try_deserialize)
+            .map_err(|e| job.config_error(e))?;
+        job.validate(spec)
+    }
+}
+
+/// One file of a job after environment substitution.
+struct Source {
+    path: PathBuf,
+    text: String,
+    format: FileFormat,
+}
+
+/// Read `path` and, before it, the files it includes into `sources`.
+/// `stack` holds the files being included, to catch cycles.
+fn read_source(path: &Path, stack: &mut Vec<PathBuf>, sources: &mut Vec<Source>) -> Result<(), SimError> {
+    let error = |line, message: String| SimError::JobFile { path: path.display().to_string(), line, message };
+    let format = match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("toml") => FileFormat::Toml,
+        Some("json") => FileFormat::Json,
+        Some("yaml") | Some("yml") => FileFormat::Yaml,
+        _ => return Err(error(None, "Job files must end in .toml, .json, .yaml or .yml".into())),
+    };
+    let canonical = path.canonicalize().map_err(|e| error(None, format!("Cannot read job file: {}", e)))?;
+    if stack.contains(&canonical) {
+        return Err(error(None, "Job file includes itself".into()));
+    }
+    let raw = fs::read_to_string(path).map_err(|e| error(None, format!("Cannot read job file: {}", e)))?;
+    let text = substitute_env(&raw).map_err(|(line, message)| error(Some(line), message))?;
+
+    let includes = Config::builder()
+        .add_source(File::from_str(&text, format))
+        .build()
+        .map_err(|e| {
+            // The format crates put "line N column M" in their messages
+            let message = e.to_string();
+            let line = message
+                .split("line ")
+                .skip(1)
+                .find_map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok());
+            error(line, message)
+        })?
+        .get::<Vec<String>>("include")
+        .or_else(|e| match e {
+            ConfigError::NotFound(_) => Ok(Vec::new()),
+            e => Err(error(line_of(&text, "include"), e.to_string())),
+        })?;
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for include in includes {
+        read_source(&dir.join(include), stack, sources)?;
+    }
+    stack.pop();
+    sources.push(Source { path: path.to_path_buf(), text, format });
+    Ok(())
+}
+
+/// Replace `${NAME}` and `${NAME:-default}` with environment variables, and
+/// `$$` with `$`, outside `#` comment lines. Errors carry a 1-based line.
+fn substitute_env(text: &str) -> Result<String, (usize, String)> {
+    let mut out = String::with_capacity(text.len());
+    for (n, line) in text.lines().enumerate() {
+        if line.trim_start().starts_with('#') {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let mut rest = line;
+        while let Some(start) = rest.find('$') {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start + 1..];
+            if let Some(after) = tail.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+            } else if let Some(braced) = tail.strip_prefix('{') {
+                let end = braced.find('}').ok_or((n + 1, "Unclosed ${ in job file".to_string()))?;
+                let (name, default) = match braced[..end].split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (&braced[..end], None),
+                };
+                match (std::env::var(name), default) {
+                    (Ok(value), _) => out.push_str(&value),
+                    (Err(_), Some(default)) => out.push_str(default),
+                    (Err(_), None) => return Err((n + 1, format!("Environment variable {} is not set", name))),
+                }
+                rest = &braced[end + 1..];
+            } else {
+                out.push('$');
+                rest = tail;
+            }
+        }
+        out.push_str(rest);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// 1-based line where the dotted `key` (e.g. `beam.energy_kev` or
+/// `scan.offset_um[1]`) is set, found by looking for each segment in turn as a
+/// TOML, JSON or YAML key.
+fn line_of(text: &str, key: &str) -> Option<usize> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut from = 0;
+    let mut found = None;
+    for segment in key.split('.').map(|s| s.split('[').next().unwrap_or(s)).filter(|s| !s.is_empty()) {
+        let offset = lines[from..].iter().position(|line| sets_key(line, segment))?;
+        from += offset;
+        found = Some(from + 1);
+    }
+    found
+}
+
+/// Whether `line` holds `name` as a key: `name =`, `"name":`, `name:`, `[name]` or `name.`.
+fn sets_key(line: &str, name: &str) -> bool {
+    let line = line.split('#').next().unwrap_or(line);
+    line.match_indices(name).any(|(i, _)| {
+        let before = line[..i].chars().next_back();
+        let after = line[i + name.len()..].trim_start_matches(['"', '\'']).trim_start();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            && after.starts_with(['=', ':', ']', '.'])
+    })
+}
+
+/// The loaded files of a job, in increasing precedence.
+struct Job {
+    sources: Vec<Source>,
+    /// Keys set from outside the files.
+    overrides: Vec<String>,
+}
+
+impl Job {
+    /// An error located where `key` is last set, or on the job file itself.
+    /// A job without files reports plain parameter errors.
+    fn error_at(&self, key: &str, message: impl Display) -> SimError {
+        let Some(top) = self.sources.last() else {
+            return SimError::InvalidParameter(message.to_string());
+        };
+        if self.overrides.iter().any(|o| o == key) {
+            let message = format!("{} (set by override)", message);
+            return SimError::JobFile { path: top.path.display().to_string(), line: None, message };
+        }
+        let located = self
+            .sources
+            .iter()
+            .rev()
+            .find_map(|source| line_of(&source.text, key).map(|line| (source, Some(line))));
+        let (source, line) = located.unwrap_or((top, None));
+        SimError::JobFile { path: source.path.display().to_string(), line, message: message.to_string() }
+    }
+
+    /// Report the result of checking the value of `key`, located at that key.
+    fn at<T>(&self, key: &str, result: Result<T, SimError>) -> Result<T, SimError> {
+        result.map_err(|e| self.error_at(key, e))
+    }
+
+    /// Locate a deserialization error by its key, or by the `name` it quotes.
+    fn config_error(&self, e: ConfigError) -> SimError {
+        let key = match &e {
+            ConfigError::Type { key: Some(key), .. } | ConfigError::NotFound(key) => Some(key.clone()),
+            e => e.to_string().split('`').nth(1).map(str::to_string),
+        };
+        self.error_at(key.as_deref().unwrap_or(""), e)
+    }
+
+    /// Fail at `second` if it is set together with the alternative `first`.
+    fn either(&self, first: &str, second: &str, both: bool) -> Result<(), SimError> {
+        if both {
+            return Err(self.error_at(second, format!("Give {} or {}, not both", first, second)));
+        }
+        Ok(())
+    }
+
+    /// Directory of the file that sets `key`, for resolving relative paths.
+    /// A job without files resolves them against the working directory.
+    fn dir_of(&self, key: &str) -> &Path {
+        self.sources
+            .iter()
+            .rev()
+            .find(|source| line_of(&source.text, key).is_some())
+            .or(self.sources.last())
+            .and_then(|source| source.path.parent())
+            .unwrap_or(Path::new(""))
+    }
+
+    /// Check the schema version and build the parameters section by section.
+    fn validate(&self, spec: JobSpec) -> Result<JobFile, SimError> {
+        match spec.version {
+            Some(JOB_FILE_VERSION) => {}
+            Some(version) => {
+                return Err(self.error_at("version", format!(
+                    "Unsupported job file version {}; this build reads version {}",
+                    version, JOB_FILE_VERSION
+                )))
+            }
+            None => {
+                return Err(self.error_at("", format!("Job file must declare version = {}", JOB_FILE_VERSION)))
+            }
+        }
+        let (beam, scan, sample, detector, output) = (spec.beam, spec.scan, spec.sample, spec.detector, spec.output);
+
+        // The constructor checks the beam and width; check each value alone to locate it
+        let defaults = SimulationParameters::new(20.0, 1.0, DEFAULT_WIDTH, 10.0)?;
+        let energy_kev = beam.energy_kev.unwrap_or(defaults.energy_kev);
+        let current_na = beam.current_na.unwrap_or(defaults.current_na);
+        let width = scan.width.unwrap_or(defaults.resolution);
+        let distance_mm = beam.distance_mm.unwrap_or(defaults.distance_mm);
+        self.at("beam.energy_kev", SimulationParameters::new(energy_kev, 1.0, 1, 1.0))?;
+        self.at("beam.current_na", SimulationParameters::new(20.0, current_na, 1, 1.0))?;
+        self.at("scan.width", SimulationParameters::new(20.0, 1.0, width, 1.0))?;
+        self.at("beam.distance_mm", SimulationParameters::new(20.0, 1.0, 1, distance_mm))?;
+        let mut params = SimulationParameters::new(energy_kev, current_na, width, distance_mm)?;
+
+        // Scan: the field comes before anything sized to it
+        if let Some(height) = scan.height {
+            params = self.at("scan.height", params.with_image_size(width, height))?;
+        }
+        self.either(
+            "scan.field_of_view_um",
+            "scan.magnification",
+            scan.field_of_view_um.is_some() && scan.magnification.is_some(),
+        )?;
+        if let Some(fov) = scan.field_of_view_um {
+            params = self.at("scan.field_of_view_um", params.with_field_of_view(fov))?;
+        }
+        if let Some(magnification) = scan.magnification {
+            params = self.at("scan.magnification", params.with_magnification(magnification))?;
+        }
+        if let Some([x, y]) = scan.offset_um {
+            params = params.with_scan_offset(x, y);
+        }
+        if let Some(rotation) = scan.rotation_deg {
+            params = params.with_scan_rotation(rotation);
+        }
+        if let Some(dwell) = scan.dwell_us {
+            params = self.at("scan.dwell_us", params.with_dwell_time(dwell))?;
+        }
+        self.either(
+            "scan.electrons_per_pixel",
+            "scan.dose_e_per_nm2",
+            scan.electrons_per_pixel.is_some() && scan.dose_e_per_nm2.is_some(),
+        )?;
+        if let Some(count) = scan.electrons_per_pixel {
+            params = self.at("scan.electrons_per_pixel", params.with_electrons_per_pixel(count))?;
+        }
+        if let Some(dose) = scan.dose_e_per_nm2 {
+            params = self.at("scan.dose_e_per_nm2", params.with_dose(dose))?;
+        }
+
+        // Probe
+        if let Some(spot) = beam.spot_size_nm {
+            params = self.at("beam.spot_size_nm", params.with_spot_size(spot))?;
+        }
+        if let Some(convergence) = beam.convergence_mrad {
+            params = self.at("beam.convergence_mrad", params.with_convergence(convergence))?;
+        }
+        if let Some(aperture) = beam.aperture_um {
+            params = self.at("beam.aperture_um", params.with_aperture(aperture))?;
+        }
+        if let Some(defocus) = beam.defocus_um {
+            params = params.with_defocus(defocus);
+        }
+        if let Some(column) = beam.column {
+            params = self.at("beam.column", params.with_column(column))?;
+        }
+        if let Some(seed) = spec.seed {
+            params = params.with_seed(seed);
+        }
+
+        // Sample
+        self.either(
+            "sample.material",
+            "sample.custom_material",
+            sample.material.is_some() && sample.custom_material.is_some(),
+        )?;
+        if let Some(name) = sample.material {
+            let material = get_preset_material(&name).ok_or_else(|| {
+                self.error_at("sample.material", format!("Unknown material {}; use a preset name or element", name))
+            })?;
+            params = params.with_material(material);
+        }
+        if let Some(custom) = sample.custom_material {
+            params = params.with_material(self.at("sample.custom_material", custom.try_into_material())?);
+        }
+        self.either("sample.height_map", "sample.tilt_deg", sample.height_map.is_some() && sample.tilt_deg.is_some())?;
+        if let Some(map) = sample.height_map {
+            params = params.with_height_map(self.at("sample.height_map", self.height_map(map))?);
+        }
+        if let Some(tilt) = sample.tilt_deg {
+            params = self.at("sample.tilt_deg", params.with_sample_tilt(tilt))?;
+        }
+        self.either("sample.geometry", "sample.layers", sample.geometry.is_some() && sample.layers.is_some())?;
+        if let Some(geometry) = sample.geometry {
+            params = self.at("sample.geometry", params.with_geometry(geometry))?;
+        }
+        if let Some(layers) = sample.layers {
+            params = self.at("sample.layers", params.with_layer_stack(layers))?;
+        }
+
+        // Detector
+        if let Some(preset) = detector.preset {
+            let config = match preset.as_str() {
+                "everhart-thornley" => DetectorConfig::everhart_thornley(),
+                "in-lens" => DetectorConfig::in_lens(),
+                "annular-bse" => DetectorConfig::annular_bse(),
+                other => {
+                    return Err(self.error_at("detector.preset", format!(
+                        "Unknown detector preset {}; expected everhart-thornley, in-lens or annular-bse",
+                        other
+                    )))
+                }
+            };
+            params = params.with_detector(config)?;
+        }
+        if detector.se_weight.is_some() && detector.signal.as_deref() != Some("mixed") {
+            return Err(self.error_at("detector.se_weight", "detector.se_weight needs signal = \"mixed\""));
+        }
+        if let Some(signal) = detector.signal {
+            params = params.with_signal(match signal.as_str() {
+                "se" => DetectorSignal::Se,
+                "bse" => DetectorSignal::Bse,
+                "detector" => DetectorSignal::Detector,
+                "transmitted" => DetectorSignal::Transmitted,
+                "mixed" => {
+                    let se_weight = detector.se_weight.unwrap_or(0.5);
+                    if !(0.0..=1.0).contains(&se_weight) {
+                        let message = format!("se_weight ({}) must be in [0, 1]", se_weight);
+                        return Err(self.error_at("detector.se_weight", message));
+                    }
+                    DetectorSignal::Mixed { se_weight }
+                }
+                other => {
+                    return Err(self.error_at("detector.signal", format!(
+                        "Unknown signal {}; expected se, bse, detector, transmitted or mixed",
+                        other
+                    )))
+                }
+            });
+        }
+
+        // Output
+        if let Some(count) = output.trajectories {
+            params = params.with_trajectories(count);
+        }
+        let output = JobOutput {
+            image: output.image.map(PathBuf::from),
+            trajectories_csv: output.trajectories_csv.map(PathBuf::from),
+        };
+        Ok(JobFile { params, output })
+    }
+
+    fn height_map(&self, spec: HeightMapSpec) -> Result<HeightMap, SimError> {
+        let path = self.dir_of("sample.height_map.path").join(&spec.path);
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("png") => {
+                let range = spec.height_range_nm.ok_or_else(|| {
+                    SimError::InvalidParameter("A PNG height map needs height_range_nm".into())
+                })?;
+                HeightMap::from_png16(&path, spec.pixel_size_nm, range)
+            }
+            Some("csv") | Some("txt") => HeightMap::from_csv(&path, spec.pixel_size_nm),
+            Some("raw") | Some("f32") => match (spec.width, spec.height) {
+                (Some(width), Some(height)) => HeightMap::from_raw_f32(&path, width, height, spec.pixel_size_nm),
+                _ => Err(SimError::InvalidParameter("A raw height map needs width and height".into())),
+            },
+            _ => Err(SimError::InvalidParameter(format!(
+                "Height map {} must be a .png, .csv, .txt, .raw or .f32 file",
+                spec.path
+            ))),
+        }
+    }
+}
+
+/// Version 1 of the job file schema.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobSpec {
+    version: Option<u32>,
+    /// Consumed while reading the files.
+    #[serde(default, rename = "include")]
+    _include: Vec<String>,
+    seed: Option<u64>,
+    #[serde(default)]
+    beam: BeamSpec,
+    #[serde(default)]
+    scan: ScanSpec,
+    #[serde(default)]
+    sample: SampleSpec,
+    #[serde(default)]
+    detector: DetectorSpec,
+    #[serde(default)]
+    output: OutputSpec,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BeamSpec {
+    energy_kev: Option<f64>,
+    current_na: Option<f64>,
+    distance_mm: Option<f64>,
+    spot_size_nm: Option<f64>,
+    convergence_mrad: Option<f64>,
+    aperture_um: Option<f64>,
+    defocus_um: Option<f64>,
+    column: Option<ColumnConfig>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanSpec {
+    width: Option<i32>,
+    height: Option<i32>,
+    field_of_view_um: Option<f64>,
+    magnification: Option<f64>,
+    offset_um: Option<[f64; 2]>,
+    rotation_deg: Option<f64>,
+    dwell_us: Option<f64>,
+    electrons_per_pixel: Option<u32>,
+    dose_e_per_nm2: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SampleSpec {
+    /// Preset name or element symbol.
+    material: Option<String>,
+    custom_material: Option<CustomMaterialSpec>,
+    tilt_deg: Option<f64>,
+    height_map: Option<HeightMapSpec>,
+    /// Regions replacing the bulk sample.
+    geometry: Option<SampleGeometry>,
+    /// Films replacing the bulk sample.
+    layers: Option<LayerStack>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HeightMapSpec {
+    /// 16-bit PNG, text grid or raw little-endian f32 grid.
+    path: String,
+    pixel_size_nm: f64,
+    /// Height of PNG value 65535.
+    height_range_nm: Option<f64>,
+    /// Grid size of a raw file.
+    width: Option<usize>,
+    height: Option<usize>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetectorSpec {
+    /// everhart-thornley, in-lens or annular-bse.
+    preset: Option<String>,
+    /// se, bse, detector, transmitted or mixed.
+    signal: Option<String>,
+    /// SE share of the mixed signal, 0.5 by default.
+    se_weight: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputSpec {
+    image: Option<String>,
+    /// Electron paths to record.
+    trajectories: Option<usize>,
+    trajectories_csv: Option<String>,
+}
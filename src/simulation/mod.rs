@@ -1,24 +1,50 @@
 //! This module manages simulation jobs, parameter sweeps, and result collection.
+pub mod column;
 pub mod parameters;
 pub mod results;
+pub mod job;
+pub mod job_file;
+pub mod sweep;
 
-use crate::ffi::wrapper::{init_simulation, run_simulation, get_scatter_data};
+pub use job::{JobHandle, Progress};
+pub use job_file::JobFile;
+pub use sweep::{ParameterSweep, SweepAxis, SweepHandle, SweepResults};
+
+use crate::backend::{CancelFlag, DefaultBackend, LineProfile, ProgressCallback, SimulationBackend};
+use crate::error::SimError;
 use parameters::SimulationParameters;
 use results::SimulationResult;
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Instant;
 
-/// Manages a queue of simulation jobs and executes them in parallel.
-pub struct SimulationManager {
+/// Manages a queue of simulation jobs and executes them in parallel on
+/// engine `B`, by default the one selected by cargo features.
+pub struct SimulationManager<B: SimulationBackend = DefaultBackend> {
     /// Shared list of parameters for jobs
     jobs: Arc<Mutex<Vec<SimulationParameters>>>,
+    /// Cancel flags of the batches started so far; each batch owns its flag
+    batches: Mutex<Vec<Weak<AtomicBool>>>,
+    backend: PhantomData<fn() -> B>,
 }
 
 impl SimulationManager {
     /// Creates a new SimulationManager with an empty job queue.
     pub fn new() -> Self {
+        Self::with_backend()
+    }
+}
+
+impl<B: SimulationBackend> SimulationManager<B> {
+    /// Creates an empty manager whose jobs run on engine `B`.
+    pub fn with_backend() -> Self {
         Self {
             jobs: Arc::new(Mutex::new(Vec::new())),
+            batches: Mutex::new(Vec::new()),
+            backend: PhantomData,
         }
     }
 
@@ -28,32 +54,72 @@ impl SimulationManager {
         jobs.push(params);
     }
 
-    /// Run all enqueued simulation jobs in parallel and return the results.
-    pub fn run_all(&self) -> Vec<SimulationResult> {
-        let jobs = {
-            let mut locked = self.jobs.lock().unwrap();
-            std::mem::take(&mut *locked)
-        };
+    /// Run all enqueued simulation jobs in parallel and return their results
+    /// in queue order. A job with invalid settings fails on its own without
+    /// affecting the others.
+    pub fn run_all(&self) -> Vec<Result<SimulationResult, SimError>> {
+        let jobs = self.take_jobs();
+        run_jobs::<B>(jobs, &self.batch_flag(), &Arc::new(AtomicU64::new(0)))
+    }
+
+    /// Start all enqueued jobs on a background thread and return a handle to
+    /// follow their progress, cancel them or wait for the results.
+    pub fn start(&self) -> JobHandle
+    where
+        B: 'static,
+    {
+        self.spawn(self.take_jobs())
+    }
+
+    /// Run every job of `sweep` in parallel, leaving the queue untouched, and
+    /// return the results tagged with their coordinates.
+    pub fn run_sweep(&self, sweep: &ParameterSweep) -> Result<SweepResults, SimError> {
+        let (jobs, coordinates) = sweep_jobs(sweep)?;
+        let results = run_jobs::<B>(jobs, &self.batch_flag(), &Arc::new(AtomicU64::new(0)));
+        Ok(SweepResults::new(sweep.axis_names(), coordinates, results))
+    }
 
-        // Execute simulations in parallel using Rayon
-        jobs.into_par_iter()
+    /// Start every job of `sweep` on a background thread, leaving the queue
+    /// untouched. Fails before starting if any point of the grid is invalid.
+    pub fn start_sweep(&self, sweep: &ParameterSweep) -> Result<SweepHandle, SimError>
+    where
+        B: 'static,
+    {
+        let (jobs, coordinates) = sweep_jobs(sweep)?;
+        Ok(SweepHandle { job: self.spawn(jobs), axes: sweep.axis_names(), coordinates })
+    }
+
+    fn spawn(&self, jobs: Vec<SimulationParameters>) -> JobHandle
+    where
+        B: 'static,
+    {
+        let total = jobs
+            .iter()
             .map(|params| {
-                // Initialize and run the Fortran simulation
-                init_simulation(
-                    params.energy_kev,
-                    params.current_na,
-                    params.resolution,
-                    params.distance_mm,
-                );
-                run_simulation();
-
-                // Retrieve raw scatter data
-                let scatter = get_scatter_data();
-
-                // Process into a SimulationResult
-                SimulationResult::from_scatter(scatter, &params)
+                let (width, height) = params.image_size();
+                width.max(0) as u64 * height.max(0) as u64
             })
-            .collect()
+            .sum();
+        let completed = Arc::new(AtomicU64::new(0));
+        let cancel = self.batch_flag();
+        let (flag, counter) = (cancel.clone(), completed.clone());
+        let thread = thread::spawn(move || run_jobs::<B>(jobs, &flag, &counter));
+        JobHandle { thread, completed, total, cancel, started: Instant::now() }
+    }
+
+    /// A fresh cancel flag for a new batch, reached by `cancel` while the
+    /// batch is running.
+    fn batch_flag(&self) -> CancelFlag {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut batches = self.batches.lock().unwrap();
+        batches.retain(|batch| batch.strong_count() > 0);
+        batches.push(Arc::downgrade(&flag));
+        flag
+    }
+
+    fn take_jobs(&self) -> Vec<SimulationParameters> {
+        let mut locked = self.jobs.lock().unwrap();
+        std::mem::take(&mut *locked)
     }
 
     /// Clears any pending jobs without running them.
@@ -61,4 +127,114 @@ impl SimulationManager {
         let mut jobs = self.jobs.lock().unwrap();
         jobs.clear();
     }
+
+    /// Asks the jobs of every batch in progress to stop at their next scan
+    /// line, on engines that support cancellation. They return partial images
+    /// marked as cancelled. To stop a single batch use `JobHandle::cancel`.
+    pub fn cancel(&self) {
+        for batch in self.batches.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            batch.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Parameters and coordinates of every point of `sweep`.
+fn sweep_jobs(sweep: &ParameterSweep) -> Result<(Vec<SimulationParameters>, Vec<Vec<String>>), SimError> {
+    Ok(sweep.points()?.into_iter().map(|point| (point.params, point.coordinates)).unzip())
+}
+
+/// Run `jobs` in parallel on engine `B`, adding the pixels each finishes to
+/// `completed`.
+fn run_jobs<B: SimulationBackend>(
+    jobs: Vec<SimulationParameters>,
+    cancel: &CancelFlag,
+    completed: &Arc<AtomicU64>,
+) -> Vec<Result<SimulationResult, SimError>> {
+    // Execute simulations in parallel using Rayon. Every job owns its own
+    // engine context, so concurrent runs never touch each other's buffers.
+    jobs.into_par_iter()
+        .map(|mut params| {
+            // Initialize and run the simulation
+            let mut sim: B = configured_simulation(&params)?;
+            let sample_ignored = !sim.capabilities().geometry
+                && (params.height_map.is_some() || params.sample_geometry().is_some());
+            sim.set_trajectory_recording(params.trajectory_count)?;
+            let finished = Arc::new(AtomicBool::new(false));
+            sim.set_cancel_flag(cancel.clone());
+            sim.set_progress_callback(job_progress(completed.clone(), finished.clone()));
+            sim.run()?;
+
+            // Retrieve raw scatter data
+            let scatter = sim.scatter_data()?;
+            let channels = sim.image_channels()?;
+            params.electrons_per_pixel = Some(channels.electrons_per_pixel as u32);
+
+            // Report the probe the column model predicted, not the nominal one
+            if params.column.is_some() {
+                let probe = sim.probe()?;
+                params.spot_size_nm = probe.spot_size_nm;
+                params.current_na = probe.current_na;
+            }
+
+            // Process into a SimulationResult
+            let mut result = SimulationResult::from_scatter(scatter, channels, &params)?;
+            result.trajectories = sim.trajectories()?;
+            result.sample_ignored = sample_ignored;
+            // A cancelled job stops before reporting its last scan line
+            result.cancelled = cancel.load(Ordering::Relaxed) && !finished.load(Ordering::Relaxed);
+            Ok(result)
+        })
+        .collect()
+}
+
+/// Progress callback of one job, adding what it finishes to the batch total
+/// and setting `finished` once the job has reported every pixel.
+fn job_progress(completed: Arc<AtomicU64>, finished: Arc<AtomicBool>) -> ProgressCallback {
+    let done = AtomicU64::new(0);
+    Arc::new(move |pixels, total| {
+        let previous = done.swap(pixels, Ordering::Relaxed);
+        completed.fetch_add(pixels.saturating_sub(previous), Ordering::Relaxed);
+        if pixels >= total {
+            finished.store(true, Ordering::Relaxed);
+        }
+    })
+}
+
+/// Run a line scan from `start` to `end` (x, y in nm) with `n_points` beam
+/// positions, using the beam and sample described by `params`.
+pub fn run_line_scan(
+    params: &SimulationParameters,
+    start: (f64, f64),
+    end: (f64, f64),
+    n_points: i32,
+) -> Result<LineProfile, SimError> {
+    let mut sim: DefaultBackend = configured_simulation(params)?;
+    sim.run_line_scan(start, end, n_points)
+}
+
+/// Create an engine set up with the beam, sample and detector of `params`.
+fn configured_simulation<B: SimulationBackend>(params: &SimulationParameters) -> Result<B, SimError> {
+    let mut sim = B::new(
+        params.energy_kev,
+        params.current_na,
+        &params.scan_raster(),
+        params.distance_mm,
+    )?;
+    sim.set_probe(
+        params.spot_size_nm,
+        params.convergence_semi_angle_mrad() * 1.0e-3,
+        params.aperture_diameter_um,
+        params.defocus_um * 1000.0,
+        params.dwell_time_us * 1.0e-6,
+    )?;
+    if let Some(column) = &params.column {
+        sim.set_column(column)?;
+    }
+    sim.set_electrons_per_pixel(params.electrons_per_pixel)?;
+    sim.set_seed(params.seed)?;
+    sim.set_material(&params.material)?;
+    sim.set_height_map(params.height_map.as_ref())?;
+    sim.set_geometry(params.sample_geometry().as_ref())?;
+    sim.set_detector(&params.detector)?;
+    Ok(sim)
 }
@@ -2,12 +2,110 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::backend::ScanRaster;
+use crate::error::SimError;
+use crate::imaging::detector::DetectorConfig;
+use crate::imaging::formation::DetectorSignal;
+use crate::materials::{get_preset_material, Material};
+use crate::sample::{HeightMap, LayerStack, SampleGeometry};
+use crate::simulation::column::ColumnConfig;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SimulationParameters {
     pub energy_kev: f64,
     pub current_na: f64,
+    /// Image width in pixels, and its height unless `height_px` is set.
     pub resolution: i32,
     pub distance_mm: f64,
+    /// Image height in pixels for rectangular scans.
+    #[serde(default)]
+    pub height_px: Option<i32>,
+    /// Horizontal field width in µm.
+    #[serde(default = "default_field_of_view")]
+    pub field_of_view_um: f64,
+    /// Centre of the scanned field on the sample (x, y) in µm.
+    #[serde(default)]
+    pub scan_offset_um: [f64; 2],
+    /// Scan rotation about the beam axis in degrees, counter-clockwise.
+    #[serde(default)]
+    pub scan_rotation_deg: f64,
+    /// Probe diameter (FWHM) in the focal plane, in nm.
+    #[serde(default = "default_spot_size")]
+    pub spot_size_nm: f64,
+    /// Beam convergence semi-angle in mrad; derived from the final aperture and
+    /// working distance when absent.
+    #[serde(default)]
+    pub convergence_mrad: Option<f64>,
+    /// Final aperture diameter in µm.
+    #[serde(default = "default_aperture")]
+    pub aperture_diameter_um: f64,
+    /// Depth of the focal plane below the nominal surface in µm (0 = in focus).
+    #[serde(default)]
+    pub defocus_um: f64,
+    /// Pixel dwell time in µs.
+    #[serde(default = "default_dwell_time")]
+    pub dwell_time_us: f64,
+    /// Primary electrons simulated per pixel; when absent, the number the beam
+    /// current delivers in one dwell.
+    #[serde(default)]
+    pub electrons_per_pixel: Option<u32>,
+    /// Seed of the engine's random streams; equal seeds and parameters give
+    /// bit-identical images.
+    #[serde(default)]
+    pub seed: u64,
+    /// Electron column predicting the spot size (or current); when absent the
+    /// probe is exactly `spot_size_nm` at `current_na`.
+    #[serde(default)]
+    pub column: Option<ColumnConfig>,
+    /// Sample material seen by the Monte Carlo engine.
+    #[serde(default = "default_material")]
+    pub material: Material,
+    /// Number of electrons whose full trajectories are recorded (0 = off).
+    #[serde(default)]
+    pub trajectory_count: usize,
+    /// Detector signal used for the rendered image.
+    #[serde(default)]
+    pub signal: DetectorSignal,
+    /// Detector forming the `DetectorSignal::Detector` image.
+    #[serde(default)]
+    pub detector: DetectorConfig,
+    /// Surface topography; a flat sample if absent.
+    #[serde(default)]
+    pub height_map: Option<HeightMap>,
+    /// Volumetric sample; when present it replaces the bulk `material` and
+    /// `height_map`.
+    #[serde(default)]
+    pub geometry: Option<SampleGeometry>,
+    /// Thin-film or multilayer sample; ignored when `geometry` is set.
+    #[serde(default)]
+    pub layer_stack: Option<LayerStack>,
+}
+
+/// Electrons per second in one nA of beam current.
+pub const ELECTRONS_PER_NA_S: f64 = 6.241_509_074e9;
+
+/// Width of the display that magnifications refer to: a 4×5 in print, in mm.
+pub const REFERENCE_DISPLAY_WIDTH_MM: f64 = 127.0;
+
+fn default_field_of_view() -> f64 {
+    10.0
+}
+
+fn default_spot_size() -> f64 {
+    5.0
+}
+
+fn default_aperture() -> f64 {
+    30.0
+}
+
+fn default_dwell_time() -> f64 {
+    1.0
+}
+
+/// Material used when none is specified: a silicon substrate.
+fn default_material() -> Material {
+    get_preset_material("Silicon").expect("Silicon preset is always available")
 }
 
 impl SimulationParameters {
@@ -16,18 +114,21 @@ impl SimulationParameters {
         current_na: f64,
         resolution: i32,
         distance_mm: f64,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, SimError> {
         if !(1.0..=100.0).contains(&energy_kev) {
-            return Err(format!("energy_kev ({} keV) out of range [1.0, 100.0]", energy_kev));
+            return Err(SimError::InvalidParameter(format!(
+                "energy_kev ({} keV) out of range [1.0, 100.0]",
+                energy_kev
+            )));
         }
         if current_na <= 0.0 {
-            return Err(format!("current_na ({} nA) must be > 0", current_na));
+            return Err(SimError::InvalidParameter(format!("current_na ({} nA) must be > 0", current_na)));
         }
         if resolution <= 0 {
-            return Err(format!("resolution ({}) must be > 0", resolution));
+            return Err(SimError::InvalidParameter(format!("resolution ({}) must be > 0", resolution)));
         }
         if distance_mm <= 0.0 {
-            return Err(format!("distance_mm ({} mm) must be > 0", distance_mm));
+            return Err(SimError::InvalidParameter(format!("distance_mm ({} mm) must be > 0", distance_mm)));
         }
 
         Ok(Self {
@@ -35,15 +136,269 @@ impl SimulationParameters {
             current_na,
             resolution,
             distance_mm,
+            height_px: None,
+            field_of_view_um: default_field_of_view(),
+            scan_offset_um: [0.0, 0.0],
+            scan_rotation_deg: 0.0,
+            spot_size_nm: default_spot_size(),
+            convergence_mrad: None,
+            aperture_diameter_um: default_aperture(),
+            defocus_um: 0.0,
+            dwell_time_us: default_dwell_time(),
+            electrons_per_pixel: None,
+            seed: 0,
+            column: None,
+            material: default_material(),
+            trajectory_count: 0,
+            signal: DetectorSignal::default(),
+            detector: DetectorConfig::default(),
+            height_map: None,
+            geometry: None,
+            layer_stack: None,
         })
     }
 
+    /// Scan a rectangular image of `width` × `height` pixels.
+    pub fn with_image_size(mut self, width: i32, height: i32) -> Result<Self, SimError> {
+        if width <= 0 || height <= 0 {
+            return Err(SimError::InvalidParameter(format!("image size ({}×{}) must be > 0", width, height)));
+        }
+        self.resolution = width;
+        self.height_px = Some(height);
+        Ok(self)
+    }
+
+    /// Image width and height in pixels.
+    pub fn image_size(&self) -> (i32, i32) {
+        (self.resolution, self.height_px.unwrap_or(self.resolution))
+    }
+
+    /// Set the horizontal field width in µm.
+    pub fn with_field_of_view(mut self, field_of_view_um: f64) -> Result<Self, SimError> {
+        if field_of_view_um <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("field_of_view_um ({} µm) must be > 0", field_of_view_um)));
+        }
+        self.field_of_view_um = field_of_view_um;
+        Ok(self)
+    }
+
+    /// Set the field of view from an instrument magnification, referred to a
+    /// display `REFERENCE_DISPLAY_WIDTH_MM` wide.
+    pub fn with_magnification(self, magnification: f64) -> Result<Self, SimError> {
+        if magnification <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("magnification ({}) must be > 0", magnification)));
+        }
+        self.with_field_of_view(REFERENCE_DISPLAY_WIDTH_MM * 1000.0 / magnification)
+    }
+
+    /// Magnification of the current field of view on the reference display.
+    pub fn magnification(&self) -> f64 {
+        REFERENCE_DISPLAY_WIDTH_MM * 1000.0 / self.field_of_view_um
+    }
+
+    /// Centre the scanned field at (x, y) µm on the sample.
+    pub fn with_scan_offset(mut self, x_um: f64, y_um: f64) -> Self {
+        self.scan_offset_um = [x_um, y_um];
+        self
+    }
+
+    /// Rotate the scan raster about the beam axis.
+    pub fn with_scan_rotation(mut self, degrees: f64) -> Self {
+        self.scan_rotation_deg = degrees;
+        self
+    }
+
+    /// Set the probe diameter (FWHM) in nm.
+    pub fn with_spot_size(mut self, spot_size_nm: f64) -> Result<Self, SimError> {
+        if spot_size_nm < 0.0 {
+            return Err(SimError::InvalidParameter(format!("spot_size_nm ({} nm) must be >= 0", spot_size_nm)));
+        }
+        self.spot_size_nm = spot_size_nm;
+        Ok(self)
+    }
+
+    /// Fix the convergence semi-angle instead of deriving it from the aperture.
+    pub fn with_convergence(mut self, convergence_mrad: f64) -> Result<Self, SimError> {
+        if !(convergence_mrad > 0.0 && convergence_mrad < 1000.0) {
+            return Err(SimError::InvalidParameter(format!(
+                "convergence_mrad ({} mrad) out of range (0, 1000)",
+                convergence_mrad
+            )));
+        }
+        self.convergence_mrad = Some(convergence_mrad);
+        Ok(self)
+    }
+
+    /// Set the final aperture diameter in µm.
+    pub fn with_aperture(mut self, aperture_diameter_um: f64) -> Result<Self, SimError> {
+        if aperture_diameter_um <= 0.0 {
+            return Err(SimError::InvalidParameter(format!(
+                "aperture_diameter_um ({} µm) must be > 0",
+                aperture_diameter_um
+            )));
+        }
+        self.aperture_diameter_um = aperture_diameter_um;
+        Ok(self)
+    }
+
+    /// Move the focal plane `defocus_um` below the nominal surface (negative = above).
+    pub fn with_defocus(mut self, defocus_um: f64) -> Self {
+        self.defocus_um = defocus_um;
+        self
+    }
+
+    /// Set the pixel dwell time in µs.
+    pub fn with_dwell_time(mut self, dwell_time_us: f64) -> Result<Self, SimError> {
+        if dwell_time_us <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("dwell_time_us ({} µs) must be > 0", dwell_time_us)));
+        }
+        self.dwell_time_us = dwell_time_us;
+        Ok(self)
+    }
+
+    /// Select the random sequence of the run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Simulate a fixed number of primary electrons per pixel, independent of
+    /// the beam current and dwell time.
+    pub fn with_electrons_per_pixel(mut self, count: u32) -> Result<Self, SimError> {
+        if count == 0 {
+            return Err(SimError::InvalidParameter("electrons_per_pixel must be > 0".into()));
+        }
+        self.electrons_per_pixel = Some(count);
+        Ok(self)
+    }
+
+    /// Simulate the primaries per pixel that give a dose in electrons per nm²
+    /// at the current field of view and image size.
+    pub fn with_dose(self, electrons_per_nm2: f64) -> Result<Self, SimError> {
+        if electrons_per_nm2 <= 0.0 {
+            return Err(SimError::InvalidParameter(format!("dose ({} e/nm²) must be > 0", electrons_per_nm2)));
+        }
+        let pixel_nm = self.scan_raster().pixel_size_nm();
+        let count = (electrons_per_nm2 * pixel_nm * pixel_nm).round().clamp(1.0, u32::MAX as f64);
+        self.with_electrons_per_pixel(count as u32)
+    }
+
+    /// Primary electrons per pixel: the set number, or the charge the beam
+    /// current delivers in one dwell.
+    pub fn primaries_per_pixel(&self) -> u64 {
+        self.electrons_per_pixel.map_or_else(
+            || (self.current_na * self.dwell_time_us * 1.0e-6 * ELECTRONS_PER_NA_S).round().max(1.0) as u64,
+            u64::from,
+        )
+    }
+
+    /// Areal dose in electrons per nm².
+    pub fn dose_e_per_nm2(&self) -> f64 {
+        let pixel_nm = self.scan_raster().pixel_size_nm();
+        self.primaries_per_pixel() as f64 / (pixel_nm * pixel_nm)
+    }
+
+    /// Convergence semi-angle in mrad: the set value, or the aperture radius seen
+    /// from the focal plane at the working distance.
+    pub fn convergence_semi_angle_mrad(&self) -> f64 {
+        self.convergence_mrad
+            .unwrap_or(0.5 * self.aperture_diameter_um / self.distance_mm)
+    }
+
+    /// Depth over which the geometric blur stays within the spot size, in µm.
+    pub fn depth_of_field_um(&self) -> f64 {
+        self.spot_size_nm / self.convergence_semi_angle_mrad()
+    }
+
+    /// Raster handed to the engine.
+    pub fn scan_raster(&self) -> ScanRaster {
+        let (width, height) = self.image_size();
+        ScanRaster {
+            width,
+            height,
+            field_of_view_nm: self.field_of_view_um * 1000.0,
+            offset_nm: (self.scan_offset_um[0] * 1000.0, self.scan_offset_um[1] * 1000.0),
+            rotation_deg: self.scan_rotation_deg,
+        }
+    }
+
+    /// Replace the sample material.
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Record the trajectories of the first `count` electrons of the run.
+    pub fn with_trajectories(mut self, count: usize) -> Self {
+        self.trajectory_count = count;
+        self
+    }
+
+    /// Select the SE, BSE or mixed detector signal for the rendered image.
+    pub fn with_signal(mut self, signal: DetectorSignal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Replace the detector after checking its geometry and response.
+    pub fn with_detector(mut self, detector: DetectorConfig) -> Result<Self, SimError> {
+        detector.validate()?;
+        self.detector = detector;
+        Ok(self)
+    }
+
+    /// Image a sample with the given surface topography.
+    pub fn with_height_map(mut self, map: HeightMap) -> Self {
+        self.height_map = Some(map);
+        self
+    }
+
+    /// Tilt a flat bulk sample by `degrees` about the y axis, replacing any
+    /// height map. The tilted plane extends a field diagonal beyond the scan.
+    pub fn with_sample_tilt(mut self, degrees: f64) -> Result<Self, SimError> {
+        let raster = self.scan_raster();
+        let field_nm = (raster.field_of_view_nm, raster.pixel_size_nm() * raster.height as f64);
+        let diagonal_nm = field_nm.0.hypot(field_nm.1);
+        let offset_nm = raster.offset_nm.0.abs().max(raster.offset_nm.1.abs());
+        self.height_map = Some(HeightMap::tilted_plane(degrees, 2.0 * (offset_nm + diagonal_nm))?);
+        Ok(self)
+    }
+
+    /// Predict the probe from an electron column after checking it.
+    pub fn with_column(mut self, column: ColumnConfig) -> Result<Self, SimError> {
+        column.validate()?;
+        self.column = Some(column);
+        Ok(self)
+    }
+
+    /// Image a volumetric sample after checking its regions.
+    pub fn with_geometry(mut self, geometry: SampleGeometry) -> Result<Self, SimError> {
+        geometry.validate()?;
+        self.geometry = Some(geometry);
+        Ok(self)
+    }
+
+    /// Image a layered film sample after checking its layers.
+    pub fn with_layer_stack(mut self, stack: LayerStack) -> Result<Self, SimError> {
+        stack.validate()?;
+        self.layer_stack = Some(stack);
+        Ok(self)
+    }
+
+    /// Region geometry the engine tracks electrons through, if the sample is
+    /// not a bulk block: `geometry`, or else the layer stack's slabs.
+    pub fn sample_geometry(&self) -> Option<SampleGeometry> {
+        self.geometry
+            .clone()
+            .or_else(|| self.layer_stack.as_ref().map(LayerStack::to_geometry))
+    }
+
     pub fn from_degrees(
         energy_kev: f64,
         current_na: f64,
         resolution: i32,
         distance_mm: f64,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, SimError> {
         Self::new(energy_kev, current_na, resolution, distance_mm)
     }
 }
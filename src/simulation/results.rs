@@ -1,58 +1,110 @@
 //! Defines the result of a SEM simulation, including raw scatter data and derived image.
 
+use crate::error::SimError;
 use crate::simulation::parameters::SimulationParameters;
 use crate::imaging::formation;
 use crate::imaging::export;
-use crate::ffi::wrapper::ScatterData;
+use crate::backend::{ImageChannels, ScatterData, Trajectories};
+use crate::imaging::formation::DetectorSignal;
 
 /// A complete simulation result, tying parameters to output data and images.
 pub struct SimulationResult {
     pub params: SimulationParameters,
     pub scatter: ScatterData,
+    /// Raw SE, BSE and detector yields per primary electron.
+    pub channels: ImageChannels,
+    /// Image rendered from the detector signal selected in `params`.
     pub image_buffer: Vec<u8>,
+    /// Size of `image_buffer`, smaller than the scan in `channels` when a side
+    /// of the scan exceeds the display limit and the image was downscaled.
     pub width: usize,
     pub height: usize,
+    /// Recorded electron paths; empty unless `params.trajectory_count > 0`.
+    pub trajectories: Trajectories,
+    /// Whether the engine lacked the `geometry` capability and simulated a flat
+    /// bulk sample instead of the height map or geometry in `params`.
+    pub sample_ignored: bool,
+    /// Whether the job was cancelled before the scan finished, leaving the
+    /// remaining lines of the image empty.
+    pub cancelled: bool,
 }
 
 impl SimulationResult {
-    /// Converts raw scatter data into a SimulationResult, applying gamma and LUT.
-    pub fn from_scatter(scatter: ScatterData, params: &SimulationParameters) -> Self {
-        // Get the 2D image data from Fortran
-        let (image_data, width, height) = crate::ffi::wrapper::get_image_data();
-        
-        println!("Raw image data dimensions: {}×{}", width, height);
-        
-        // Apply image formation (normalize to [0,255], gamma=1.0 by default)
-        let image_buffer = formation::to_grayscale_bytes(
-            &image_data,
-            height,
-            width,
-            /* gamma */ 1.0,
-            /* lut */ None,
-        ).0;  // Only take the buffer, dimensions are already known
+    /// Converts raw scatter data and image channels into a SimulationResult,
+    /// rendering the detector signal chosen in `params`.
+    pub fn from_scatter(
+        scatter: ScatterData,
+        channels: ImageChannels,
+        params: &SimulationParameters,
+    ) -> Result<Self, SimError> {
+        log::debug!("Raw image data dimensions: {}×{}", channels.width, channels.height);
+        log::debug!(
+            "Simulated {} primaries per pixel ({} in total)",
+            channels.electrons_per_pixel,
+            channels.total_electrons()
+        );
+
+        let (image_buffer, width, height) = Self::render_channels(&channels, params.signal)?;
 
-        println!("Final image dimensions: {}×{}", width, height);
+        log::debug!("Final image dimensions: {}×{}", width, height);
         
-        SimulationResult {
+        Ok(SimulationResult {
             params: params.clone(),
             scatter,
+            channels,
             image_buffer,
             width,
             height,
-        }
+            trajectories: Trajectories::default(),
+            sample_ignored: false,
+            cancelled: false,
+        })
+    }
+
+    /// Render the image seen with a different detector signal, at the size of
+    /// `image_buffer`.
+    pub fn render(&self, signal: DetectorSignal) -> Result<Vec<u8>, SimError> {
+        Self::render_channels(&self.channels, signal).map(|(buffer, _, _)| buffer)
+    }
+
+    /// Rendered buffer and its width and height.
+    fn render_channels(
+        channels: &ImageChannels,
+        signal: DetectorSignal,
+    ) -> Result<(Vec<u8>, usize, usize), SimError> {
+        let data = formation::detector_signal(channels, signal);
+
+        // Apply image formation (normalize to [0,255], gamma=1.0 by default)
+        formation::to_grayscale_bytes(
+            &data,
+            channels.height,
+            channels.width,
+            /* gamma */ 1.0,
+            /* lut */ None,
+        )
     }
 
     /// Save the result image to a PNG file with embedded metadata.
-    pub fn save_png(&self, path: &str) -> Result<(), image::ImageError> {
-        println!("Saving PNG with dimensions: {}×{}", self.width, self.height);
+    pub fn save_png(&self, path: &str) -> Result<(), SimError> {
+        log::debug!("Saving PNG with dimensions: {}×{}", self.width, self.height);
         export::save_png_with_metadata(
             path,
             &self.image_buffer,
             self.width as u32,
             self.height as u32,
             &self.params,
-        )
-        .map_err(image::ImageError::IoError)
+        )?;
+        Ok(())
+    }
+
+    /// Save the recorded trajectories as CSV, one row per vertex.
+    pub fn save_trajectories_csv(&self, path: &str) -> Result<(), SimError> {
+        Ok(export::save_trajectories_csv(path, &self.trajectories)?)
+    }
+
+    /// Save the recorded trajectories as JSON polylines.
+    pub fn save_trajectories_json(&self, path: &str) -> Result<(), SimError> {
+        Ok(export::save_trajectories_json(path, &self.trajectories)?)
     }
     
 }
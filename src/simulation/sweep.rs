@@ -0,0 +1,303 @@
+//! Parameter sweeps: Cartesian grids of jobs tagged with their coordinates,
+//! and the scalar image metrics gathered from their results.
+
+use crate::error::SimError;
+use crate::imaging::export;
+use crate::imaging::formation::detector_signal;
+use crate::materials::Material;
+use crate::simulation::job::{JobHandle, Progress};
+use crate::simulation::parameters::SimulationParameters;
+use crate::simulation::results::SimulationResult;
+
+/// `count` evenly spaced values from `start` to `end` inclusive.
+pub fn linspace(start: f64, end: f64, count: usize) -> Vec<f64> {
+    match count {
+        0 => Vec::new(),
+        1 => vec![start],
+        _ => (0..count)
+            .map(|i| start + (end - start) * i as f64 / (count - 1) as f64)
+            .collect(),
+    }
+}
+
+/// One swept parameter and the values it takes.
+#[derive(Clone, Debug)]
+pub enum SweepAxis {
+    /// Beam energy in keV.
+    Energy(Vec<f64>),
+    /// Beam current in nA.
+    Current(Vec<f64>),
+    /// Image width in pixels; rectangular scans keep their aspect ratio.
+    Resolution(Vec<i32>),
+    /// Sample tilt in degrees, see `SimulationParameters::with_sample_tilt`.
+    Tilt(Vec<f64>),
+    Material(Vec<Material>),
+    /// Pixel dwell time in µs.
+    DwellTime(Vec<f64>),
+    /// Horizontal field width in µm.
+    FieldOfView(Vec<f64>),
+    Seed(Vec<u64>),
+}
+
+impl SweepAxis {
+    /// Column name of the axis in tables.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SweepAxis::Energy(_) => "energy_kev",
+            SweepAxis::Current(_) => "current_na",
+            SweepAxis::Resolution(_) => "resolution",
+            SweepAxis::Tilt(_) => "tilt_deg",
+            SweepAxis::Material(_) => "material",
+            SweepAxis::DwellTime(_) => "dwell_us",
+            SweepAxis::FieldOfView(_) => "fov_um",
+            SweepAxis::Seed(_) => "seed",
+        }
+    }
+
+    /// Number of values on the axis.
+    pub fn len(&self) -> usize {
+        match self {
+            SweepAxis::Energy(v)
+            | SweepAxis::Current(v)
+            | SweepAxis::Tilt(v)
+            | SweepAxis::DwellTime(v)
+            | SweepAxis::FieldOfView(v) => v.len(),
+            SweepAxis::Resolution(v) => v.len(),
+            SweepAxis::Material(v) => v.len(),
+            SweepAxis::Seed(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Label of value `i`.
+    fn label(&self, i: usize) -> String {
+        match self {
+            SweepAxis::Energy(v)
+            | SweepAxis::Current(v)
+            | SweepAxis::Tilt(v)
+            | SweepAxis::DwellTime(v)
+            | SweepAxis::FieldOfView(v) => v[i].to_string(),
+            SweepAxis::Resolution(v) => v[i].to_string(),
+            SweepAxis::Material(v) => v[i].name.clone(),
+            SweepAxis::Seed(v) => v[i].to_string(),
+        }
+    }
+
+    /// `params` with the axis set to value `i`, checked like the builders do.
+    fn apply(&self, i: usize, params: SimulationParameters) -> Result<SimulationParameters, SimError> {
+        // The constructor holds the range checks of the beam and image size
+        let check = |energy_kev, current_na, resolution| {
+            SimulationParameters::new(energy_kev, current_na, resolution, params.distance_mm)
+        };
+        match self {
+            SweepAxis::Energy(v) => {
+                let checked = check(v[i], params.current_na, params.resolution)?;
+                Ok(SimulationParameters { energy_kev: checked.energy_kev, ..params })
+            }
+            SweepAxis::Current(v) => {
+                let checked = check(params.energy_kev, v[i], params.resolution)?;
+                Ok(SimulationParameters { current_na: checked.current_na, ..params })
+            }
+            SweepAxis::Resolution(v) => match params.height_px {
+                Some(height) => {
+                    let scaled = (height as f64 * v[i] as f64 / params.resolution as f64).round().max(1.0);
+                    params.with_image_size(v[i], scaled as i32)
+                }
+                None => {
+                    let checked = check(params.energy_kev, params.current_na, v[i])?;
+                    Ok(SimulationParameters { resolution: checked.resolution, ..params })
+                }
+            },
+            SweepAxis::Tilt(v) => params.with_sample_tilt(v[i]),
+            SweepAxis::Material(v) => Ok(params.with_material(v[i].clone())),
+            SweepAxis::DwellTime(v) => params.with_dwell_time(v[i]),
+            SweepAxis::FieldOfView(v) => params.with_field_of_view(v[i]),
+            SweepAxis::Seed(v) => Ok(params.with_seed(v[i])),
+        }
+    }
+}
+
+/// A grid of jobs: every combination of the axis values applied to a base set
+/// of parameters, the first axis varying slowest.
+#[derive(Clone, Debug)]
+pub struct ParameterSweep {
+    base: SimulationParameters,
+    axes: Vec<SweepAxis>,
+}
+
+/// One job of a sweep.
+#[derive(Clone, Debug)]
+pub struct SweepPoint {
+    /// Value labels, one per axis.
+    pub coordinates: Vec<String>,
+    pub params: SimulationParameters,
+}
+
+impl ParameterSweep {
+    /// A sweep without axes, holding the single job `base`.
+    pub fn new(base: SimulationParameters) -> Self {
+        ParameterSweep { base, axes: Vec::new() }
+    }
+
+    /// Add an axis, multiplying the number of jobs by its length.
+    pub fn with_axis(mut self, axis: SweepAxis) -> Self {
+        self.axes.push(axis);
+        self
+    }
+
+    /// Column names of the axes, in the order they were added.
+    pub fn axis_names(&self) -> Vec<&'static str> {
+        self.axes.iter().map(SweepAxis::name).collect()
+    }
+
+    /// Number of jobs in the grid.
+    pub fn len(&self) -> usize {
+        self.axes.iter().map(SweepAxis::len).product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Expand the grid into jobs. Fails on an empty axis or on any value the
+    /// parameter builders reject, before anything runs.
+    pub fn points(&self) -> Result<Vec<SweepPoint>, SimError> {
+        if let Some(axis) = self.axes.iter().find(|axis| axis.is_empty()) {
+            return Err(SimError::InvalidParameter(format!("Sweep axis {} has no values", axis.name())));
+        }
+        (0..self.len())
+            .map(|n| {
+                // Mixed-radix digits of n, the last axis varying fastest
+                let mut indices = vec![0; self.axes.len()];
+                let mut rest = n;
+                for (index, axis) in indices.iter_mut().zip(&self.axes).rev() {
+                    *index = rest % axis.len();
+                    rest /= axis.len();
+                }
+
+                // The tilted plane is sized to the scan, so it is built last
+                let mut params = self.base.clone();
+                let ordered = self.axes.iter().zip(&indices);
+                for (axis, &i) in ordered.clone().filter(|(axis, _)| !matches!(axis, SweepAxis::Tilt(_))) {
+                    params = axis.apply(i, params)?;
+                }
+                for (axis, &i) in ordered.filter(|(axis, _)| matches!(axis, SweepAxis::Tilt(_))) {
+                    params = axis.apply(i, params)?;
+                }
+                let coordinates = self.axes.iter().zip(&indices).map(|(axis, &i)| axis.label(i)).collect();
+                Ok(SweepPoint { coordinates, params })
+            })
+            .collect()
+    }
+}
+
+/// Scalar summary of one image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageMetrics {
+    /// Mean of the rendered detector signal per primary electron.
+    pub mean_intensity: f64,
+    /// Backscattered electrons per primary electron, averaged over the image.
+    pub bse_coefficient: f64,
+    /// Energy they carry away as a fraction of the beam energy, per primary.
+    pub bse_energy_fraction: f64,
+    /// RMS contrast of the rendered signal: its standard deviation over its mean.
+    pub contrast: f64,
+}
+
+impl ImageMetrics {
+    /// Metrics of the raw channels behind `result`, before any display scaling.
+    pub fn of(result: &SimulationResult) -> Self {
+        let mean = |data: &[f64]| {
+            if data.is_empty() { 0.0 } else { data.iter().sum::<f64>() / data.len() as f64 }
+        };
+        let signal = detector_signal(&result.channels, result.params.signal);
+        let mean_intensity = mean(&signal);
+        let squares: Vec<f64> = signal.iter().map(|s| (s - mean_intensity).powi(2)).collect();
+        let variance = mean(&squares);
+        ImageMetrics {
+            mean_intensity,
+            bse_coefficient: mean(&result.channels.backscattered),
+            bse_energy_fraction: mean(&result.channels.bse),
+            contrast: if mean_intensity > 0.0 { variance.sqrt() / mean_intensity } else { 0.0 },
+        }
+    }
+}
+
+/// Outcome of one sweep job.
+pub struct SweepResult {
+    /// Value labels, one per axis.
+    pub coordinates: Vec<String>,
+    pub result: Result<SimulationResult, SimError>,
+}
+
+impl SweepResult {
+    /// Metrics of the image, or `None` if the job failed.
+    pub fn metrics(&self) -> Option<ImageMetrics> {
+        self.result.as_ref().ok().map(ImageMetrics::of)
+    }
+}
+
+/// Results of a sweep in grid order: a table with one row per job.
+pub struct SweepResults {
+    /// Column names of the axes.
+    pub axes: Vec<&'static str>,
+    pub rows: Vec<SweepResult>,
+}
+
+impl SweepResults {
+    pub(crate) fn new(
+        axes: Vec<&'static str>,
+        coordinates: Vec<Vec<String>>,
+        results: Vec<Result<SimulationResult, SimError>>,
+    ) -> Self {
+        let rows = coordinates
+            .into_iter()
+            .zip(results)
+            .map(|(coordinates, result)| SweepResult { coordinates, result })
+            .collect();
+        SweepResults { axes, rows }
+    }
+
+    /// Number of jobs that failed.
+    pub fn failures(&self) -> usize {
+        self.rows.iter().filter(|row| row.result.is_err()).count()
+    }
+
+    /// Save the coordinates and metrics of every job as CSV.
+    pub fn save_metrics_csv(&self, path: &str) -> Result<(), SimError> {
+        export::save_sweep_metrics_csv(path, self)?;
+        Ok(())
+    }
+}
+
+/// A sweep started by `SimulationManager::start_sweep`.
+pub struct SweepHandle {
+    pub(crate) job: JobHandle,
+    pub(crate) axes: Vec<&'static str>,
+    pub(crate) coordinates: Vec<Vec<String>>,
+}
+
+impl SweepHandle {
+    /// Pixels finished so far across the whole grid.
+    pub fn progress(&self) -> Progress {
+        self.job.progress()
+    }
+
+    /// Asks every job to stop at its next scan line, see `JobHandle::cancel`.
+    pub fn cancel(&self) {
+        self.job.cancel();
+    }
+
+    /// Whether every job has returned, so `wait` will not block.
+    pub fn is_finished(&self) -> bool {
+        self.job.is_finished()
+    }
+
+    /// Blocks until the sweep is done and returns its tagged results.
+    pub fn wait(self) -> SweepResults {
+        SweepResults::new(self.axes, self.coordinates, self.job.wait())
+    }
+}
@@ -3,43 +3,61 @@
 // Pull in the library crate (your core simulator modules)
 extern crate QuantFocus;
 
-use QuantFocus::simulation::SimulationManager;
+use QuantFocus::error::SimError;
+use QuantFocus::simulation::{JobFile, SimulationManager};
 use QuantFocus::simulation::parameters::SimulationParameters;
+use QuantFocus::simulation::results::SimulationResult;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-/// Runs the SEM simulation with fixed parameters and returns
-/// (width, height, grayscale buffer) ready for display.
-pub fn run_simulation() -> (u32, u32, Vec<u8>) {
+/// Number of electron paths recorded for the interaction-volume view.
+const DISPLAY_TRAJECTORIES: usize = 200;
+
+/// Runs the SEM simulation described by `job_file`, or with fixed parameters
+/// without one, and returns the result including a sample of electron
+/// trajectories for display.
+pub fn run_simulation(job_file: Option<&Path>) -> Result<SimulationResult, SimError> {
     // 1) Create and configure the simulation manager
     let mut sim = SimulationManager::new();
     sim.clear();
 
-    // Configure simulation for high-quality SEM imaging:
-    // - Energy: 20.0 keV for good penetration
-    // - Current: 10.0 nA for good signal-to-noise
-    // - Resolution: 512x512 pixels for detailed imaging
-    // - Working distance: 10.0 mm (typical SEM working distance)
-    let params = SimulationParameters::new(
-        20.0,    // beam energy (keV)
-        10.0,    // beam current (nA)
-        512,     // resolution (pixels)
-        10.0,    // working distance (mm)
-    )
-    .expect("Valid default parameters");
+    let params = match job_file {
+        Some(path) => {
+            let params = JobFile::load(path)?.params;
+            // The viewer always shows an interaction volume
+            let count = if params.trajectory_count > 0 { params.trajectory_count } else { DISPLAY_TRAJECTORIES };
+            params.with_trajectories(count)
+        }
+        // Configure simulation for high-quality SEM imaging:
+        // - Energy: 20.0 keV for good penetration
+        // - Current: 10.0 nA for good signal-to-noise
+        // - Resolution: 512x512 pixels for detailed imaging
+        // - Working distance: 10.0 mm (typical SEM working distance)
+        None => SimulationParameters::new(
+            20.0,    // beam energy (keV)
+            10.0,    // beam current (nA)
+            512,     // resolution (pixels)
+            10.0,    // working distance (mm)
+        )?
+        .with_trajectories(DISPLAY_TRAJECTORIES),
+    };
 
     sim.enqueue(params);
 
-    // Run the simulation and get results
-    let mut results = sim.run_all();
-    let result = results
+    // Run the simulation in the background, reporting progress until it is done
+    let job = sim.start();
+    while !job.is_finished() {
+        thread::sleep(Duration::from_millis(500));
+        let progress = job.progress();
+        match progress.eta() {
+            Some(eta) => println!("{:.1}% done, about {:.0} s left", 100.0 * progress.fraction(), eta.as_secs_f64()),
+            None => println!("{:.1}% done", 100.0 * progress.fraction()),
+        }
+    }
+    job.wait()
         .pop()
-        .expect("SimulationManager produced no results");
-
-    // Extract image data
-    let width = result.width as u32;
-    let height = result.height as u32;
-    let buffer = result.image_buffer;
-
-    (width, height, buffer)
+        .expect("SimulationManager produced no results")
 }
 
 /* Commented out UI code preserved for reference with updated parameters:
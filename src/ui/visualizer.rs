@@ -5,6 +5,8 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use std::time::Duration;
 
+use QuantFocus::backend::Trajectories;
+
 /// Displays an 8-bit grayscale buffer by converting it to RGB24 and streaming it.
 pub fn display_image(
     title: &str,
@@ -117,3 +119,80 @@ pub fn display_image(
 
     Ok(())
 }
+
+/// Draws recorded electron trajectories projected onto the X–Z plane.
+///
+/// Depth increases downwards from the sample surface (drawn in blue). Each
+/// segment is coloured from red (beam energy) to dark blue (near cutoff).
+pub fn display_interaction_volume(title: &str, trajectories: &Trajectories) -> Result<(), String> {
+    const WINDOW_SIZE: u32 = 640;
+    const MARGIN: f64 = 20.0;
+
+    let vertices = || trajectories.electrons.iter().flatten();
+    if vertices().next().is_none() {
+        return Err("No trajectories to display".into());
+    }
+
+    // Scale the extent of all trajectories into the window, keeping x and z isotropic
+    let (mut x_min, mut x_max, mut z_max, mut e_max) = (f64::INFINITY, f64::NEG_INFINITY, 0.0_f64, 0.0_f64);
+    for v in vertices() {
+        x_min = x_min.min(v.position[0]);
+        x_max = x_max.max(v.position[0]);
+        z_max = z_max.max(v.position[2]);
+        e_max = e_max.max(v.energy_kev);
+    }
+    let extent = (x_max - x_min).max(z_max).max(1.0);
+    let scale = (WINDOW_SIZE as f64 - 2.0 * MARGIN) / extent;
+    let x_center = 0.5 * (x_min + x_max);
+    let to_screen = |p: &[f64; 3]| {
+        sdl2::rect::Point::new(
+            (WINDOW_SIZE as f64 / 2.0 + (p[0] - x_center) * scale) as i32,
+            (MARGIN + p[2] * scale) as i32,
+        )
+    };
+    println!("Drawing {} trajectories over {:.0} nm", trajectories.electrons.len(), extent);
+
+    let sdl = sdl2::init().map_err(|e| e.to_string())?;
+    let video = sdl.video().map_err(|e| e.to_string())?;
+    let window = video
+        .window(title, WINDOW_SIZE, WINDOW_SIZE)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+    let mut events = sdl.event_pump().map_err(|e| e.to_string())?;
+    'running: loop {
+        for ev in events.poll_iter() {
+            match ev {
+                Event::Quit { .. }
+                | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    break 'running;
+                }
+                _ => {}
+            }
+        }
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        // Sample surface at z = 0
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(80, 120, 255));
+        canvas.draw_line((0, MARGIN as i32), (WINDOW_SIZE as i32, MARGIN as i32))?;
+
+        for electron in &trajectories.electrons {
+            for segment in electron.windows(2) {
+                let t = (segment[1].energy_kev / e_max).clamp(0.0, 1.0);
+                canvas.set_draw_color(sdl2::pixels::Color::RGB(
+                    (255.0 * t) as u8,
+                    (64.0 * t) as u8,
+                    (96.0 + 64.0 * (1.0 - t)) as u8,
+                ));
+                canvas.draw_line(to_screen(&segment[0].position), to_screen(&segment[1].position))?;
+            }
+        }
+        canvas.present();
+        std::thread::sleep(Duration::from_millis(16));
+    }
+
+    Ok(())
+}